@@ -9,17 +9,76 @@ fn solo_sigmoid(v: &f64) -> f64 {
 pub fn sigmoid(v: &Vector2D) -> Vector2D {
     let mut new_values = vec![];
     for value in &v.values {
-        new_values.push(solo_sigmoid(&value));
+        new_values.push(solo_sigmoid(value));
     }
     Vector2D::new(new_values, v.shape)
 }
 
 pub fn sigmoid_derivative(v: &Vector2D) -> Vector2D {
     let sig = sigmoid(v);
-    let sag = 1. as f64 - &sig;
+    let sag = 1. - &sig;
     sig * sag
 }
 
+pub fn tanh(v: &Vector2D) -> Vector2D {
+    v.map(f64::tanh)
+}
+
+pub fn tanh_derivative(v: &Vector2D) -> Vector2D {
+    v.map(|x| 1. - x.tanh().powi(2))
+}
+
+pub fn relu(v: &Vector2D) -> Vector2D {
+    v.map(|x| x.max(0.))
+}
+
+pub fn relu_derivative(v: &Vector2D) -> Vector2D {
+    v.map(|x| if x > 0. { 1. } else { 0. })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Activation {
+    Sigmoid,
+    Tanh,
+    Relu,
+    Identity,
+}
+
+impl Activation {
+    pub fn apply(&self, v: &Vector2D) -> Vector2D {
+        match self {
+            Activation::Sigmoid => sigmoid(v),
+            Activation::Tanh => tanh(v),
+            Activation::Relu => relu(v),
+            Activation::Identity => v.clone(),
+        }
+    }
+
+    pub fn derivative(&self, v: &Vector2D) -> Vector2D {
+        match self {
+            Activation::Sigmoid => sigmoid_derivative(v),
+            Activation::Tanh => tanh_derivative(v),
+            Activation::Relu => relu_derivative(v),
+            Activation::Identity => v.map(|_| 1.),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::Relu => "relu",
+            Activation::Identity => "identity",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Activation> {
+        [Activation::Sigmoid, Activation::Tanh, Activation::Relu, Activation::Identity]
+            .into_iter()
+            .find(|activation| activation.name() == name)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -31,4 +90,24 @@ mod tests {
         assert!(solo_sigmoid(&0.00001) > 0.5);
         assert!(solo_sigmoid(&-0.00001) < 0.5);     
     }
+
+    #[test]
+    fn test_activations() {
+        let v: Vector2D = Vector2D::new(vec![-1., 0., 2.], [1, 3]);
+        assert!(relu(&v).values == vec![0., 0., 2.]);
+        assert!(relu_derivative(&v).values == vec![0., 0., 1.]);
+        assert!(tanh(&v).values[1] == 0.);
+        assert!(tanh_derivative(&v).values[1] == 1.);
+        assert!(Activation::Identity.apply(&v).values == v.values);
+        assert!(Activation::Identity.derivative(&v).values == vec![1.; 3]);
+        assert!(Activation::Sigmoid.apply(&v).values == sigmoid(&v).values);
+    }
+
+    #[test]
+    fn test_activation_names() {
+        for activation in [Activation::Sigmoid, Activation::Tanh, Activation::Relu, Activation::Identity] {
+            assert!(Activation::from_name(activation.name()) == Some(activation));
+        }
+        assert!(Activation::from_name("softmax").is_none());
+    }
 }
\ No newline at end of file
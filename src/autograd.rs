@@ -0,0 +1,419 @@
+// This file contains tape-based reverse-mode automatic differentiation. Every operation on a
+// Var records its inputs on the tape, backward() walks the tape in reverse and applies the
+// chain rule, so models can be trained without hand-written derivatives.
+use std::{cell::RefCell, ops};
+use crate::{activation::sigmoid, vectors::models::Vector2D};
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Leaf,
+    Dot(usize, usize),
+    // adds a [1, n] row to every row
+    RowAdd(usize, usize),
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Div(usize, usize),
+    Scale(usize, f64),
+    Shift(usize),
+    Ln(usize),
+    Exp(usize),
+    Sigmoid(usize),
+    Tanh(usize),
+    Relu(usize),
+    // over all elements, giving a [1, 1] scalar
+    Sum(usize),
+    Mean(usize),
+}
+
+struct Node {
+    value: Vector2D,
+    op: Op,
+}
+
+#[derive(Default)]
+pub struct Tape {
+    nodes: RefCell<Vec<Node>>,
+    gradients: RefCell<Vec<Option<Vector2D>>>,
+}
+
+impl Tape {
+    pub fn new() -> Tape {
+        Tape::default()
+    }
+
+    // A leaf, e.g. a parameter or an input.
+    pub fn var(&self, value: Vector2D) -> Var<'_> {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.borrow().is_empty()
+    }
+
+    fn push(&self, value: Vector2D, op: Op) -> Var<'_> {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(Node { value, op });
+        Var { tape: self, index: nodes.len() - 1 }
+    }
+
+    fn value(&self, index: usize) -> Vector2D {
+        self.nodes.borrow()[index].value.clone()
+    }
+}
+
+fn accumulate(gradients: &mut [Option<Vector2D>], index: usize, gradient: Vector2D) {
+    gradients[index] = Some(match gradients[index].take() {
+        Some(existing) => existing + gradient,
+        None => gradient,
+    });
+}
+
+// A handle to a value on the tape; copying it does not copy the value.
+#[derive(Clone, Copy)]
+pub struct Var<'t> {
+    tape: &'t Tape,
+    index: usize,
+}
+
+impl<'t> Var<'t> {
+    pub fn value(&self) -> Vector2D {
+        self.tape.value(self.index)
+    }
+
+    pub fn shape(&self) -> [usize; 2] {
+        self.tape.nodes.borrow()[self.index].value.shape
+    }
+
+    // The gradient of the last backward call with respect to this value, None if the
+    // value does not influence the result.
+    pub fn grad(&self) -> Option<Vector2D> {
+        self.tape.gradients.borrow().get(self.index).cloned().flatten()
+    }
+
+    fn unary<F: Fn(&Vector2D) -> Vector2D>(&self, f: F, op: Op) -> Var<'t> {
+        let value: Vector2D = f(&self.tape.nodes.borrow()[self.index].value);
+        self.tape.push(value, op)
+    }
+
+    fn binary<F: Fn(&Vector2D, &Vector2D) -> Vector2D>(&self, other: Var<'t>, f: F, op: Op) -> Var<'t> {
+        if !std::ptr::eq(self.tape, other.tape) {
+            panic!("Can not combine variables of different tapes.");
+        }
+        let value: Vector2D = {
+            let nodes = self.tape.nodes.borrow();
+            f(&nodes[self.index].value, &nodes[other.index].value)
+        };
+        self.tape.push(value, op)
+    }
+
+    fn element_wise(&self, other: Var<'t>, op: Op) -> Var<'t> {
+        if self.shape() != other.shape() {
+            panic!("Element-wise operation on shapes {:?} and {:?}.", self.shape(), other.shape());
+        }
+        self.binary(other, |a, b| match op {
+            Op::Add(..) => a + b,
+            Op::Sub(..) => a - b,
+            Op::Mul(..) => a * b,
+            _ => a / b,
+        }, op)
+    }
+
+    pub fn dot(&self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, |a, b| a.dot(b), Op::Dot(self.index, other.index))
+    }
+
+    pub fn row_add(&self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, |a, b| a.row_add(b), Op::RowAdd(self.index, other.index))
+    }
+
+    pub fn ln(&self) -> Var<'t> {
+        self.unary(|v| v.ln(), Op::Ln(self.index))
+    }
+
+    pub fn exp(&self) -> Var<'t> {
+        self.unary(|v| v.map(f64::exp), Op::Exp(self.index))
+    }
+
+    pub fn sigmoid(&self) -> Var<'t> {
+        self.unary(sigmoid, Op::Sigmoid(self.index))
+    }
+
+    pub fn tanh(&self) -> Var<'t> {
+        self.unary(|v| v.map(f64::tanh), Op::Tanh(self.index))
+    }
+
+    pub fn relu(&self) -> Var<'t> {
+        self.unary(|v| v.map(|x| x.max(0.)), Op::Relu(self.index))
+    }
+
+    pub fn sum(&self) -> Var<'t> {
+        self.unary(|v| Vector2D::new(vec![v.values.iter().sum()], [1, 1]), Op::Sum(self.index))
+    }
+
+    pub fn mean(&self) -> Var<'t> {
+        self.unary(|v| Vector2D::new(vec![v.overall_mean()], [1, 1]), Op::Mean(self.index))
+    }
+
+    // Fills the gradients of this [1, 1] scalar with respect to every value on the tape
+    // that it depends on. Calling it again replaces the previous gradients.
+    pub fn backward(&self) {
+        if self.shape() != [1, 1] {
+            panic!("backward needs a scalar of shape [1, 1] but the value has shape {:?}.", self.shape());
+        }
+        let nodes = self.tape.nodes.borrow();
+        let mut gradients: Vec<Option<Vector2D>> = vec![None; nodes.len()];
+        gradients[self.index] = Some(Vector2D::new(vec![1.], [1, 1]));
+
+        for index in (0..=self.index).rev() {
+            let g: Vector2D = match gradients[index].take() {
+                Some(g) => g,
+                None => continue,
+            };
+            let out: &Vector2D = &nodes[index].value;
+            let value = |i: usize| -> &Vector2D { &nodes[i].value };
+            match nodes[index].op {
+                Op::Leaf => {},
+                Op::Dot(a, b) => {
+                    accumulate(&mut gradients, a, g.dot(&value(b).transpose()));
+                    accumulate(&mut gradients, b, value(a).transpose().dot(&g));
+                },
+                Op::RowAdd(a, b) => {
+                    accumulate(&mut gradients, b, g.mean(0) * g.shape[0] as f64);
+                    accumulate(&mut gradients, a, g.clone());
+                },
+                Op::Add(a, b) => {
+                    accumulate(&mut gradients, a, g.clone());
+                    accumulate(&mut gradients, b, g.clone());
+                },
+                Op::Sub(a, b) => {
+                    accumulate(&mut gradients, a, g.clone());
+                    accumulate(&mut gradients, b, -g.clone());
+                },
+                Op::Mul(a, b) => {
+                    accumulate(&mut gradients, a, &g * value(b));
+                    accumulate(&mut gradients, b, &g * value(a));
+                },
+                Op::Div(a, b) => {
+                    accumulate(&mut gradients, a, &g / value(b));
+                    accumulate(&mut gradients, b, -(&(&g * out) / value(b)));
+                },
+                Op::Scale(a, factor) => accumulate(&mut gradients, a, &g * factor),
+                Op::Shift(a) => accumulate(&mut gradients, a, g.clone()),
+                Op::Ln(a) => accumulate(&mut gradients, a, &g / value(a)),
+                Op::Exp(a) => accumulate(&mut gradients, a, &g * out),
+                Op::Sigmoid(a) => accumulate(&mut gradients, a, &g * &(out * &(1. - out))),
+                Op::Tanh(a) => accumulate(&mut gradients, a, &g * &(1. - &(out * out))),
+                Op::Relu(a) => accumulate(&mut gradients, a, &g * &value(a).map(|x| if x > 0. { 1. } else { 0. })),
+                Op::Sum(a) => {
+                    let shape: [usize; 2] = value(a).shape;
+                    accumulate(&mut gradients, a, Vector2D::new(vec![g[0]; shape[0] * shape[1]], shape));
+                },
+                Op::Mean(a) => {
+                    let shape: [usize; 2] = value(a).shape;
+                    let n: f64 = (shape[0] * shape[1]) as f64;
+                    accumulate(&mut gradients, a, Vector2D::new(vec![g[0] / n; shape[0] * shape[1]], shape));
+                },
+            }
+            gradients[index] = Some(g);
+        }
+        *self.tape.gradients.borrow_mut() = gradients;
+    }
+}
+
+impl<'t> ops::Add for Var<'t> {
+    type Output = Var<'t>;
+
+    fn add(self, other: Var<'t>) -> Var<'t> {
+        self.element_wise(other, Op::Add(self.index, other.index))
+    }
+}
+
+impl<'t> ops::Sub for Var<'t> {
+    type Output = Var<'t>;
+
+    fn sub(self, other: Var<'t>) -> Var<'t> {
+        self.element_wise(other, Op::Sub(self.index, other.index))
+    }
+}
+
+impl<'t> ops::Mul for Var<'t> {
+    type Output = Var<'t>;
+
+    fn mul(self, other: Var<'t>) -> Var<'t> {
+        self.element_wise(other, Op::Mul(self.index, other.index))
+    }
+}
+
+impl<'t> ops::Div for Var<'t> {
+    type Output = Var<'t>;
+
+    fn div(self, other: Var<'t>) -> Var<'t> {
+        self.element_wise(other, Op::Div(self.index, other.index))
+    }
+}
+
+impl<'t> ops::Mul<f64> for Var<'t> {
+    type Output = Var<'t>;
+
+    fn mul(self, factor: f64) -> Var<'t> {
+        self.unary(|v| v * factor, Op::Scale(self.index, factor))
+    }
+}
+
+impl<'t> ops::Add<f64> for Var<'t> {
+    type Output = Var<'t>;
+
+    fn add(self, shift: f64) -> Var<'t> {
+        self.unary(|v| v + shift, Op::Shift(self.index))
+    }
+}
+
+impl<'t> ops::Sub<f64> for Var<'t> {
+    type Output = Var<'t>;
+
+    fn sub(self, shift: f64) -> Var<'t> {
+        self + (-shift)
+    }
+}
+
+impl<'t> ops::Neg for Var<'t> {
+    type Output = Var<'t>;
+
+    fn neg(self) -> Var<'t> {
+        self * -1.
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use crate::neuralnetwork::NeuralNetwork;
+
+    fn random(shape: [usize; 2], seed: u64) -> Vector2D {
+        let mut rng: StdRng = StdRng::seed_from_u64(seed);
+        Vector2D::new((0..shape[0] * shape[1]).map(|_| rng.gen_range(0.1..1.)).collect(), shape)
+    }
+
+    // Compares the gradients of f with central finite differences for every input element.
+    fn check<F: for<'t> Fn(&[Var<'t>]) -> Var<'t>>(inputs: Vec<Vector2D>, f: F) {
+        let tape: Tape = Tape::new();
+        let vars: Vec<Var> = inputs.iter().map(|v| tape.var(v.clone())).collect();
+        f(&vars).backward();
+
+        let eps: f64 = 1e-6;
+        let evaluate = |inputs: &[Vector2D]| -> f64 {
+            let tape: Tape = Tape::new();
+            let vars: Vec<Var> = inputs.iter().map(|v| tape.var(v.clone())).collect();
+            f(&vars).value()[0]
+        };
+        for (input, var) in inputs.iter().enumerate() {
+            let gradient: Vector2D = vars[input].grad().unwrap();
+            assert!(gradient.shape == var.shape);
+            for idx in 0..var.values.len() {
+                let (mut plus, mut minus) = (inputs.clone(), inputs.clone());
+                plus[input].values[idx] += eps;
+                minus[input].values[idx] -= eps;
+                let numeric: f64 = (evaluate(&plus) - evaluate(&minus)) / (2. * eps);
+                assert!((numeric - gradient[idx]).abs() < 1e-6, "input {} element {}: {} vs {}", input, idx, numeric, gradient[idx]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_matrix_operations() {
+        check(vec![random([3, 2], 1), random([2, 4], 2), random([1, 4], 3)], |v| v[0].dot(v[1]).row_add(v[2]).sum());
+    }
+
+    #[test]
+    fn test_element_wise_operations() {
+        let inputs: Vec<Vector2D> = vec![random([2, 3], 1), random([2, 3], 2)];
+        check(inputs.clone(), |v| (v[0] + v[1]).mean());
+        check(inputs.clone(), |v| (v[0] - v[1] * 2.).sum());
+        check(inputs.clone(), |v| (v[0] * v[1]).mean());
+        check(inputs.clone(), |v| (v[0] / v[1]).sum());
+        check(vec![inputs[0].clone()], |v| (-(v[0] + 1.) * v[0] - 0.5).mean());
+    }
+
+    #[test]
+    fn test_functions() {
+        let inputs: Vec<Vector2D> = vec![random([2, 3], 1) - 0.5];
+        check(inputs.clone(), |v| v[0].sigmoid().mean());
+        check(inputs.clone(), |v| v[0].tanh().sum());
+        check(inputs.clone(), |v| v[0].relu().sum());
+        check(inputs.clone(), |v| v[0].exp().mean());
+        check(vec![random([2, 3], 2)], |v| v[0].ln().sum());
+    }
+
+    #[test]
+    fn test_reused_values_accumulate() {
+        check(vec![random([2, 2], 1)], |v| (v[0] * v[0] + v[0].sigmoid() * v[0]).sum());
+    }
+
+    #[test]
+    #[should_panic(expected = "backward needs a scalar")]
+    fn test_backward_needs_scalar() {
+        let tape: Tape = Tape::new();
+        tape.var(random([2, 2], 1)).backward();
+    }
+
+    fn cross_entropy<'t>(h: Var<'t>, y: Var<'t>) -> Var<'t> {
+        let ones: Var = h.tape.var(Vector2D::new(vec![1.; y.value().values.len()], y.shape()));
+        -(y * h.ln() + (ones - y) * (ones - h).ln()).mean()
+    }
+
+    #[test]
+    fn test_matches_network_backward() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        let x: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 0., 0., 1.], [4, 1]);
+        nn.forward(&x);
+        nn.backward(&y);
+
+        let tape: Tape = Tape::new();
+        let weights: Vec<Var> = nn.parameters.weights.iter().map(|w| tape.var(w.clone())).collect();
+        let biases: Vec<Var> = nn.parameters.biases.iter().map(|b| tape.var(b.clone())).collect();
+        let mut a: Var = tape.var(x);
+        for layer in 0..2 {
+            a = a.dot(weights[layer]).row_add(biases[layer]).sigmoid();
+        }
+        cross_entropy(a, tape.var(y)).backward();
+
+        for layer in 0..2 {
+            let difference: Vector2D = weights[layer].grad().unwrap() - &nn.gradients.weights[layer];
+            assert!(difference.values.iter().all(|d| d.abs() < 1e-12));
+            let difference: Vector2D = biases[layer].grad().unwrap() - &nn.gradients.biases[layer];
+            assert!(difference.values.iter().all(|d| d.abs() < 1e-12));
+        }
+    }
+
+    #[test]
+    fn test_trains_xnor_without_manual_derivatives() {
+        let x: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 0., 0., 1.], [4, 1]);
+        let mut parameters: Vec<Vector2D> = vec![
+            random([2, 4], 1) - 0.5, Vector2D::zeros([1, 4]), random([4, 1], 2) - 0.5, Vector2D::zeros([1, 1]),
+        ];
+
+        let mut losses: Vec<f64> = vec![];
+        for _ in 0..3000 {
+            let tape: Tape = Tape::new();
+            let p: Vec<Var> = parameters.iter().map(|v| tape.var(v.clone())).collect();
+            let h: Var = tape.var(x.clone()).dot(p[0]).row_add(p[1]).tanh().dot(p[2]).row_add(p[3]).sigmoid();
+            let loss: Var = cross_entropy(h, tape.var(y.clone()));
+            loss.backward();
+            losses.push(loss.value()[0]);
+            for (parameter, var) in parameters.iter_mut().zip(p.iter()) {
+                *parameter = &*parameter - var.grad().unwrap() * 0.5;
+            }
+        }
+        assert!(losses[2999] < 0.1);
+        assert!(losses[2999] < losses[0]);
+    }
+}
@@ -0,0 +1,282 @@
+// This file contains a fluent builder for the neural network. The builder methods only record
+// the configuration, build() checks it as a whole and reports the first problem as a
+// BuildError instead of panicking.
+use std::fmt;
+use rand::{Rng, SeedableRng, thread_rng, rngs::StdRng};
+use crate::{activation::Activation, clipping::GradientClipping, initializer::Initializer, layers::dropout::Dropout, loss::Loss, neuralnetwork::{HyperParameters, NeuralNetwork, initialize_biases, initialize_weights}, optimizer::{Optimizer, Sgd}, vectors::models::Vector2D};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildError {
+    MissingInput,
+    DuplicateInput { first: usize, second: usize },
+    NoLayers,
+    // layer is "input" or "dense layer <index>"
+    ZeroSize { layer: String },
+    InvalidValue { parameter: &'static str, value: f64, expected: &'static str },
+    MisplacedDropout { reason: &'static str },
+    IncompatibleLoss { loss: Loss, activation: Activation },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::MissingInput => write!(f, "The input size is missing, call input() before build()."),
+            BuildError::DuplicateInput { first, second } => write!(f, "The input size is set twice ({} and {}).", first, second),
+            BuildError::NoLayers => write!(f, "The network needs at least one dense layer."),
+            BuildError::ZeroSize { layer } => write!(f, "The {} has to have at least one unit.", layer),
+            BuildError::InvalidValue { parameter, value, expected } => write!(f, "The {} has to be {} but is {}.", parameter, expected, value),
+            BuildError::MisplacedDropout { reason } => write!(f, "Dropout {}.", reason),
+            BuildError::IncompatibleLoss { loss, activation } => {
+                write!(f, "The {} loss needs a sigmoid output layer but the output activation is {}.", loss.name(), activation.name())
+            },
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+pub struct NeuralNetworkBuilder {
+    inputs: Vec<usize>,
+    // units and activation of every dense layer, in order
+    dense: Vec<(usize, Activation)>,
+    // dense layer the dropout follows (None if there is none yet) and the rate
+    dropout: Vec<(Option<usize>, f64)>,
+    loss: Loss,
+    optimizer: Box<dyn Optimizer>,
+    learning_rate: f64,
+    batch_size: Option<usize>,
+    seed: Option<u64>,
+    weights: Initializer,
+    biases: Initializer,
+    clipping: Option<GradientClipping>,
+}
+
+impl Default for NeuralNetworkBuilder {
+    fn default() -> NeuralNetworkBuilder {
+        NeuralNetworkBuilder::new()
+    }
+}
+
+impl NeuralNetworkBuilder {
+    // The defaults are the ones of NeuralNetwork::new: cross entropy, SGD with learning rate
+    // 1, full batches, Xavier normal weights and zero biases.
+    pub fn new() -> NeuralNetworkBuilder {
+        NeuralNetworkBuilder {
+            inputs: vec![], dense: vec![], dropout: vec![],
+            loss: Loss::CrossEntropy,
+            optimizer: Box::new(Sgd::new()),
+            learning_rate: 1.,
+            batch_size: None,
+            seed: None,
+            weights: Initializer::XavierNormal,
+            biases: Initializer::Zeros,
+            clipping: None,
+        }
+    }
+
+    pub fn input(mut self, features: usize) -> NeuralNetworkBuilder {
+        self.inputs.push(features);
+        self
+    }
+
+    pub fn dense(mut self, units: usize, activation: Activation) -> NeuralNetworkBuilder {
+        self.dense.push((units, activation));
+        self
+    }
+
+    // Adds dropout behind the last dense layer added so far.
+    pub fn dropout(mut self, rate: f64) -> NeuralNetworkBuilder {
+        self.dropout.push((self.dense.len().checked_sub(1), rate));
+        self
+    }
+
+    pub fn loss(mut self, loss: Loss) -> NeuralNetworkBuilder {
+        self.loss = loss;
+        self
+    }
+
+    pub fn optimizer<O: Optimizer + 'static>(mut self, optimizer: O) -> NeuralNetworkBuilder {
+        self.optimizer = Box::new(optimizer);
+        self
+    }
+
+    pub fn learning_rate(mut self, learning_rate: f64) -> NeuralNetworkBuilder {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> NeuralNetworkBuilder {
+        self.batch_size = Some(batch_size);
+        self
+    }
+
+    // Seeds the weight initialization, the dropout masks and the shuffling of the batches,
+    // so two networks built with the same seed train identically.
+    pub fn seed(mut self, seed: u64) -> NeuralNetworkBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn initializer(mut self, weights: Initializer, biases: Initializer) -> NeuralNetworkBuilder {
+        self.weights = weights;
+        self.biases = biases;
+        self
+    }
+
+    pub fn clipping(mut self, clipping: GradientClipping) -> NeuralNetworkBuilder {
+        self.clipping = Some(clipping);
+        self
+    }
+
+    fn validate(&self) -> Result<(), BuildError> {
+        let input: usize = match self.inputs[..] {
+            [] => return Err(BuildError::MissingInput),
+            [input] => input,
+            [first, second, ..] => return Err(BuildError::DuplicateInput { first, second }),
+        };
+        if input == 0 {
+            return Err(BuildError::ZeroSize { layer: "input".to_string() });
+        }
+        if self.dense.is_empty() {
+            return Err(BuildError::NoLayers);
+        }
+        if let Some(layer) = self.dense.iter().position(|(units, _)| *units == 0) {
+            return Err(BuildError::ZeroSize { layer: format!("dense layer {}", layer) });
+        }
+        if !self.learning_rate.is_finite() || self.learning_rate <= 0. {
+            return Err(BuildError::InvalidValue { parameter: "learning rate", value: self.learning_rate, expected: "positive and finite" });
+        }
+        if self.batch_size == Some(0) {
+            return Err(BuildError::InvalidValue { parameter: "batch size", value: 0., expected: "at least 1" });
+        }
+        for (after, rate) in &self.dropout {
+            match after {
+                None => return Err(BuildError::MisplacedDropout { reason: "needs a dense layer in front of it" }),
+                Some(after) if *after == self.dense.len() - 1 => return Err(BuildError::MisplacedDropout { reason: "cannot follow the output layer" }),
+                _ => {},
+            }
+            if !(0. ..1.).contains(rate) {
+                return Err(BuildError::InvalidValue { parameter: "dropout rate", value: *rate, expected: "in [0, 1)" });
+            }
+        }
+        if let Some(clipping) = self.clipping {
+            let (parameter, value) = match clipping {
+                GradientClipping::Value(value) => ("clip value", value),
+                GradientClipping::Norm(norm) => ("clip norm", norm),
+            };
+            if !value.is_finite() || value <= 0. {
+                return Err(BuildError::InvalidValue { parameter, value, expected: "positive and finite" });
+            }
+        }
+        let output: Activation = self.dense[self.dense.len() - 1].1;
+        if self.loss == Loss::CrossEntropy && output != Activation::Sigmoid {
+            return Err(BuildError::IncompatibleLoss { loss: self.loss, activation: output });
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<NeuralNetwork, BuildError> {
+        self.validate()?;
+
+        let mut shape: Vec<usize> = vec![self.inputs[0]];
+        shape.extend(self.dense.iter().map(|(units, _)| *units));
+        let mut hyperparameters: HyperParameters = HyperParameters::new(shape, self.learning_rate);
+        hyperparameters.activations = self.dense.iter().map(|(_, activation)| *activation).collect();
+        hyperparameters.loss = self.loss;
+        hyperparameters.batch_size = self.batch_size;
+        hyperparameters.clipping = self.clipping;
+
+        let seed: u64 = self.seed.unwrap_or_else(|| thread_rng().gen());
+        hyperparameters.seed = seed;
+        let mut rng: StdRng = StdRng::seed_from_u64(seed);
+        let weights: Vec<Vector2D> = initialize_weights(&hyperparameters.shape, &self.weights, &mut rng);
+        let biases: Vec<Vector2D> = initialize_biases(&hyperparameters.shape, &self.biases, &mut rng);
+
+        let mut nn: NeuralNetwork = NeuralNetwork::from_parameters(hyperparameters, weights, biases);
+        nn.optimizer = self.optimizer;
+        for (after, rate) in self.dropout {
+            nn.add_layer(after.unwrap(), Box::new(Dropout::with_seed(rate, rng.gen())));
+        }
+        Ok(nn)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::Adam;
+
+    fn xor() -> NeuralNetworkBuilder {
+        NeuralNetworkBuilder::new()
+            .input(2)
+            .dense(3, Activation::Tanh)
+            .dense(1, Activation::Sigmoid)
+    }
+
+    #[test]
+    fn test_build() {
+        let nn: NeuralNetwork = xor().loss(Loss::CrossEntropy).optimizer(Adam::new()).learning_rate(0.1).seed(3).batch_size(2).build().unwrap();
+        assert!(nn.hyperparameters.shape == vec![2, 3, 1]);
+        assert!(nn.hyperparameters.activations == vec![Activation::Tanh, Activation::Sigmoid]);
+        assert!(nn.hyperparameters.learning_rate == 0.1);
+        assert!(nn.hyperparameters.batch_size == Some(2));
+        assert!(nn.hyperparameters.seed == 3);
+        assert!(nn.optimizer.state().name == "adam");
+        assert!(nn.parameters.weights[0].shape == [2, 3] && nn.parameters.biases[1].shape == [1, 1]);
+    }
+
+    #[test]
+    fn test_seed_makes_builds_reproducible() {
+        let a: NeuralNetwork = xor().seed(11).build().unwrap();
+        let b: NeuralNetwork = xor().seed(11).build().unwrap();
+        let c: NeuralNetwork = xor().seed(12).build().unwrap();
+        assert!(a.parameters.weights == b.parameters.weights);
+        assert!(a.parameters.weights != c.parameters.weights);
+    }
+
+    #[test]
+    fn test_dropout_follows_last_dense_layer() {
+        let nn: NeuralNetwork = NeuralNetworkBuilder::new().input(4).dense(8, Activation::Relu).dropout(0.5).dense(1, Activation::Sigmoid).build().unwrap();
+        assert!(nn.extra_layers[0].len() == 1 && nn.extra_layers[0][0].name() == "dropout");
+        assert!(nn.extra_layers[1].is_empty());
+    }
+
+    #[test]
+    fn test_configuration_errors() {
+        let error = |builder: NeuralNetworkBuilder| builder.build().err().unwrap();
+        assert!(error(NeuralNetworkBuilder::new().dense(1, Activation::Sigmoid)) == BuildError::MissingInput);
+        assert!(error(xor().input(3)) == BuildError::DuplicateInput { first: 2, second: 3 });
+        assert!(error(NeuralNetworkBuilder::new().input(2)) == BuildError::NoLayers);
+        assert!(error(NeuralNetworkBuilder::new().input(0).dense(1, Activation::Sigmoid)) == BuildError::ZeroSize { layer: "input".to_string() });
+        assert!(error(xor().learning_rate(-1.)).to_string() == "The learning rate has to be positive and finite but is -1.");
+        assert!(error(xor().batch_size(0)).to_string() == "The batch size has to be at least 1 but is 0.");
+        assert!(error(xor().clipping(GradientClipping::Norm(f64::NAN))).to_string() == "The clip norm has to be positive and finite but is NaN.");
+        assert!(error(NeuralNetworkBuilder::new().input(2).dropout(0.5).dense(1, Activation::Sigmoid)).to_string() == "Dropout needs a dense layer in front of it.");
+        assert!(error(xor().dropout(0.5)).to_string() == "Dropout cannot follow the output layer.");
+        assert!(error(NeuralNetworkBuilder::new().input(2).dense(3, Activation::Relu).dropout(1.).dense(1, Activation::Sigmoid)).to_string() == "The dropout rate has to be in [0, 1) but is 1.");
+
+        let zero_units: BuildError = error(NeuralNetworkBuilder::new().input(2).dense(3, Activation::Tanh).dense(0, Activation::Sigmoid));
+        assert!(zero_units.to_string() == "The dense layer 1 has to have at least one unit.");
+    }
+
+    #[test]
+    fn test_cross_entropy_needs_sigmoid_output() {
+        let builder: NeuralNetworkBuilder = NeuralNetworkBuilder::new().input(2).dense(1, Activation::Identity);
+        let error: BuildError = builder.build().err().unwrap();
+        assert!(error == BuildError::IncompatibleLoss { loss: Loss::CrossEntropy, activation: Activation::Identity });
+        assert!(error.to_string().contains("needs a sigmoid output layer"));
+
+        let regression = NeuralNetworkBuilder::new().input(2).dense(1, Activation::Identity).loss(Loss::MeanSquaredError).build();
+        assert!(regression.is_ok());
+    }
+
+    #[test]
+    fn test_built_network_learns_xor() {
+        let mut nn: NeuralNetwork = xor().optimizer(Adam::new()).learning_rate(0.05).seed(7).build().unwrap();
+        let input: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let output: Vector2D = Vector2D::new(vec![0., 1., 1., 0.], [4, 1]);
+        nn.training(input.clone(), output.clone(), None, 1000, false);
+        assert!(nn.evaluate(&input, &output).accuracy == Some(1.));
+    }
+}
@@ -0,0 +1,223 @@
+// This file contains the callback system of the training loop and some built-in callbacks.
+use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+use crate::{neuralnetwork::NeuralNetwork, history::{self, EpochRecord, History}};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    Continue,
+    Stop,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchLogs {
+    pub epoch: usize,
+    pub batch: usize,
+    pub size: usize,
+    pub loss: f64,
+    pub learning_rate: f64,
+    // global norm of the weight and bias gradients before clipping
+    pub gradient_norm: f64,
+}
+
+// All hooks do nothing by default. The network is passed as it is at that point of the
+// training, e.g. on_batch_end sees the gradients of the batch and the updated parameters.
+// Returning Control::Stop ends the training after the current batch or epoch.
+pub trait Callback: Send + Sync {
+    fn on_train_begin(&mut self, _nn: &NeuralNetwork) {}
+
+    fn on_epoch_begin(&mut self, _epoch: usize, _nn: &NeuralNetwork) {}
+
+    fn on_batch_begin(&mut self, _epoch: usize, _batch: usize, _nn: &NeuralNetwork) {}
+
+    fn on_batch_end(&mut self, _logs: &BatchLogs, _nn: &NeuralNetwork) -> Control {
+        Control::Continue
+    }
+
+    fn on_epoch_end(&mut self, _record: &EpochRecord, _nn: &NeuralNetwork) -> Control {
+        Control::Continue
+    }
+
+    fn on_train_end(&mut self, _history: &History, _nn: &NeuralNetwork) {}
+}
+
+// Prints the epoch summary every `every` epochs.
+pub struct ProgressPrinter {
+    pub every: usize,
+}
+
+impl ProgressPrinter {
+    pub fn new(every: usize) -> ProgressPrinter {
+        ProgressPrinter { every: every.max(1) }
+    }
+}
+
+impl Callback for ProgressPrinter {
+    fn on_epoch_end(&mut self, record: &EpochRecord, _nn: &NeuralNetwork) -> Control {
+        if record.epoch.is_multiple_of(self.every) {
+            let mut line: String = format!("Epoch {}: loss {}", record.epoch, record.loss);
+            if let Some(validation_loss) = record.validation_loss {
+                line.push_str(&format!(", validation loss {}", validation_loss));
+            }
+            for (name, value) in &record.metrics {
+                line.push_str(&format!(", {} {}", name, value));
+            }
+            line.push_str(&format!(", learning rate {} ({:.3}s)", record.learning_rate, record.seconds));
+            println!("{}", line);
+        }
+        Control::Continue
+    }
+}
+
+// Appends one CSV row per epoch to a file, so the log survives a crashed run.
+// The metric columns are taken from the first epoch.
+pub struct CsvLogger {
+    pub path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    metrics: Vec<String>,
+}
+
+impl CsvLogger {
+    pub fn new<P: AsRef<Path>>(path: P) -> CsvLogger {
+        CsvLogger { path: path.as_ref().to_path_buf(), writer: None, metrics: vec![] }
+    }
+
+    fn write_record(&mut self, record: &EpochRecord) -> std::io::Result<()> {
+        if self.writer.is_none() {
+            let mut writer: BufWriter<File> = BufWriter::new(File::create(&self.path)?);
+            self.metrics = record.metrics.iter().map(|(name, _)| name.clone()).collect();
+            writeln!(writer, "{}", history::csv_header(&self.metrics))?;
+            self.writer = Some(writer);
+        }
+
+        let writer: &mut BufWriter<File> = self.writer.as_mut().unwrap();
+        writeln!(writer, "{}", history::csv_row(record, &self.metrics))?;
+        writer.flush()
+    }
+}
+
+impl Callback for CsvLogger {
+    fn on_train_begin(&mut self, _nn: &NeuralNetwork) {
+        self.writer = None;
+    }
+
+    fn on_epoch_end(&mut self, record: &EpochRecord, _nn: &NeuralNetwork) -> Control {
+        if let Err(e) = self.write_record(record) {
+            eprintln!("Could not write training log {}: {}", self.path.display(), e);
+        }
+        Control::Continue
+    }
+
+    fn on_train_end(&mut self, _history: &History, _nn: &NeuralNetwork) {
+        self.writer = None;
+    }
+}
+
+// Stops the training as soon as a batch loss is NaN or infinite.
+pub struct TerminateOnNaN;
+
+impl Callback for TerminateOnNaN {
+    fn on_batch_end(&mut self, logs: &BatchLogs, _nn: &NeuralNetwork) -> Control {
+        if logs.loss.is_finite() {
+            return Control::Continue;
+        }
+        println!("Batch {} of epoch {}: loss {}, terminating training", logs.batch, logs.epoch, logs.loss);
+        Control::Stop
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::vectors::models::Vector2D;
+
+    fn xnor() -> (Vector2D, Vector2D) {
+        let x: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 0., 0., 1.], [4, 1]);
+        (x, y)
+    }
+
+    // Records the order of all hooks and stops after `stop_after` epochs.
+    struct Recorder {
+        events: Arc<Mutex<Vec<String>>>,
+        stop_after: usize,
+    }
+
+    impl Callback for Recorder {
+        fn on_train_begin(&mut self, _nn: &NeuralNetwork) {
+            self.events.lock().unwrap().push("train_begin".to_string());
+        }
+
+        fn on_epoch_begin(&mut self, epoch: usize, _nn: &NeuralNetwork) {
+            self.events.lock().unwrap().push(format!("epoch_begin {}", epoch));
+        }
+
+        fn on_batch_begin(&mut self, _epoch: usize, batch: usize, _nn: &NeuralNetwork) {
+            self.events.lock().unwrap().push(format!("batch_begin {}", batch));
+        }
+
+        fn on_batch_end(&mut self, logs: &BatchLogs, nn: &NeuralNetwork) -> Control {
+            assert!(logs.size == 2);
+            assert!(nn.gradients.weights[0].shape == [2, 3]);
+            self.events.lock().unwrap().push(format!("batch_end {}", logs.batch));
+            Control::Continue
+        }
+
+        fn on_epoch_end(&mut self, record: &EpochRecord, _nn: &NeuralNetwork) -> Control {
+            self.events.lock().unwrap().push(format!("epoch_end {}", record.epoch));
+            if record.epoch + 1 >= self.stop_after { Control::Stop } else { Control::Continue }
+        }
+
+        fn on_train_end(&mut self, history: &History, _nn: &NeuralNetwork) {
+            self.events.lock().unwrap().push(format!("train_end {}", history.len()));
+        }
+    }
+
+    #[test]
+    fn test_hook_order_and_stop() {
+        let events: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.batch_size = Some(2);
+        nn.callbacks.push(Box::new(Recorder { events: Arc::clone(&events), stop_after: 2 }));
+        let (x, y) = xnor();
+        let history: History = nn.training(x, y, None, 10, false);
+
+        assert!(history.len() == 2);
+        assert!(nn.callbacks.len() == 1);
+        let expected: Vec<&str> = vec![
+            "train_begin",
+            "epoch_begin 0", "batch_begin 0", "batch_end 0", "batch_begin 1", "batch_end 1", "epoch_end 0",
+            "epoch_begin 1", "batch_begin 0", "batch_end 0", "batch_begin 1", "batch_end 1", "epoch_end 1",
+            "train_end 2",
+        ];
+        assert!(*events.lock().unwrap() == expected);
+    }
+
+    #[test]
+    fn test_terminate_on_nan() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.callbacks.push(Box::new(TerminateOnNaN));
+        // a saturated prediction of exactly 0 for a positive label gives an infinite loss
+        nn.parameters.weights[1] = Vector2D::new(vec![-1e6; 3], [3, 1]);
+        let (x, y) = xnor();
+        let history: History = nn.training(x, y, None, 10, false);
+        assert!(history.len() == 1);
+    }
+
+    #[test]
+    fn test_csv_logger() {
+        let path: PathBuf = std::env::temp_dir().join("rust_network_test_log.csv");
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.callbacks.push(Box::new(CsvLogger::new(&path)));
+        let (x, y) = xnor();
+        nn.training(x.clone(), y.clone(), Some((x, y)), 3, false);
+
+        let text: String = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.len() == 4);
+        assert!(lines[0] == "epoch,loss,validation_loss,learning_rate,seconds,validation_accuracy");
+        assert!(lines[3].starts_with("2,"));
+    }
+}
@@ -0,0 +1,357 @@
+// This file holds training checkpoints: everything needed to continue an interrupted training
+// run with the exact same results as an uninterrupted one.
+use std::{fs, path::{Path, PathBuf}};
+use crate::{
+    early_stopping::EarlyStoppingState,
+    neuralnetwork::NeuralNetwork,
+    optimizer::OptimizerState,
+    serialization::{ModelError, json::Json, vector_from_json, vector_to_json},
+    vectors::models::Vector2D,
+};
+
+// Writes a checkpoint to directory every `every` epochs (epoch_<n>.json) and/or whenever the
+// monitored loss improved (best.json).
+pub struct Checkpointing {
+    pub directory: PathBuf,
+    pub every: Option<usize>,
+    pub on_best: bool,
+}
+
+impl Checkpointing {
+    pub fn new<P: AsRef<Path>>(directory: P, every: Option<usize>, on_best: bool) -> Checkpointing {
+        Checkpointing { directory: directory.as_ref().to_path_buf(), every, on_best }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    // number of completed epochs
+    pub epoch: usize,
+    pub previous_loss: Option<f64>,
+    pub best_loss: f64,
+    pub seed: u64,
+    pub weights: Vec<Vector2D>,
+    pub biases: Vec<Vector2D>,
+    pub optimizer: OptimizerState,
+    pub scheduler: Vec<f64>,
+    pub early_stopping: Option<EarlyStoppingState>,
+}
+
+fn missing(name: &str) -> ModelError {
+    ModelError::Format(format!("checkpoint has no valid {}", name))
+}
+
+// Losses and scheduler values can be non-finite (an infinite best loss before the first
+// improvement, a NaN loss after a diverged epoch). JSON has no numbers for them, so they are
+// written as the strings "NaN", "inf" and "-inf".
+fn float_to_json(value: f64) -> Json {
+    match value {
+        v if v.is_nan() => Json::from("NaN"),
+        f64::INFINITY => Json::from("inf"),
+        f64::NEG_INFINITY => Json::from("-inf"),
+        v => Json::from(v),
+    }
+}
+
+fn float_from_json(json: &Json) -> Option<f64> {
+    match json.as_str() {
+        Some("NaN") => Some(f64::NAN),
+        Some("inf") => Some(f64::INFINITY),
+        Some("-inf") => Some(f64::NEG_INFINITY),
+        Some(_) => None,
+        None => json.as_f64(),
+    }
+}
+
+fn vectors_to_json(vectors: &[Vector2D]) -> Json {
+    Json::Array(vectors.iter().map(vector_to_json).collect())
+}
+
+fn vectors_from_json(json: &Json, name: &str) -> Result<Vec<Vector2D>, ModelError> {
+    let entries: &Vec<Json> = json.as_array().ok_or_else(|| missing(name))?;
+    entries.iter().enumerate()
+        .map(|(idx, entry)| vector_from_json(entry, &format!("{}[{}]", name, idx)))
+        .collect()
+}
+
+fn early_stopping_to_json(state: &EarlyStoppingState) -> Json {
+    Json::Object(vec![
+        ("best_loss".to_string(), float_to_json(state.best_loss)),
+        ("best_epoch".to_string(), Json::from(state.best_epoch)),
+        ("wait".to_string(), Json::from(state.wait)),
+        ("best_weights".to_string(), vectors_to_json(&state.best_weights)),
+        ("best_biases".to_string(), vectors_to_json(&state.best_biases)),
+        ("best_layers".to_string(), vectors_to_json(&state.best_layers)),
+    ])
+}
+
+fn early_stopping_from_json(json: &Json) -> Result<EarlyStoppingState, ModelError> {
+    let field = |name: &str| json.get(name).ok_or_else(|| missing(&format!("early stopping {}", name)));
+    Ok(EarlyStoppingState {
+        best_loss: float_from_json(field("best_loss")?).ok_or_else(|| missing("early stopping best_loss"))?,
+        best_epoch: field("best_epoch")?.as_usize().ok_or_else(|| missing("early stopping best_epoch"))?,
+        wait: field("wait")?.as_usize().ok_or_else(|| missing("early stopping wait"))?,
+        best_weights: vectors_from_json(field("best_weights")?, "early stopping best_weights")?,
+        best_biases: vectors_from_json(field("best_biases")?, "early stopping best_biases")?,
+        best_layers: vectors_from_json(field("best_layers")?, "early stopping best_layers")?,
+    })
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("epoch".to_string(), Json::from(self.epoch)),
+            ("previous_loss".to_string(), self.previous_loss.map_or(Json::Null, float_to_json)),
+            ("best_loss".to_string(), float_to_json(self.best_loss)),
+            // u64 seeds do not fit into a JSON number without losing precision
+            ("seed".to_string(), Json::String(self.seed.to_string())),
+            ("weights".to_string(), vectors_to_json(&self.weights)),
+            ("biases".to_string(), vectors_to_json(&self.biases)),
+            ("optimizer".to_string(), Json::Object(vec![
+                ("name".to_string(), Json::from(self.optimizer.name.as_str())),
+                ("steps".to_string(), Json::from(self.optimizer.steps)),
+                ("slots".to_string(), Json::Array(self.optimizer.slots.iter().map(|slot| vectors_to_json(slot)).collect())),
+            ])),
+            ("scheduler".to_string(), Json::Array(self.scheduler.iter().map(|v| float_to_json(*v)).collect())),
+            ("early_stopping".to_string(), self.early_stopping.as_ref().map_or(Json::Null, early_stopping_to_json)),
+        ])
+    }
+
+    pub fn from_json(json: &Json) -> Result<Checkpoint, ModelError> {
+        let field = |name: &str| json.get(name).ok_or_else(|| missing(name));
+        let optimizer: &Json = field("optimizer")?;
+        let slots: Vec<Vec<Vector2D>> = optimizer.get("slots").and_then(Json::as_array).ok_or_else(|| missing("optimizer slots"))?
+            .iter().map(|slot| vectors_from_json(slot, "optimizer slot"))
+            .collect::<Result<Vec<Vec<Vector2D>>, ModelError>>()?;
+
+        Ok(Checkpoint {
+            epoch: field("epoch")?.as_usize().ok_or_else(|| missing("epoch"))?,
+            previous_loss: match field("previous_loss")? {
+                Json::Null => None,
+                value => Some(float_from_json(value).ok_or_else(|| missing("previous_loss"))?),
+            },
+            best_loss: float_from_json(field("best_loss")?).ok_or_else(|| missing("best_loss"))?,
+            seed: field("seed")?.as_str().and_then(|s| s.parse::<u64>().ok()).ok_or_else(|| missing("seed"))?,
+            weights: vectors_from_json(field("weights")?, "weights")?,
+            biases: vectors_from_json(field("biases")?, "biases")?,
+            optimizer: OptimizerState {
+                name: optimizer.get("name").and_then(Json::as_str).ok_or_else(|| missing("optimizer name"))?.to_string(),
+                steps: optimizer.get("steps").and_then(Json::as_usize).ok_or_else(|| missing("optimizer steps"))?,
+                slots,
+            },
+            scheduler: field("scheduler")?.as_array()
+                .and_then(|s| s.iter().map(float_from_json).collect::<Option<Vec<f64>>>())
+                .ok_or_else(|| missing("scheduler"))?,
+            early_stopping: match field("early_stopping")? {
+                Json::Null => None,
+                state => Some(early_stopping_from_json(state)?),
+            },
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelError> {
+        fs::write(path, self.to_json().to_string_pretty())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint, ModelError> {
+        let text: String = fs::read_to_string(path)?;
+        let json: Json = Json::parse(&text).map_err(ModelError::Format)?;
+        Checkpoint::from_json(&json)
+    }
+}
+
+impl NeuralNetwork {
+    // Only dense layers are stored, so networks with added layers (whose parameters, running
+    // statistics or dropout masks would be lost) are rejected with ModelError::UnsupportedLayer.
+    pub fn checkpoint(&self) -> Result<Checkpoint, ModelError> {
+        self.check_saveable()?;
+        Ok(Checkpoint {
+            epoch: self.state.epoch,
+            previous_loss: self.state.previous_loss,
+            best_loss: self.state.best_loss,
+            seed: self.hyperparameters.seed,
+            weights: self.parameters.weights.clone(),
+            biases: self.parameters.biases.clone(),
+            optimizer: self.optimizer.state(),
+            scheduler: self.scheduler.as_ref().map_or(vec![], |s| s.state()),
+            early_stopping: self.early_stopping.as_ref().map(|e| e.state()),
+        })
+    }
+
+    // Restores parameters, optimizer, scheduler and early stopping state, seed and epoch
+    // counter. The network has to be built with the same architecture, optimizer, scheduler
+    // and early stopping as the one that wrote the checkpoint; training then continues at
+    // checkpoint.epoch.
+    pub fn resume_from(&mut self, checkpoint: &Checkpoint) -> Result<(), ModelError> {
+        self.check_saveable()?;
+        if checkpoint.weights.len() != self.parameters.weights.len() || checkpoint.biases.len() != self.parameters.biases.len() {
+            return Err(ModelError::Format(format!(
+                "checkpoint has {} layers but the network has {}", checkpoint.weights.len(), self.parameters.weights.len()
+            )));
+        }
+        let pairs = [("weights", &checkpoint.weights, &self.parameters.weights), ("biases", &checkpoint.biases, &self.parameters.biases)];
+        for (name, stored, current) in pairs {
+            for (layer, (stored, current)) in stored.iter().zip(current.iter()).enumerate() {
+                if stored.shape != current.shape {
+                    return Err(ModelError::ShapeMismatch {
+                        parameter: format!("{}[{}]", name, layer),
+                        expected: current.shape,
+                        found: stored.shape,
+                    });
+                }
+            }
+        }
+        self.optimizer.load_state(&checkpoint.optimizer).map_err(ModelError::Format)?;
+
+        self.parameters.weights = checkpoint.weights.clone();
+        self.parameters.biases = checkpoint.biases.clone();
+        if let Some(scheduler) = &mut self.scheduler {
+            scheduler.load_state(&checkpoint.scheduler);
+        }
+        if let (Some(early_stopping), Some(state)) = (&mut self.early_stopping, &checkpoint.early_stopping) {
+            early_stopping.load_state(state);
+        }
+        self.hyperparameters.seed = checkpoint.seed;
+        self.state.epoch = checkpoint.epoch;
+        self.state.previous_loss = checkpoint.previous_loss;
+        self.state.best_loss = checkpoint.best_loss;
+        Ok(())
+    }
+
+    pub(crate) fn write_checkpoints(&self, improved: bool) -> Result<(), ModelError> {
+        let checkpointing: &Checkpointing = match &self.checkpointing {
+            Some(checkpointing) => checkpointing,
+            None => return Ok(()),
+        };
+        let mut paths: Vec<PathBuf> = vec![];
+        if checkpointing.every.is_some_and(|every| self.state.epoch.is_multiple_of(every)) {
+            paths.push(checkpointing.directory.join(format!("epoch_{}.json", self.state.epoch)));
+        }
+        if checkpointing.on_best && improved {
+            paths.push(checkpointing.directory.join("best.json"));
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let checkpoint: Checkpoint = self.checkpoint()?;
+        fs::create_dir_all(&checkpointing.directory)?;
+        for path in paths {
+            checkpoint.save(&path)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{early_stopping::EarlyStopping, layers::dropout::Dropout, neuralnetwork::TrainingError, optimizer::Adam, scheduler::ReduceOnPlateau};
+
+    fn network() -> NeuralNetwork {
+        let mut nn: NeuralNetwork = NeuralNetwork::with_optimizer(vec![2, 3, 1], 0.1, Box::new(Adam::new()));
+        nn.scheduler = Some(Box::new(ReduceOnPlateau::new(0.5, 0)));
+        // never stops, but its state has to be carried over
+        nn.early_stopping = Some(EarlyStopping::new(100, 0., false));
+        nn.hyperparameters.batch_size = Some(3);
+        nn.hyperparameters.seed = 1234;
+        nn
+    }
+
+    fn data() -> (Vector2D, Vector2D) {
+        let x: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1., 0.1, 0.9, 0.9, 0.1, 0.95, 0.9], [7, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 0., 0., 1., 0., 0., 1.], [7, 1]);
+        (x, y)
+    }
+
+    #[test]
+    fn test_resume_gives_identical_results() {
+        let (x, y) = data();
+        let mut uninterrupted: NeuralNetwork = network();
+        let initial: Checkpoint = uninterrupted.checkpoint().unwrap();
+        uninterrupted.training(x.clone(), y.clone(), None, 20, false);
+
+        let mut first: NeuralNetwork = network();
+        first.resume_from(&initial).unwrap();
+        first.training(x.clone(), y.clone(), None, 8, false);
+        let text: String = first.checkpoint().unwrap().to_json().to_string_pretty();
+
+        let mut resumed: NeuralNetwork = network();
+        resumed.hyperparameters.seed = 99;
+        resumed.resume_from(&Checkpoint::from_json(&Json::parse(&text).unwrap()).unwrap()).unwrap();
+        assert!(resumed.state.epoch == 8);
+        resumed.training(x, y, None, 12, false);
+
+        assert!(resumed.parameters.weights == uninterrupted.parameters.weights);
+        assert!(resumed.parameters.biases == uninterrupted.parameters.biases);
+        assert!(resumed.checkpoint().unwrap() == uninterrupted.checkpoint().unwrap());
+    }
+
+    #[test]
+    fn test_non_finite_losses_survive_the_round_trip() {
+        let mut checkpoint: Checkpoint = network().checkpoint().unwrap();
+        checkpoint.previous_loss = Some(f64::NAN);
+        checkpoint.best_loss = f64::INFINITY;
+        checkpoint.scheduler = vec![f64::NEG_INFINITY, 0.5];
+        let json: Json = Json::parse(&checkpoint.to_json().to_string_pretty()).unwrap();
+        let restored: Checkpoint = Checkpoint::from_json(&json).unwrap();
+        assert!(restored.previous_loss.is_some_and(f64::is_nan));
+        assert!(restored.best_loss == f64::INFINITY);
+        assert!(restored.scheduler == vec![f64::NEG_INFINITY, 0.5]);
+
+        // null is no longer read as infinity
+        let text: String = checkpoint.to_json().to_string_pretty().replacen("\"inf\"", "null", 1);
+        assert!(Checkpoint::from_json(&Json::parse(&text).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_resume_rejects_other_architecture() {
+        let nn: NeuralNetwork = network();
+        let mut other: NeuralNetwork = NeuralNetwork::with_optimizer(vec![2, 4, 1], 0.1, Box::new(Adam::new()));
+        assert!(matches!(other.resume_from(&nn.checkpoint().unwrap()), Err(ModelError::ShapeMismatch { .. })));
+
+        let mut other: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        assert!(matches!(other.resume_from(&nn.checkpoint().unwrap()), Err(ModelError::Format(_))));
+    }
+
+    #[test]
+    fn test_periodic_and_best_checkpoints() {
+        let directory: PathBuf = std::env::temp_dir().join("rust_network_test_checkpoints");
+        let _ = fs::remove_dir_all(&directory);
+        let (x, y) = data();
+        let mut nn: NeuralNetwork = network();
+        nn.checkpointing = Some(Checkpointing::new(&directory, Some(2), true));
+        nn.training(x, y, None, 5, false);
+
+        assert!(directory.join("epoch_2.json").exists());
+        assert!(directory.join("epoch_4.json").exists());
+        assert!(!directory.join("epoch_5.json").exists());
+        let best: Checkpoint = Checkpoint::load(directory.join("best.json")).unwrap();
+        assert!(best.best_loss == nn.state.best_loss);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_write_failure_ends_training() {
+        let file: PathBuf = std::env::temp_dir().join("rust_network_test_checkpoint_file");
+        fs::write(&file, "not a directory").unwrap();
+        let (x, y) = data();
+        let mut nn: NeuralNetwork = network();
+        nn.checkpointing = Some(Checkpointing::new(file.join("checkpoints"), Some(1), false));
+        let result = nn.try_training(x, y, None, 5, false);
+        fs::remove_file(&file).unwrap();
+        assert!(matches!(result, Err(TrainingError::Checkpoint(ModelError::Io(_)))));
+        assert!(nn.state.epoch == 1);
+    }
+
+    #[test]
+    fn test_networks_with_added_layers_are_rejected() {
+        let mut nn: NeuralNetwork = network();
+        let checkpoint: Checkpoint = nn.checkpoint().unwrap();
+        nn.add_layer(0, Box::new(Dropout::with_seed(0.5, 1)));
+        assert!(matches!(nn.checkpoint(), Err(ModelError::UnsupportedLayer(_))));
+        assert!(matches!(nn.resume_from(&checkpoint), Err(ModelError::UnsupportedLayer(_))));
+    }
+}
@@ -0,0 +1,99 @@
+// This file contains gradient clipping, applied to the gradients between backward and update
+// to keep saturated or exploding layers from producing huge steps.
+use crate::vectors::models::Vector2D;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientClipping {
+    // every gradient entry is clamped to [-value, value]
+    Value(f64),
+    // all gradients are scaled down together so their global L2 norm is at most this value,
+    // which keeps the direction of the step
+    Norm(f64),
+}
+
+// L2 norm of all gradients together, as if they were one long vector.
+pub fn global_norm(gradients: &[&Vector2D]) -> f64 {
+    gradients.iter().flat_map(|g| g.values.iter()).map(|v| v * v).sum::<f64>().sqrt()
+}
+
+impl GradientClipping {
+    // Clips the gradients in place and returns their global norm before clipping.
+    pub fn clip(&self, gradients: Vec<&mut Vector2D>) -> f64 {
+        let norm: f64 = global_norm(&gradients.iter().map(|g| &**g).collect::<Vec<&Vector2D>>());
+        match *self {
+            GradientClipping::Value(value) => {
+                if !value.is_finite() || value <= 0. {
+                    panic!("Clip value has to be positive but is {}.", value);
+                }
+                for gradient in gradients {
+                    *gradient = gradient.map(|g| g.clamp(-value, value));
+                }
+            },
+            GradientClipping::Norm(max_norm) => {
+                if !max_norm.is_finite() || max_norm <= 0. {
+                    panic!("Clip norm has to be positive but is {}.", max_norm);
+                }
+                if norm > max_norm {
+                    for gradient in gradients {
+                        *gradient = &*gradient * (max_norm / norm);
+                    }
+                }
+            },
+        }
+        norm
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradients() -> (Vector2D, Vector2D) {
+        (Vector2D::new(vec![3., -4.], [1, 2]), Vector2D::new(vec![0., 12.], [2, 1]))
+    }
+
+    #[test]
+    fn test_clip_by_value() {
+        let (mut a, mut b) = gradients();
+        let norm: f64 = GradientClipping::Value(2.).clip(vec![&mut a, &mut b]);
+        assert!(norm == 13.);
+        assert!(a.values == vec![2., -2.]);
+        assert!(b.values == vec![0., 2.]);
+    }
+
+    #[test]
+    fn test_clip_by_norm() {
+        let (mut a, mut b) = gradients();
+        let norm: f64 = GradientClipping::Norm(6.5).clip(vec![&mut a, &mut b]);
+        assert!(norm == 13.);
+        assert!(a.values == vec![1.5, -2.] && b.values == vec![0., 6.]);
+        assert!(global_norm(&[&a, &b]) == 6.5);
+
+        // gradients below the norm are left alone
+        let norm: f64 = GradientClipping::Norm(10.).clip(vec![&mut a, &mut b]);
+        assert!(norm == 6.5);
+        assert!(a.values == vec![1.5, -2.]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Clip norm has to be positive but is 0")]
+    fn test_clip_norm_has_to_be_positive() {
+        let (mut a, _) = gradients();
+        GradientClipping::Norm(0.).clip(vec![&mut a]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Clip value has to be positive but is NaN")]
+    fn test_clip_value_rejects_nan() {
+        let (mut a, _) = gradients();
+        GradientClipping::Value(f64::NAN).clip(vec![&mut a]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Clip norm has to be positive but is NaN")]
+    fn test_clip_norm_rejects_nan() {
+        let (mut a, _) = gradients();
+        GradientClipping::Norm(f64::NAN).clip(vec![&mut a]);
+    }
+}
@@ -1,6 +1,23 @@
 // This file contains functions to generate data that will be used as input for the neural network
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
 use crate::{gaussian::Gaussian, vectors::models::Vector2D};
 
+// Shuffles the samples and splits off the given fraction as validation data.
+// Returns ((x_train, y_train), (x_validation, y_validation)).
+pub fn train_validation_split(x: &Vector2D, y: &Vector2D, validation_fraction: f64, seed: u64) -> ((Vector2D, Vector2D), (Vector2D, Vector2D)) {
+    if x.shape[0] != y.shape[0] {
+        panic!("Can not split {} inputs and {} outputs into train and validation data.", x.shape[0], y.shape[0]);
+    }
+    let mut indices: Vec<usize> = (0..x.shape[0]).collect();
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    let validation_samples: usize = (validation_fraction * x.shape[0] as f64).round() as usize;
+    let (validation, train) = indices.split_at(validation_samples);
+    (
+        (x.select_rows(train), y.select_rows(train)),
+        (x.select_rows(validation), y.select_rows(validation)),
+    )
+}
+
 pub struct XnorDataset {
     pub x: Vector2D,
     pub y: Vector2D,
@@ -43,3 +60,25 @@ impl XnorDataset {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_train_validation_split() {
+        let x: Vector2D = Vector2D::new((0..20).map(|v| v as f64).collect(), [10, 2]);
+        let y: Vector2D = Vector2D::new((0..10).map(|v| v as f64).collect(), [10, 1]);
+        let ((x_train, y_train), (x_validation, y_validation)) = train_validation_split(&x, &y, 0.3, 1);
+        assert!(x_train.shape == [7, 2] && y_train.shape == [7, 1]);
+        assert!(x_validation.shape == [3, 2] && y_validation.shape == [3, 1]);
+        // rows stay paired
+        for row in 0..3 {
+            assert!(x_validation[(row, 0)] == 2. * y_validation[row]);
+        }
+        let mut all: Vec<f64> = [y_train.values, y_validation.values].concat();
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(all == y.values);
+    }
+}
@@ -0,0 +1,156 @@
+// This file contains early stopping, which ends the training once the monitored loss
+// (the validation loss if there is validation data) stops improving.
+use crate::vectors::models::Vector2D;
+
+pub struct EarlyStopping {
+    pub patience: usize,
+    pub min_delta: f64,
+    pub restore_best_weights: bool,
+    best_loss: f64,
+    best_epoch: usize,
+    wait: usize,
+    best_weights: Vec<Vector2D>,
+    best_biases: Vec<Vector2D>,
+    // parameters of the added layers, see NeuralNetwork::layer_parameters
+    best_layers: Vec<Vector2D>,
+    // set by load_state so the next training continues instead of starting over
+    resumed: bool,
+}
+
+// What early stopping tracks between epochs, stored in checkpoints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EarlyStoppingState {
+    pub best_loss: f64,
+    pub best_epoch: usize,
+    pub wait: usize,
+    pub best_weights: Vec<Vector2D>,
+    pub best_biases: Vec<Vector2D>,
+    pub best_layers: Vec<Vector2D>,
+}
+
+impl EarlyStopping {
+    pub fn new(patience: usize, min_delta: f64, restore_best_weights: bool) -> EarlyStopping {
+        EarlyStopping {
+            patience, min_delta, restore_best_weights,
+            best_loss: f64::INFINITY, best_epoch: 0, wait: 0,
+            best_weights: vec![], best_biases: vec![], best_layers: vec![], resumed: false,
+        }
+    }
+
+    pub fn best_loss(&self) -> f64 {
+        self.best_loss
+    }
+
+    pub fn best_epoch(&self) -> usize {
+        self.best_epoch
+    }
+
+    pub fn reset(&mut self) {
+        self.best_loss = f64::INFINITY;
+        self.best_epoch = 0;
+        self.wait = 0;
+        self.best_weights = vec![];
+        self.best_biases = vec![];
+        self.best_layers = vec![];
+    }
+
+    // Called when a training starts: a state loaded from a checkpoint is continued, otherwise
+    // the training starts over.
+    pub(crate) fn start(&mut self) {
+        if !std::mem::take(&mut self.resumed) {
+            self.reset();
+        }
+    }
+
+    pub fn state(&self) -> EarlyStoppingState {
+        EarlyStoppingState {
+            best_loss: self.best_loss,
+            best_epoch: self.best_epoch,
+            wait: self.wait,
+            best_weights: self.best_weights.clone(),
+            best_biases: self.best_biases.clone(),
+            best_layers: self.best_layers.clone(),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &EarlyStoppingState) {
+        self.best_loss = state.best_loss;
+        self.best_epoch = state.best_epoch;
+        self.wait = state.wait;
+        self.best_weights = state.best_weights.clone();
+        self.best_biases = state.best_biases.clone();
+        self.best_layers = state.best_layers.clone();
+        self.resumed = true;
+    }
+
+    // Records the loss of the given epoch and returns true if the training should stop. layers
+    // are the parameters of the layers added to the network, restored together with the rest.
+    pub fn update(&mut self, epoch: usize, loss: f64, weights: &[Vector2D], biases: &[Vector2D], layers: &[Vector2D]) -> bool {
+        if loss < self.best_loss - self.min_delta {
+            self.best_loss = loss;
+            self.best_epoch = epoch;
+            self.wait = 0;
+            if self.restore_best_weights {
+                self.best_weights = weights.to_vec();
+                self.best_biases = biases.to_vec();
+                self.best_layers = layers.to_vec();
+            }
+            return false;
+        }
+        self.wait += 1;
+        self.wait >= self.patience
+    }
+
+    // The best weights, biases and added layer parameters seen so far, if restore_best_weights
+    // is set.
+    pub fn best_parameters(&self) -> Option<(Vec<Vector2D>, Vec<Vector2D>, Vec<Vector2D>)> {
+        if !self.restore_best_weights || self.best_weights.is_empty() {
+            return None;
+        }
+        Some((self.best_weights.clone(), self.best_biases.clone(), self.best_layers.clone()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patience_and_min_delta() {
+        let mut es: EarlyStopping = EarlyStopping::new(2, 0.1, false);
+        assert!(!es.update(0, 1., &[], &[], &[]));
+        assert!(!es.update(1, 0.95, &[], &[], &[]));
+        assert!(es.update(2, 0.91, &[], &[], &[]));
+        assert!(es.best_loss() == 1.);
+        assert!(es.best_epoch() == 0);
+        assert!(es.best_parameters().is_none());
+    }
+
+    #[test]
+    fn test_keeps_best_weights() {
+        let mut es: EarlyStopping = EarlyStopping::new(1, 0., true);
+        let good: Vec<Vector2D> = vec![Vector2D::new(vec![1.], [1, 1])];
+        let bad: Vec<Vector2D> = vec![Vector2D::new(vec![2.], [1, 1])];
+        assert!(!es.update(0, 0.5, &good, &good, &good));
+        assert!(es.update(1, 0.7, &bad, &bad, &bad));
+        let (weights, biases, layers) = es.best_parameters().unwrap();
+        assert!(weights == good && biases == good && layers == good);
+    }
+
+    #[test]
+    fn test_loaded_state_survives_the_next_start() {
+        let mut es: EarlyStopping = EarlyStopping::new(3, 0., false);
+        es.update(0, 0.5, &[], &[], &[]);
+        es.update(1, 0.6, &[], &[], &[]);
+        let state: EarlyStoppingState = es.state();
+
+        let mut resumed: EarlyStopping = EarlyStopping::new(3, 0., false);
+        resumed.load_state(&state);
+        resumed.start();
+        assert!(resumed.state() == state);
+        // only the first training after loading continues
+        resumed.start();
+        assert!(resumed.best_loss() == f64::INFINITY);
+    }
+}
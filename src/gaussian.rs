@@ -1,15 +1,21 @@
 // This file holds functions to sample from an arbitrary gaussian distribution
 use rand::{Rng, rngs::ThreadRng, thread_rng};
 
-pub struct Gaussian {
+pub struct Gaussian<R: Rng = ThreadRng> {
     mean: f64,
     std: f64,
-    random_number_generator: ThreadRng
+    random_number_generator: R
 }
 
 impl Gaussian {
     pub fn new(mean: f64, std: f64) -> Gaussian {
-        Gaussian { mean: mean, std: std, random_number_generator: thread_rng() }
+        Gaussian::with_rng(mean, std, thread_rng())
+    }
+}
+
+impl<R: Rng> Gaussian<R> {
+    pub fn with_rng(mean: f64, std: f64, random_number_generator: R) -> Gaussian<R> {
+        Gaussian { mean, std, random_number_generator }
     }
 
     fn box_muller(&mut self) -> Vec<f64> {
@@ -64,12 +70,20 @@ mod tests {
         }
     }
 
-    fn mean(numbers: &Vec<f64>) -> f64 {
+    #[test]
+    fn gaussian_with_seeded_rng_is_reproducible() {
+        use rand::{SeedableRng, rngs::StdRng};
+        let mut g_1 = Gaussian::with_rng(0., 1., StdRng::seed_from_u64(42));
+        let mut g_2 = Gaussian::with_rng(0., 1., StdRng::seed_from_u64(42));
+        assert!(g_1.samples(11) == g_2.samples(11));
+    }
+
+    fn mean(numbers: &[f64]) -> f64 {
         let sum: f64 = numbers.iter().sum();
         sum / numbers.len() as f64
     }
 
-    fn standard_deviation(numbers: &Vec<f64>) -> f64 {
+    fn standard_deviation(numbers: &[f64]) -> f64 {
         let m: f64 = mean(numbers);
         let mut v: Vec<f64> = Vec::new();
         for number in numbers {
@@ -0,0 +1,324 @@
+// This file contains numerical gradient checking: every parameter is perturbed by +-epsilon,
+// the central finite difference of the loss is compared with the analytic gradient and the
+// largest relative error is reported per parameter tensor.
+use std::fmt;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use crate::{layers::Layer, neuralnetwork::NeuralNetwork, vectors::models::Vector2D};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParameterError {
+    // e.g. "weights[0]", "biases[1]", "extra_layers[0][0].batch_norm[1]" or "input_layers[0].conv2d[0]"
+    pub name: String,
+    pub relative_error: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GradCheckReport {
+    pub errors: Vec<ParameterError>,
+}
+
+impl GradCheckReport {
+    pub fn max_error(&self) -> f64 {
+        self.errors.iter().map(|e| e.relative_error).fold(0., f64::max)
+    }
+
+    pub fn passed(&self, tolerance: f64) -> bool {
+        self.max_error() <= tolerance
+    }
+}
+
+impl fmt::Display for GradCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width: usize = self.errors.iter().map(|e| e.name.len()).max().unwrap_or(0).max(9);
+        writeln!(f, "{:<width$} relative error", "parameter", width = width)?;
+        for error in &self.errors {
+            writeln!(f, "{:<width$} {:.3e}", error.name, error.relative_error, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+// |a - n| / (|a| + |n|), gradients that are both practically zero count as equal.
+fn relative_error(analytic: f64, numeric: f64) -> f64 {
+    (analytic - numeric).abs() / (analytic.abs() + numeric.abs()).max(1e-8)
+}
+
+fn max_relative_error(analytic: &Vector2D, numeric: &[f64]) -> f64 {
+    analytic.values.iter().zip(numeric.iter()).map(|(a, n)| relative_error(*a, *n)).fold(0., f64::max)
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Weights(usize),
+    Biases(usize),
+    Extra(usize, usize, usize),
+    Input(usize, usize),
+}
+
+fn parameter_mut(nn: &mut NeuralNetwork, target: Target) -> &mut Vector2D {
+    match target {
+        Target::Weights(layer) => &mut nn.parameters.weights[layer],
+        Target::Biases(layer) => &mut nn.parameters.biases[layer],
+        Target::Extra(layer, idx, parameter) => nn.extra_layers[layer][idx].parameters_and_gradients().0.remove(parameter),
+        Target::Input(idx, parameter) => nn.input_layers[idx].parameters_and_gradients().0.remove(parameter),
+    }
+}
+
+fn network_loss(nn: &mut NeuralNetwork, input: &Vector2D, target: &Vector2D) -> f64 {
+    let h: Vector2D = nn.forward(input);
+    nn.hyperparameters.loss.loss(&h, target) + nn.penalty()
+}
+
+// Checks NeuralNetwork::backward against the training loss (including regularization) of one
+// batch. The network runs in its current mode, so dropout has to be switched off with eval().
+// The many forward passes would move the running statistics of batch normalization in train
+// mode, so the buffers of all added layers are put back afterwards.
+pub fn gradcheck(nn: &mut NeuralNetwork, input: &Vector2D, target: &Vector2D, epsilon: f64) -> GradCheckReport {
+    let buffers: Vec<Vec<Vector2D>> = nn.input_layers.iter().chain(nn.extra_layers.iter().flatten()).map(|layer| layer.buffers()).collect();
+    nn.forward(input);
+    nn.backward(target);
+
+    let mut checks: Vec<(String, Target, Vector2D)> = vec![];
+    for layer in 0..nn.parameters.weights.len() {
+        checks.push((format!("weights[{}]", layer), Target::Weights(layer), nn.gradients.weights[layer].clone()));
+        checks.push((format!("biases[{}]", layer), Target::Biases(layer), nn.gradients.biases[layer].clone()));
+    }
+    for (layer, extras) in nn.extra_layers.iter().enumerate() {
+        for (idx, extra) in extras.iter().enumerate() {
+            for (parameter, gradient) in extra.gradients().into_iter().enumerate() {
+                let name: String = format!("extra_layers[{}][{}].{}[{}]", layer, idx, extra.name(), parameter);
+                checks.push((name, Target::Extra(layer, idx, parameter), gradient.clone()));
+            }
+        }
+    }
+    for (idx, layer) in nn.input_layers.iter().enumerate() {
+        for (parameter, gradient) in layer.gradients().into_iter().enumerate() {
+            let name: String = format!("input_layers[{}].{}[{}]", idx, layer.name(), parameter);
+            checks.push((name, Target::Input(idx, parameter), gradient.clone()));
+        }
+    }
+
+    let mut report: GradCheckReport = GradCheckReport::default();
+    for (name, target_parameter, analytic) in checks {
+        let mut numeric: Vec<f64> = vec![];
+        for idx in 0..analytic.values.len() {
+            let original: f64 = parameter_mut(nn, target_parameter).values[idx];
+            parameter_mut(nn, target_parameter).values[idx] = original + epsilon;
+            let plus: f64 = network_loss(nn, input, target);
+            parameter_mut(nn, target_parameter).values[idx] = original - epsilon;
+            let minus: f64 = network_loss(nn, input, target);
+            parameter_mut(nn, target_parameter).values[idx] = original;
+            numeric.push((plus - minus) / (2. * epsilon));
+        }
+        report.errors.push(ParameterError { relative_error: max_relative_error(&analytic, &numeric), name });
+    }
+    for (layer, buffers) in nn.input_layers.iter_mut().chain(nn.extra_layers.iter_mut().flatten()).zip(buffers.iter()) {
+        layer.load_buffers(buffers);
+    }
+    report
+}
+
+// The loss mean(sum(output * weights)) over the rows, with fixed pseudo-random weights.
+fn layer_loss(layer: &mut dyn Layer, input: &Vector2D, weights: &Vector2D, training: bool) -> f64 {
+    (layer.forward(input, training) * weights).values.iter().sum::<f64>() / input.shape[0] as f64
+}
+
+// Checks the input and parameter gradients of a single layer, e.g. a custom one. Following
+// the convention of Layer::backward the input gradient is compared per sample.
+pub fn gradcheck_layer(layer: &mut dyn Layer, input: &Vector2D, training: bool, epsilon: f64) -> GradCheckReport {
+    let output: Vector2D = layer.forward(input, training);
+    let mut rng: StdRng = StdRng::seed_from_u64(0);
+    let weights: Vector2D = Vector2D::new((0..output.values.len()).map(|_| rng.gen_range(-1. ..1.)).collect(), output.shape);
+    let input_gradient: Vector2D = layer.backward(&weights);
+    let gradients: Vec<Vector2D> = layer.gradients().into_iter().cloned().collect();
+
+    let mut report: GradCheckReport = GradCheckReport::default();
+    let mut numeric: Vec<f64> = vec![];
+    for idx in 0..input.values.len() {
+        let (mut plus, mut minus) = (input.clone(), input.clone());
+        plus.values[idx] += epsilon;
+        minus.values[idx] -= epsilon;
+        let difference: f64 = layer_loss(layer, &plus, &weights, training) - layer_loss(layer, &minus, &weights, training);
+        numeric.push(difference / (2. * epsilon) * input.shape[0] as f64);
+    }
+    report.errors.push(ParameterError { name: "input".to_string(), relative_error: max_relative_error(&input_gradient, &numeric) });
+
+    for (parameter, analytic) in gradients.iter().enumerate() {
+        let mut numeric: Vec<f64> = vec![];
+        for idx in 0..analytic.values.len() {
+            let original: f64 = layer.parameters()[parameter].values[idx];
+            layer.parameters_and_gradients().0[parameter].values[idx] = original + epsilon;
+            let plus: f64 = layer_loss(layer, input, &weights, training);
+            layer.parameters_and_gradients().0[parameter].values[idx] = original - epsilon;
+            let minus: f64 = layer_loss(layer, input, &weights, training);
+            layer.parameters_and_gradients().0[parameter].values[idx] = original;
+            numeric.push((plus - minus) / (2. * epsilon));
+        }
+        let name: String = format!("{}[{}]", layer.name(), parameter);
+        report.errors.push(ParameterError { name, relative_error: max_relative_error(analytic, &numeric) });
+    }
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        activation::Activation,
+        initializer::Initializer,
+        layers::{convolution::{Conv2D, ConvConfig}, dropout::Dropout, normalization::{BatchNorm, LayerNorm}, pooling::{AvgPool2D, Flatten, MaxPool2D}},
+        loss::Loss,
+        optimizer::Sgd,
+        regularizer::Regularizer,
+    };
+
+    const EPSILON: f64 = 1e-6;
+    const TOLERANCE: f64 = 1e-5;
+
+    fn data(outputs: usize) -> (Vector2D, Vector2D) {
+        let mut rng: StdRng = StdRng::seed_from_u64(1);
+        let x: Vector2D = Vector2D::new((0..15).map(|_| rng.gen_range(-1. ..1.)).collect(), [5, 3]);
+        let y: Vector2D = Vector2D::new((0..5 * outputs).map(|_| rng.gen_range(0.05..0.95)).collect(), [5, outputs]);
+        (x, y)
+    }
+
+    fn network(shape: Vec<usize>, activations: Vec<Activation>, loss: Loss) -> NeuralNetwork {
+        // the seed also fixes the parameters of the layers the tests add
+        let mut nn: NeuralNetwork = NeuralNetwork::with_seed(shape, 1., Box::new(Sgd::new()), 4);
+        nn.hyperparameters.activations = activations;
+        nn.hyperparameters.loss = loss;
+        let mut rng: StdRng = StdRng::seed_from_u64(3);
+        for layer in 0..nn.parameters.weights.len() {
+            nn.parameters.weights[layer] = Initializer::XavierUniform.initialize(nn.parameters.weights[layer].shape, &mut rng);
+            nn.parameters.biases[layer] = Initializer::Constant(0.1).initialize(nn.parameters.biases[layer].shape, &mut rng);
+        }
+        nn
+    }
+
+    fn conv(input_shape: [usize; 3], filters: usize, kernel: [usize; 2], config: ConvConfig) -> Box<Conv2D> {
+        Box::new(Conv2D::with_config(input_shape, filters, kernel, config))
+    }
+
+    fn assert_passes(report: GradCheckReport) {
+        assert!(report.passed(TOLERANCE), "gradient check failed:\n{}", report);
+    }
+
+    #[test]
+    fn test_every_activation_and_loss() {
+        let hidden: [Activation; 4] = [Activation::Sigmoid, Activation::Tanh, Activation::Relu, Activation::Identity];
+        for outputs in [1, 2] {
+            let (x, y) = data(outputs);
+            for activation in hidden {
+                // cross entropy needs probabilities
+                let mut nn: NeuralNetwork = network(vec![3, 4, outputs], vec![activation, Activation::Sigmoid], Loss::CrossEntropy);
+                assert_passes(gradcheck(&mut nn, &x, &y, EPSILON));
+
+                let mut nn: NeuralNetwork = network(vec![3, 4, outputs], vec![Activation::Tanh, activation], Loss::MeanSquaredError);
+                assert_passes(gradcheck(&mut nn, &x, &y, EPSILON));
+            }
+        }
+    }
+
+    #[test]
+    fn test_regularized_network() {
+        let (x, y) = data(1);
+        let mut nn: NeuralNetwork = network(vec![3, 4, 1], vec![Activation::Tanh, Activation::Sigmoid], Loss::CrossEntropy);
+        nn.hyperparameters.regularizers = vec![Some(Regularizer::L2(0.1)), Some(Regularizer::ElasticNet { l1: 0.01, l2: 0.05 })];
+        nn.hyperparameters.regularize_biases = true;
+        let report: GradCheckReport = gradcheck(&mut nn, &x, &y, EPSILON);
+        assert!(report.errors.len() == 4);
+        assert_passes(report);
+    }
+
+    #[test]
+    fn test_network_with_extra_layers() {
+        let (x, y) = data(2);
+        let mut nn: NeuralNetwork = network(vec![3, 4, 4, 2], vec![Activation::Tanh, Activation::Relu, Activation::Sigmoid], Loss::MeanSquaredError);
+        nn.add_layer(0, Box::new(BatchNorm::new(4)));
+        nn.add_layer(1, Box::new(LayerNorm::new(4)));
+        let report: GradCheckReport = gradcheck(&mut nn, &x, &y, EPSILON);
+        assert!(report.errors.len() == 10);
+        assert_passes(report);
+
+        nn.add_layer(1, Box::new(Dropout::with_seed(0.5, 1)));
+        nn.eval();
+        assert_passes(gradcheck(&mut nn, &x, &y, EPSILON));
+    }
+
+    #[test]
+    fn test_running_statistics_are_restored() {
+        let (x, y) = data(2);
+        let mut nn: NeuralNetwork = network(vec![3, 4, 2], vec![Activation::Tanh, Activation::Sigmoid], Loss::MeanSquaredError);
+        nn.add_layer(0, Box::new(BatchNorm::new(4)));
+        let before: Vec<Vector2D> = nn.extra_layers[0][0].buffers();
+        assert_passes(gradcheck(&mut nn, &x, &y, EPSILON));
+        assert!(nn.extra_layers[0][0].buffers() == before);
+    }
+
+    #[test]
+    fn test_convolutional_network() {
+        let mut rng: StdRng = StdRng::seed_from_u64(2);
+        let x: Vector2D = Vector2D::new((0..3 * 50).map(|_| rng.gen_range(-1. ..1.)).collect(), [3, 50]);
+        let y: Vector2D = Vector2D::new(vec![0.2, 0.9, 0.4], [3, 1]);
+        let mut nn: NeuralNetwork = network(vec![8, 3, 1], vec![Activation::Tanh, Activation::Sigmoid], Loss::CrossEntropy);
+        let config: ConvConfig = ConvConfig { stride: 1, padding: 1, dilation: 1 };
+        nn.add_input_layer(conv([2, 5, 5], 2, [3, 3], config));
+        nn.add_input_layer(Box::new(MaxPool2D::new([2, 5, 5], [2, 2])));
+        nn.add_input_layer(Box::new(Flatten::new([2, 2, 2])));
+        let report: GradCheckReport = gradcheck(&mut nn, &x, &y, EPSILON);
+        assert!(report.errors.len() == 6);
+        assert_passes(report);
+
+        let mut nn: NeuralNetwork = network(vec![4, 1], vec![Activation::Sigmoid], Loss::CrossEntropy);
+        nn.add_input_layer(Box::new(AvgPool2D::new([1, 5, 5], [2, 2])));
+        nn.add_input_layer(conv([1, 2, 2], 4, [2, 2], ConvConfig::default()));
+        let x: Vector2D = Vector2D::new(x.values[..75].to_vec(), [3, 25]);
+        assert_passes(gradcheck(&mut nn, &x, &y, EPSILON));
+    }
+
+    #[test]
+    fn test_layers() {
+        let (x, _) = data(1);
+        for training in [true, false] {
+            let mut bn: BatchNorm = BatchNorm::new(3);
+            bn.gamma = Vector2D::new(vec![1.5, -0.5, 0.8], [1, 3]);
+            assert_passes(gradcheck_layer(&mut bn, &x, training, EPSILON));
+
+            let mut ln: LayerNorm = LayerNorm::new(3);
+            ln.beta = Vector2D::new(vec![0.1, 0.2, -0.3], [1, 3]);
+            assert_passes(gradcheck_layer(&mut ln, &x, training, EPSILON));
+        }
+        // dropout samples a new mask on every training forward, so only inference is checked
+        assert_passes(gradcheck_layer(&mut Dropout::with_seed(0.5, 1), &x, false, EPSILON));
+    }
+
+    #[test]
+    fn test_detects_wrong_gradients() {
+        struct Doubling;
+
+        impl Layer for Doubling {
+            fn name(&self) -> &'static str {
+                "doubling"
+            }
+
+            fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+                input * 2.
+            }
+
+            fn predict(&self, input: &Vector2D) -> Vector2D {
+                input * 2.
+            }
+
+            fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+                gradient.clone()
+            }
+        }
+
+        let (x, _) = data(1);
+        let report: GradCheckReport = gradcheck_layer(&mut Doubling, &x, true, EPSILON);
+        assert!(!report.passed(TOLERANCE));
+        assert!((report.max_error() - 1. / 3.).abs() < 1e-6);
+        assert!(report.to_string().contains("input"));
+    }
+}
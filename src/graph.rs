@@ -0,0 +1,444 @@
+// This file contains the Graph model, a network of layers where the output of a node can feed
+// several nodes and nodes can merge several outputs by adding or concatenating them, e.g. for
+// residual blocks or models with several inputs and outputs. A node can only take nodes added
+// before it as inputs, so every graph is acyclic.
+use crate::{layers::Layer, loss::Loss, optimizer::{Optimizer, Sgd}, vectors::models::Vector2D};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Merge {
+    // element-wise sum of inputs with the same shape
+    Add,
+    // inputs placed next to each other, in the order they were given
+    Concat,
+}
+
+enum Operation {
+    // the number of features of the input
+    Input(usize),
+    Layer(Box<dyn Layer>),
+    Merge(Merge),
+}
+
+struct Node {
+    operation: Operation,
+    inputs: Vec<NodeId>,
+}
+
+pub struct Graph {
+    pub learning_rate: f64,
+    pub optimizer: Box<dyn Optimizer>,
+    nodes: Vec<Node>,
+    inputs: Vec<NodeId>,
+    // the output nodes with the loss each of them is trained on
+    outputs: Vec<(NodeId, Loss)>,
+    // the output of every node in the last forward call
+    values: Vec<Option<Vector2D>>,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
+fn accumulate(slot: &mut Option<Vector2D>, gradient: Vector2D) {
+    *slot = Some(match slot.take() {
+        Some(sum) => sum + gradient,
+        None => gradient,
+    });
+}
+
+fn merge(kind: Merge, inputs: &[&Vector2D]) -> Vector2D {
+    match kind {
+        Merge::Add => inputs[1..].iter().fold(inputs[0].clone(), |sum, input| {
+            if input.shape != sum.shape {
+                panic!("Cannot add outputs with shapes {:?} and {:?}.", sum.shape, input.shape);
+            }
+            sum + *input
+        }),
+        Merge::Concat => Vector2D::concat_columns(&inputs.iter().map(|input| (*input).clone()).collect::<Vec<Vector2D>>()),
+    }
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph::with_optimizer(1., Box::new(Sgd::new()))
+    }
+
+    pub fn with_optimizer(learning_rate: f64, optimizer: Box<dyn Optimizer>) -> Graph {
+        Graph { learning_rate, optimizer, nodes: vec![], inputs: vec![], outputs: vec![], values: vec![] }
+    }
+
+    fn check_node(&self, node: NodeId) {
+        if node.0 >= self.nodes.len() {
+            panic!("Node {} does not exist, the graph only has {} nodes.", node.0, self.nodes.len());
+        }
+    }
+
+    fn push(&mut self, operation: Operation, inputs: Vec<NodeId>) -> NodeId {
+        for input in &inputs {
+            self.check_node(*input);
+        }
+        self.nodes.push(Node { operation, inputs });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    // Inputs are passed to forward, predict and training in the order they were added.
+    pub fn input(&mut self, features: usize) -> NodeId {
+        let node: NodeId = self.push(Operation::Input(features), vec![]);
+        self.inputs.push(node);
+        node
+    }
+
+    pub fn layer(&mut self, input: NodeId, layer: Box<dyn Layer>) -> NodeId {
+        self.push(Operation::Layer(layer), vec![input])
+    }
+
+    pub fn merge(&mut self, kind: Merge, inputs: &[NodeId]) -> NodeId {
+        if inputs.len() < 2 {
+            panic!("{:?} needs at least two inputs but got {}.", kind, inputs.len());
+        }
+        self.push(Operation::Merge(kind), inputs.to_vec())
+    }
+
+    pub fn add(&mut self, inputs: &[NodeId]) -> NodeId {
+        self.merge(Merge::Add, inputs)
+    }
+
+    pub fn concat(&mut self, inputs: &[NodeId]) -> NodeId {
+        self.merge(Merge::Concat, inputs)
+    }
+
+    // Marks a node as output. Outputs are returned, and take targets, in the order they were
+    // marked.
+    pub fn output(&mut self, node: NodeId, loss: Loss) {
+        self.check_node(node);
+        self.outputs.push((node, loss));
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = &dyn Layer> {
+        self.nodes.iter().filter_map(|node| match &node.operation {
+            Operation::Layer(layer) => Some(layer.as_ref()),
+            _ => None,
+        })
+    }
+
+    // The nodes the outputs depend on, every node behind all of its inputs.
+    pub fn order(&self) -> Vec<NodeId> {
+        let mut visited: Vec<bool> = vec![false; self.nodes.len()];
+        let mut order: Vec<NodeId> = vec![];
+        // depth-first with an explicit stack, a node is placed once all its inputs are
+        let mut stack: Vec<(usize, bool)> = self.outputs.iter().rev().map(|(node, _)| (node.0, false)).collect();
+        while let Some((node, expanded)) = stack.pop() {
+            if expanded {
+                order.push(NodeId(node));
+                continue;
+            }
+            if visited[node] {
+                continue;
+            }
+            visited[node] = true;
+            stack.push((node, true));
+            stack.extend(self.nodes[node].inputs.iter().rev().filter(|input| !visited[input.0]).map(|input| (input.0, false)));
+        }
+        order
+    }
+
+    fn check_inputs(&self, inputs: &[Vector2D]) {
+        if inputs.len() != self.inputs.len() {
+            panic!("The graph has {} inputs but got {}.", self.inputs.len(), inputs.len());
+        }
+        for (node, input) in self.inputs.iter().zip(inputs.iter()) {
+            if let Operation::Input(features) = self.nodes[node.0].operation {
+                if input.shape[1] != features {
+                    panic!("Input node {} expects {} features but got {}.", node.0, features, input.shape[1]);
+                }
+            }
+        }
+    }
+
+    fn outputs(&self, values: &[Option<Vector2D>]) -> Vec<Vector2D> {
+        self.outputs.iter().map(|(node, _)| values[node.0].clone().unwrap()).collect()
+    }
+
+    pub fn forward(&mut self, inputs: &[Vector2D], training: bool) -> Vec<Vector2D> {
+        self.check_inputs(inputs);
+        let mut values: Vec<Option<Vector2D>> = vec![None; self.nodes.len()];
+        for NodeId(idx) in self.order() {
+            let node: &mut Node = &mut self.nodes[idx];
+            let parents: Vec<&Vector2D> = node.inputs.iter().map(|input| values[input.0].as_ref().unwrap()).collect();
+            let value: Vector2D = match &mut node.operation {
+                Operation::Input(_) => inputs[self.inputs.iter().position(|input| input.0 == idx).unwrap()].clone(),
+                Operation::Layer(layer) => layer.forward(parents[0], training),
+                Operation::Merge(kind) => merge(*kind, &parents),
+            };
+            values[idx] = Some(value);
+        }
+        let outputs: Vec<Vector2D> = self.outputs(&values);
+        self.values = values;
+        outputs
+    }
+
+    pub fn predict(&self, inputs: &[Vector2D]) -> Vec<Vector2D> {
+        self.check_inputs(inputs);
+        let mut values: Vec<Option<Vector2D>> = vec![None; self.nodes.len()];
+        for NodeId(idx) in self.order() {
+            let parents: Vec<&Vector2D> = self.nodes[idx].inputs.iter().map(|input| values[input.0].as_ref().unwrap()).collect();
+            let value: Vector2D = match &self.nodes[idx].operation {
+                Operation::Input(_) => inputs[self.inputs.iter().position(|input| input.0 == idx).unwrap()].clone(),
+                Operation::Layer(layer) => layer.predict(parents[0]),
+                Operation::Merge(kind) => merge(*kind, &parents),
+            };
+            values[idx] = Some(value);
+        }
+        self.outputs(&values)
+    }
+
+    // Sum of the losses of all outputs.
+    pub fn loss(&self, outputs: &[Vector2D], targets: &[Vector2D]) -> f64 {
+        self.outputs.iter().zip(outputs.iter().zip(targets.iter())).map(|((_, loss), (h, y))| loss.loss(h, y)).sum()
+    }
+
+    // Backpropagates the losses of the last forward call in reverse order. Nodes feeding
+    // several others sum the gradients they get back. Returns the gradient with respect to
+    // every input.
+    pub fn backward(&mut self, targets: &[Vector2D]) -> Vec<Vector2D> {
+        if targets.len() != self.outputs.len() {
+            panic!("The graph has {} outputs but got {} targets.", self.outputs.len(), targets.len());
+        }
+        let mut gradients: Vec<Option<Vector2D>> = vec![None; self.nodes.len()];
+        for ((node, loss), target) in self.outputs.iter().zip(targets.iter()) {
+            let h: Vector2D = self.values[node.0].clone().expect("backward has to follow forward.");
+            accumulate(&mut gradients[node.0], loss.derivative(h, target));
+        }
+
+        for NodeId(idx) in self.order().into_iter().rev() {
+            let gradient: Vector2D = match &gradients[idx] {
+                Some(gradient) => gradient.clone(),
+                None => continue,
+            };
+            let inputs: Vec<NodeId> = self.nodes[idx].inputs.clone();
+            match &mut self.nodes[idx].operation {
+                Operation::Input(_) => {},
+                Operation::Layer(layer) => accumulate(&mut gradients[inputs[0].0], layer.backward(&gradient)),
+                Operation::Merge(Merge::Add) => {
+                    for input in inputs {
+                        accumulate(&mut gradients[input.0], gradient.clone());
+                    }
+                },
+                Operation::Merge(Merge::Concat) => {
+                    let mut start: usize = 0;
+                    for input in inputs {
+                        let width: usize = self.values[input.0].as_ref().unwrap().shape[1];
+                        accumulate(&mut gradients[input.0], gradient.select_columns(start, start + width));
+                        start += width;
+                    }
+                },
+            }
+        }
+        // inputs the outputs do not depend on get a zero gradient
+        let rows: usize = targets.first().map_or(0, |target| target.shape[0]);
+        self.inputs.iter().map(|input| {
+            let features: usize = match self.nodes[input.0].operation {
+                Operation::Input(features) => features,
+                _ => unreachable!(),
+            };
+            gradients[input.0].take().unwrap_or_else(|| Vector2D::zeros([rows, features]))
+        }).collect()
+    }
+
+    // Hands the parameters and gradients of all layers to the optimizer, in the order the
+    // layers were added.
+    pub fn update(&mut self, learning_rate: f64) {
+        let mut parameters: Vec<&mut Vector2D> = vec![];
+        let mut gradients: Vec<&Vector2D> = vec![];
+        for node in self.nodes.iter_mut() {
+            if let Operation::Layer(layer) = &mut node.operation {
+                let (layer_parameters, layer_gradients) = layer.parameters_and_gradients();
+                parameters.extend(layer_parameters);
+                gradients.extend(layer_gradients);
+            }
+        }
+        self.optimizer.step(learning_rate, parameters, gradients);
+        for node in self.nodes.iter_mut() {
+            if let Operation::Layer(layer) = &mut node.operation {
+                layer.sparse_update(learning_rate);
+            }
+        }
+    }
+
+    // Full-batch training, returns the loss of every epoch.
+    pub fn training(&mut self, inputs: &[Vector2D], targets: &[Vector2D], epochs: usize) -> Vec<f64> {
+        let mut losses: Vec<f64> = vec![];
+        for _ in 0..epochs {
+            let outputs: Vec<Vector2D> = self.forward(inputs, true);
+            losses.push(self.loss(&outputs, targets));
+            self.backward(targets);
+            self.update(self.learning_rate);
+        }
+        losses
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::{activation::Activation, layers::dense::Dense};
+
+    fn dense(inputs: usize, outputs: usize, activation: Activation, rng: &mut StdRng) -> Box<dyn Layer> {
+        let mut dense: Dense = Dense::new(inputs, outputs, activation);
+        dense.initialize(rng);
+        dense.biases = dense.biases.map(|_| 0.1);
+        Box::new(dense)
+    }
+
+    fn layer_mut(graph: &mut Graph, node: usize) -> &mut Box<dyn Layer> {
+        match &mut graph.nodes[node].operation {
+            Operation::Layer(layer) => layer,
+            _ => panic!("Node {} is not a layer.", node),
+        }
+    }
+
+    // Compares the gradients of every layer and input with central differences of the loss.
+    fn check_gradients(graph: &mut Graph, inputs: &[Vector2D], targets: &[Vector2D]) {
+        let outputs: Vec<Vector2D> = graph.forward(inputs, true);
+        assert!(outputs.len() == targets.len());
+        let input_gradients: Vec<Vector2D> = graph.backward(targets);
+        let epsilon: f64 = 1e-6;
+        let close = |analytic: f64, numeric: f64| (analytic - numeric).abs() <= 1e-6 * (1. + numeric.abs());
+
+        let layers: Vec<usize> = (0..graph.nodes.len()).filter(|node| matches!(graph.nodes[*node].operation, Operation::Layer(_))).collect();
+        for node in layers {
+            let analytic: Vec<Vector2D> = layer_mut(graph, node).gradients().into_iter().cloned().collect();
+            for (parameter, analytic) in analytic.iter().enumerate() {
+                for idx in 0..analytic.len() {
+                    let original: f64 = layer_mut(graph, node).parameters()[parameter].values[idx];
+                    let mut loss_at = |value: f64| {
+                        layer_mut(graph, node).parameters_and_gradients().0[parameter].values[idx] = value;
+                        graph.loss(&graph.predict(inputs), targets)
+                    };
+                    let numeric: f64 = (loss_at(original + epsilon) - loss_at(original - epsilon)) / (2. * epsilon);
+                    layer_mut(graph, node).parameters_and_gradients().0[parameter].values[idx] = original;
+                    assert!(close(analytic.values[idx], numeric), "node {} parameter {}[{}]: {} vs {}", node, parameter, idx, analytic.values[idx], numeric);
+                }
+            }
+        }
+
+        for (input, analytic) in input_gradients.iter().enumerate() {
+            for idx in 0..analytic.len() {
+                let (mut plus, mut minus) = (inputs.to_vec(), inputs.to_vec());
+                plus[input].values[idx] += epsilon;
+                minus[input].values[idx] -= epsilon;
+                let difference: f64 = graph.loss(&graph.predict(&plus), targets) - graph.loss(&graph.predict(&minus), targets);
+                let numeric: f64 = difference / (2. * epsilon) * inputs[input].shape[0] as f64;
+                assert!(close(analytic.values[idx], numeric), "input {}[{}]: {} vs {}", input, idx, analytic.values[idx], numeric);
+            }
+        }
+    }
+
+    fn data() -> (Vector2D, Vector2D) {
+        let x: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let y: Vector2D = Vector2D::new(vec![0., 1., 1., 0.], [4, 1]);
+        (x, y)
+    }
+
+    #[test]
+    fn test_residual_block() {
+        let mut rng: StdRng = StdRng::seed_from_u64(1);
+        let mut graph: Graph = Graph::new();
+        let x: NodeId = graph.input(2);
+        let hidden: NodeId = graph.layer(x, dense(2, 2, Activation::Tanh, &mut rng));
+        let residual: NodeId = graph.add(&[x, hidden]);
+        let output: NodeId = graph.layer(residual, dense(2, 1, Activation::Sigmoid, &mut rng));
+        graph.output(output, Loss::CrossEntropy);
+
+        let (input, target) = data();
+        let layers: Vec<&dyn Layer> = graph.layers().collect();
+        let expected: Vector2D = layers[1].predict(&(&input + layers[0].predict(&input)));
+        let inputs: Vec<Vector2D> = vec![input];
+        assert!(graph.predict(&inputs) == vec![expected]);
+        // the input feeds the dense layer and the sum
+        check_gradients(&mut graph, &inputs, &[target]);
+    }
+
+    #[test]
+    fn test_multiple_inputs_and_outputs() {
+        let mut rng: StdRng = StdRng::seed_from_u64(2);
+        let mut graph: Graph = Graph::new();
+        let a: NodeId = graph.input(2);
+        let b: NodeId = graph.input(1);
+        let joined: NodeId = graph.concat(&[a, b, a]);
+        let shared: NodeId = graph.layer(joined, dense(5, 3, Activation::Tanh, &mut rng));
+        let classifier: NodeId = graph.layer(shared, dense(3, 1, Activation::Sigmoid, &mut rng));
+        let regressor: NodeId = graph.layer(shared, dense(3, 2, Activation::Identity, &mut rng));
+        graph.output(classifier, Loss::CrossEntropy);
+        graph.output(regressor, Loss::MeanSquaredError);
+
+        let (x, y) = data();
+        let b_input: Vector2D = Vector2D::new(vec![0.5, -1., 2., 0.], [4, 1]);
+        let regression: Vector2D = Vector2D::new(vec![1., 0., -1., 2., 0.5, 0.5, 0., 1.], [4, 2]);
+        let outputs: Vec<Vector2D> = graph.predict(&[x.clone(), b_input.clone()]);
+        assert!(outputs[0].shape == [4, 1] && outputs[1].shape == [4, 2]);
+        check_gradients(&mut graph, &[x, b_input], &[y, regression]);
+    }
+
+    #[test]
+    fn test_order_skips_unused_nodes() {
+        let mut graph: Graph = Graph::new();
+        let x: NodeId = graph.input(2);
+        // would panic on the two input features if it were computed
+        let unused: NodeId = graph.layer(x, Box::new(Dense::new(3, 1, Activation::Identity)));
+        let first: NodeId = graph.layer(x, Box::new(Dense::new(2, 2, Activation::Tanh)));
+        let second: NodeId = graph.layer(first, Box::new(Dense::new(2, 2, Activation::Tanh)));
+        let sum: NodeId = graph.add(&[second, first]);
+        graph.output(sum, Loss::MeanSquaredError);
+
+        let order: Vec<NodeId> = graph.order();
+        assert!(order == vec![x, first, second, sum]);
+        assert!(!order.contains(&unused));
+        let (input, _) = data();
+        assert!(graph.forward(&[input], false)[0].shape == [4, 2]);
+    }
+
+    #[test]
+    fn test_training_with_skip_connection() {
+        let mut rng: StdRng = StdRng::seed_from_u64(3);
+        let mut graph: Graph = Graph::new();
+        let x: NodeId = graph.input(2);
+        let hidden: NodeId = graph.layer(x, dense(2, 4, Activation::Tanh, &mut rng));
+        let skip: NodeId = graph.concat(&[x, hidden]);
+        let output: NodeId = graph.layer(skip, dense(6, 1, Activation::Sigmoid, &mut rng));
+        graph.output(output, Loss::CrossEntropy);
+
+        let (input, target) = data();
+        let (inputs, targets) = (vec![input], vec![target]);
+        let losses: Vec<f64> = graph.training(&inputs, &targets, 1000);
+        assert!(losses[999] < losses[0]);
+        let prediction: Vector2D = graph.predict(&inputs)[0].map(|p| if p >= 0.5 { 1. } else { 0. });
+        assert!(prediction == targets[0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot add outputs with shapes [4, 2] and [4, 3]")]
+    fn test_add_checks_shapes() {
+        let mut graph: Graph = Graph::new();
+        let x: NodeId = graph.input(2);
+        let wide: NodeId = graph.layer(x, Box::new(Dense::new(2, 3, Activation::Tanh)));
+        let sum: NodeId = graph.add(&[x, wide]);
+        graph.output(sum, Loss::MeanSquaredError);
+        graph.predict(&[data().0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Concat needs at least two inputs but got 1")]
+    fn test_merge_needs_two_inputs() {
+        let mut graph: Graph = Graph::new();
+        let x: NodeId = graph.input(2);
+        graph.concat(&[x]);
+    }
+}
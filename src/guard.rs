@@ -0,0 +1,79 @@
+// This file contains the guard against non-finite values during training. It checks the loss
+// and the gradients of every batch before the update, so a NaN or an infinity never reaches
+// the parameters.
+use std::fmt;
+use crate::{optimizer::OptimizerState, vectors::models::Vector2D};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NonFiniteAction {
+    // end the training with a NonFiniteError
+    Abort,
+    // leave out the batch, its loss does not count towards the epoch loss
+    SkipBatch,
+    // restore the parameters and optimizer state from before the last update, then leave out
+    // the batch
+    Rollback,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NonFiniteError {
+    pub epoch: usize,
+    pub batch: usize,
+    // what was not finite, e.g. "loss" or "weight gradient of layer 1"
+    pub source: String,
+    pub value: f64,
+}
+
+impl fmt::Display for NonFiniteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Non-finite {} ({}) in epoch {}, batch {}.", self.source, self.value, self.epoch, self.batch)
+    }
+}
+
+impl std::error::Error for NonFiniteError {}
+
+// All parameters and the optimizer state from before an update.
+pub(crate) struct Snapshot {
+    pub weights: Vec<Vector2D>,
+    pub biases: Vec<Vector2D>,
+    // the parameters of the added layers, see NeuralNetwork::layer_parameters
+    pub layers: Vec<Vector2D>,
+    pub optimizer: OptimizerState,
+}
+
+pub struct NonFiniteGuard {
+    pub action: NonFiniteAction,
+    // batches left out by SkipBatch or Rollback, over all trainings
+    pub skipped_batches: usize,
+    pub(crate) snapshot: Option<Snapshot>,
+}
+
+impl NonFiniteGuard {
+    pub fn new(action: NonFiniteAction) -> NonFiniteGuard {
+        NonFiniteGuard { action, skipped_batches: 0, snapshot: None }
+    }
+}
+
+// The first value that is not finite, if any.
+pub fn first_non_finite(v: &Vector2D) -> Option<f64> {
+    v.values.iter().copied().find(|value| !value.is_finite())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_non_finite() {
+        assert!(first_non_finite(&Vector2D::new(vec![1., -2.], [1, 2])).is_none());
+        assert!(first_non_finite(&Vector2D::new(vec![1., f64::INFINITY, f64::NAN], [1, 3])) == Some(f64::INFINITY));
+        assert!(first_non_finite(&Vector2D::new(vec![f64::NAN], [1, 1])).unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_error_message() {
+        let error: NonFiniteError = NonFiniteError { epoch: 3, batch: 1, source: "weight gradient of layer 0".to_string(), value: f64::NAN };
+        assert!(error.to_string() == "Non-finite weight gradient of layer 0 (NaN) in epoch 3, batch 1.");
+    }
+}
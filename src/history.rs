@@ -0,0 +1,168 @@
+// This file holds the training history returned by NeuralNetwork::training, which can be
+// exported to CSV or JSON to plot learning curves.
+use std::{fs, path::Path};
+use crate::serialization::json::Json;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpochRecord {
+    pub epoch: usize,
+    pub loss: f64,
+    pub validation_loss: Option<f64>,
+    pub learning_rate: f64,
+    // wall-clock time the epoch took, including validation
+    pub seconds: f64,
+    pub metrics: Vec<(String, f64)>,
+}
+
+impl EpochRecord {
+    pub fn metric(&self, name: &str) -> Option<f64> {
+        self.metrics.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+    pub epochs: Vec<EpochRecord>,
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map_or(String::new(), |v| v.to_string())
+}
+
+// The CSV header line with one column per metric, shared by History::to_csv and CsvLogger.
+pub(crate) fn csv_header(metrics: &[String]) -> String {
+    let mut header: Vec<String> = ["epoch", "loss", "validation_loss", "learning_rate", "seconds"].iter().map(|s| s.to_string()).collect();
+    header.extend(metrics.iter().cloned());
+    header.join(",")
+}
+
+// One CSV line, with empty cells for a missing validation loss or metric.
+pub(crate) fn csv_row(record: &EpochRecord, metrics: &[String]) -> String {
+    let mut row: Vec<String> = vec![
+        record.epoch.to_string(),
+        record.loss.to_string(),
+        optional(record.validation_loss),
+        record.learning_rate.to_string(),
+        record.seconds.to_string(),
+    ];
+    for name in metrics {
+        row.push(optional(record.metric(name)));
+    }
+    row.join(",")
+}
+
+impl History {
+    pub fn new() -> History {
+        History { epochs: vec![] }
+    }
+
+    pub fn push(&mut self, record: EpochRecord) {
+        self.epochs.push(record);
+    }
+
+    pub fn len(&self) -> usize {
+        self.epochs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.epochs.is_empty()
+    }
+
+    pub fn losses(&self) -> Vec<f64> {
+        self.epochs.iter().map(|e| e.loss).collect()
+    }
+
+    pub fn validation_losses(&self) -> Vec<Option<f64>> {
+        self.epochs.iter().map(|e| e.validation_loss).collect()
+    }
+
+    pub fn learning_rates(&self) -> Vec<f64> {
+        self.epochs.iter().map(|e| e.learning_rate).collect()
+    }
+
+    // All metric names in order of their first appearance.
+    pub fn metric_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        for record in &self.epochs {
+            for (name, _) in &record.metrics {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    pub fn to_csv(&self) -> String {
+        let names: Vec<String> = self.metric_names();
+        let mut lines: Vec<String> = vec![csv_header(&names)];
+        for record in &self.epochs {
+            lines.push(csv_row(record, &names));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::Array(self.epochs.iter().map(|record| {
+            let mut entries: Vec<(String, Json)> = vec![
+                ("epoch".to_string(), Json::from(record.epoch)),
+                ("loss".to_string(), Json::from(record.loss)),
+                ("validation_loss".to_string(), record.validation_loss.map_or(Json::Null, Json::from)),
+                ("learning_rate".to_string(), Json::from(record.learning_rate)),
+                ("seconds".to_string(), Json::from(record.seconds)),
+            ];
+            let metrics: Vec<(String, Json)> = record.metrics.iter().map(|(n, v)| (n.clone(), Json::from(*v))).collect();
+            entries.push(("metrics".to_string(), Json::Object(metrics)));
+            Json::Object(entries)
+        }).collect())
+    }
+
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        fs::write(path, self.to_csv())
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        fs::write(path, self.to_json().to_string_pretty())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> History {
+        let mut history: History = History::new();
+        history.push(EpochRecord { epoch: 0, loss: 0.75, validation_loss: None, learning_rate: 0.1, seconds: 0.5, metrics: vec![] });
+        history.push(EpochRecord {
+            epoch: 1, loss: 0.5, validation_loss: Some(0.625), learning_rate: 0.05, seconds: 0.25,
+            metrics: vec![("validation_accuracy".to_string(), 0.75)],
+        });
+        history
+    }
+
+    #[test]
+    fn test_csv() {
+        let expected: &str = "epoch,loss,validation_loss,learning_rate,seconds,validation_accuracy\n\
+                              0,0.75,,0.1,0.5,\n\
+                              1,0.5,0.625,0.05,0.25,0.75\n";
+        assert!(history().to_csv() == expected);
+    }
+
+    #[test]
+    fn test_json() {
+        let json: Json = Json::parse(&history().to_json().to_string_pretty()).unwrap();
+        let epochs: &Vec<Json> = json.as_array().unwrap();
+        assert!(epochs.len() == 2);
+        assert!(epochs[0].get("validation_loss") == Some(&Json::Null));
+        assert!(epochs[1].get("metrics").unwrap().get("validation_accuracy").unwrap().as_f64() == Some(0.75));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let history: History = history();
+        assert!(history.losses() == vec![0.75, 0.5]);
+        assert!(history.validation_losses() == vec![None, Some(0.625)]);
+        assert!(history.learning_rates() == vec![0.1, 0.05]);
+    }
+}
@@ -0,0 +1,134 @@
+// This file contains strategies to initialize the weights and biases of a layer
+use rand::Rng;
+use crate::{gaussian::Gaussian, vectors::models::Vector2D};
+
+// Weights have shape [fan_in, fan_out], biases [1, fan_out].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Initializer {
+    XavierUniform,
+    XavierNormal,
+    HeUniform,
+    HeNormal,
+    LeCunUniform,
+    LeCunNormal,
+    Orthogonal { gain: f64 },
+    Constant(f64),
+    Zeros,
+}
+
+fn uniform<R: Rng + ?Sized>(limit: f64, n: usize, rng: &mut R) -> Vec<f64> {
+    (0..n).map(|_| rng.gen_range(-limit..=limit)).collect()
+}
+
+fn normal<R: Rng + ?Sized>(std: f64, n: usize, rng: &mut R) -> Vec<f64> {
+    Gaussian::with_rng(0., std, rng).samples(n)
+}
+
+// Orthonormalizes the columns of a tall matrix with (modified) Gram-Schmidt.
+fn gram_schmidt(v: &Vector2D) -> Vector2D {
+    let [rows, columns] = v.shape;
+    let mut q: Vector2D = v.clone();
+    for column in 0..columns {
+        for previous in 0..column {
+            let mut projection: f64 = 0.;
+            for row in 0..rows {
+                projection += q[(row, column)] * q[(row, previous)];
+            }
+            for row in 0..rows {
+                q[(row, column)] -= projection * q[(row, previous)];
+            }
+        }
+        let mut norm: f64 = 0.;
+        for row in 0..rows {
+            norm += q[(row, column)] * q[(row, column)];
+        }
+        let norm: f64 = norm.sqrt().max(f64::EPSILON);
+        for row in 0..rows {
+            q[(row, column)] /= norm;
+        }
+    }
+    q
+}
+
+impl Initializer {
+    pub fn initialize<R: Rng + ?Sized>(&self, shape: [usize; 2], rng: &mut R) -> Vector2D {
+        let n: usize = shape[0] * shape[1];
+        let fan_in: f64 = shape[0] as f64;
+        let fan_out: f64 = shape[1] as f64;
+
+        let values: Vec<f64> = match self {
+            Initializer::XavierUniform => uniform((6. / (fan_in + fan_out)).sqrt(), n, rng),
+            Initializer::XavierNormal => normal((2. / (fan_in + fan_out)).sqrt(), n, rng),
+            Initializer::HeUniform => uniform((6. / fan_in).sqrt(), n, rng),
+            Initializer::HeNormal => normal((2. / fan_in).sqrt(), n, rng),
+            Initializer::LeCunUniform => uniform((3. / fan_in).sqrt(), n, rng),
+            Initializer::LeCunNormal => normal((1. / fan_in).sqrt(), n, rng),
+            Initializer::Orthogonal { gain } => {
+                let random: Vector2D = Vector2D::new(normal(1., n, rng), shape);
+                let q: Vector2D = if shape[0] >= shape[1] {
+                    gram_schmidt(&random)
+                } else {
+                    gram_schmidt(&random.transpose()).transpose()
+                };
+                (*gain * q).values
+            },
+            Initializer::Constant(value) => vec![*value; n],
+            Initializer::Zeros => vec![0.; n],
+        };
+        Vector2D::new(values, shape)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    fn variance(v: &Vector2D) -> f64 {
+        let mean: f64 = v.values.iter().sum::<f64>() / v.values.len() as f64;
+        v.values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / v.values.len() as f64
+    }
+
+    #[test]
+    fn test_variances_scale_with_fan_in() {
+        let mut rng: StdRng = StdRng::seed_from_u64(7);
+        let shape: [usize; 2] = [200, 100];
+        let cases: Vec<(Initializer, f64)> = vec![
+            (Initializer::XavierUniform, 2. / 300.),
+            (Initializer::XavierNormal, 2. / 300.),
+            (Initializer::HeUniform, 2. / 200.),
+            (Initializer::HeNormal, 2. / 200.),
+            (Initializer::LeCunUniform, 1. / 200.),
+            (Initializer::LeCunNormal, 1. / 200.),
+        ];
+        for (initializer, expected) in cases {
+            let v: Vector2D = initializer.initialize(shape, &mut rng);
+            assert!(v.shape == shape);
+            assert!((variance(&v) - expected).abs() < 0.1 * expected);
+        }
+    }
+
+    #[test]
+    fn test_orthogonal() {
+        let mut rng: StdRng = StdRng::seed_from_u64(7);
+        for shape in [[5, 3], [3, 5]] {
+            let v: Vector2D = Initializer::Orthogonal { gain: 1. }.initialize(shape, &mut rng);
+            // the smaller gram matrix has to be the identity
+            let gram: Vector2D = if shape[0] >= shape[1] { v.transpose().dot(&v) } else { v.dot(&v.transpose()) };
+            for i in 0..gram.shape[0] {
+                for j in 0..gram.shape[1] {
+                    let expected: f64 = if i == j { 1. } else { 0. };
+                    assert!((gram[(i, j)] - expected).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_constant_and_zeros() {
+        let mut rng: StdRng = StdRng::seed_from_u64(7);
+        assert!(Initializer::Constant(0.1).initialize([1, 3], &mut rng).values == vec![0.1; 3]);
+        assert!(Initializer::Zeros.initialize([2, 2], &mut rng).values == vec![0.; 4]);
+    }
+}
@@ -0,0 +1,498 @@
+// This file contains scaled dot-product attention, multi-head self-attention, sinusoidal
+// positional encodings and a pre-norm transformer encoder block. Every input row is one
+// sequence flattened in token, feature order, [batch, tokens * dimension], like the output
+// of the embedding and recurrent layers.
+use rand::RngCore;
+use crate::{activation::Activation, initializer::Initializer, layers::{Layer, dense::Dense, normalization::LayerNorm}, vectors::models::Vector2D};
+
+fn tokens(name: &str, input: &Vector2D, dimension: usize) -> usize {
+    if input.shape[1] == 0 || !input.shape[1].is_multiple_of(dimension) {
+        panic!("{} expects rows of tokens * {} features but got {} values.", name, dimension, input.shape[1]);
+    }
+    input.shape[1] / dimension
+}
+
+fn reshape(v: &Vector2D, shape: [usize; 2]) -> Vector2D {
+    Vector2D::new(v.values.clone(), shape)
+}
+
+// Lower triangular mask of ones, so every token only attends to itself and earlier tokens.
+pub fn causal_mask(length: usize) -> Vector2D {
+    let values: Vec<f64> = (0..length * length).map(|idx| if idx % length <= idx / length { 1. } else { 0. }).collect();
+    Vector2D::new(values, [length, length])
+}
+
+// Softmax over every row, only over the columns where the mask is not zero. Rows without any
+// allowed column are all zero.
+fn masked_softmax(scores: &Vector2D, mask: Option<&Vector2D>) -> Vector2D {
+    let [rows, columns] = scores.shape;
+    let mut values: Vec<f64> = vec![0.; rows * columns];
+    for row in 0..rows {
+        let allowed: Vec<usize> = (0..columns).filter(|c| mask.is_none_or(|m| m[(row, *c)] != 0.)).collect();
+        let max: f64 = allowed.iter().map(|c| scores[(row, *c)]).fold(f64::NEG_INFINITY, f64::max);
+        let sum: f64 = allowed.iter().map(|c| (scores[(row, *c)] - max).exp()).sum();
+        for c in allowed {
+            values[row * columns + c] = (scores[(row, c)] - max).exp() / sum;
+        }
+    }
+    Vector2D::new(values, scores.shape)
+}
+
+// softmax(q k^T / sqrt(d_k)) v for queries [n, d_k], keys [m, d_k] and values [m, d_v]. The
+// optional mask [n, m] blocks the entries where it is zero. Returns the output [n, d_v] and
+// the attention weights [n, m].
+pub fn scaled_dot_product_attention(q: &Vector2D, k: &Vector2D, v: &Vector2D, mask: Option<&Vector2D>) -> (Vector2D, Vector2D) {
+    if let Some(mask) = mask {
+        if mask.shape != [q.shape[0], k.shape[0]] {
+            panic!("Attention mask has shape {:?} but the scores have shape {:?}.", mask.shape, [q.shape[0], k.shape[0]]);
+        }
+    }
+    let scores: Vector2D = q.dot(&k.transpose()) / (q.shape[1] as f64).sqrt();
+    let weights: Vector2D = masked_softmax(&scores, mask);
+    (weights.dot(v), weights)
+}
+
+// Gradients with respect to q, k and v. Masked entries have zero weight and get no gradient.
+fn attention_backward(q: &Vector2D, k: &Vector2D, v: &Vector2D, weights: &Vector2D, gradient: &Vector2D) -> (Vector2D, Vector2D, Vector2D) {
+    let d_v: Vector2D = weights.transpose().dot(gradient);
+    let d_weights: Vector2D = gradient.dot(&v.transpose());
+    let row_sums: Vector2D = (&d_weights * weights).mean(1) * -(weights.shape[1] as f64);
+    let d_scores: Vector2D = (weights * &d_weights.column_add(&row_sums)) / (q.shape[1] as f64).sqrt();
+    (d_scores.dot(k), d_scores.transpose().dot(q), d_v)
+}
+
+// The encoding [length, dimension] with sin(pos / 10000^(2i / dimension)) in column 2i and
+// the cosine in column 2i + 1.
+pub fn sinusoidal_encoding(length: usize, dimension: usize) -> Vector2D {
+    let mut values: Vec<f64> = vec![];
+    for position in 0..length {
+        for column in 0..dimension {
+            let angle: f64 = position as f64 / 10000f64.powf((column - column % 2) as f64 / dimension as f64);
+            values.push(if column % 2 == 0 { angle.sin() } else { angle.cos() });
+        }
+    }
+    Vector2D::new(values, [length, dimension])
+}
+
+// Adds the sinusoidal encoding of the token positions to every sequence.
+pub struct PositionalEncoding {
+    pub dimension: usize,
+}
+
+impl PositionalEncoding {
+    pub fn new(dimension: usize) -> PositionalEncoding {
+        PositionalEncoding { dimension }
+    }
+}
+
+impl Layer for PositionalEncoding {
+    fn name(&self) -> &'static str {
+        "positional_encoding"
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        self.predict(input)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        let length: usize = tokens("PositionalEncoding", input, self.dimension);
+        input.row_add(&reshape(&sinusoidal_encoding(length, self.dimension), [1, input.shape[1]]))
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        gradient.clone()
+    }
+}
+
+struct SampleCache {
+    x: Vector2D,
+    q: Vector2D,
+    k: Vector2D,
+    v: Vector2D,
+    // attention weights of every head
+    weights: Vec<Vector2D>,
+    heads: Vector2D,
+}
+
+// Self-attention with the heads splitting the dimension evenly. The projections are stored in
+// the order query, key, value, output.
+pub struct MultiHeadAttention {
+    pub heads: usize,
+    // [tokens, tokens], e.g. causal_mask, shared by all sequences
+    pub mask: Option<Vector2D>,
+    pub weights: [Vector2D; 4],
+    pub biases: [Vector2D; 4],
+    weights_gradients: [Vector2D; 4],
+    biases_gradients: [Vector2D; 4],
+    caches: Vec<SampleCache>,
+}
+
+impl MultiHeadAttention {
+    pub fn new(dimension: usize, heads: usize) -> MultiHeadAttention {
+        if heads == 0 || !dimension.is_multiple_of(heads) {
+            panic!("Cannot split dimension {} into {} heads.", dimension, heads);
+        }
+        let zeros = |shape: [usize; 2]| [0; 4].map(|_| Vector2D::zeros(shape));
+        let mut attention: MultiHeadAttention = MultiHeadAttention {
+            heads,
+            mask: None,
+            weights: zeros([dimension, dimension]),
+            biases: zeros([1, dimension]),
+            weights_gradients: zeros([dimension, dimension]),
+            biases_gradients: zeros([1, dimension]),
+            caches: vec![],
+        };
+        attention.initialize(&mut rand::thread_rng());
+        attention
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.weights[0].shape[0]
+    }
+
+    fn head(&self, v: &Vector2D, head: usize) -> Vector2D {
+        let size: usize = self.dimension() / self.heads;
+        v.select_columns(head * size, (head + 1) * size)
+    }
+
+    fn project(&self, x: &Vector2D, index: usize) -> Vector2D {
+        x.dot(&self.weights[index]).row_add(&self.biases[index])
+    }
+
+    // Returns the output of one sequence [tokens, dimension] and what backward needs.
+    fn attend(&self, x: Vector2D) -> (Vector2D, SampleCache) {
+        let (q, k, v) = (self.project(&x, 0), self.project(&x, 1), self.project(&x, 2));
+        let mut outputs: Vec<Vector2D> = vec![];
+        let mut weights: Vec<Vector2D> = vec![];
+        for head in 0..self.heads {
+            let (output, w) = scaled_dot_product_attention(&self.head(&q, head), &self.head(&k, head), &self.head(&v, head), self.mask.as_ref());
+            outputs.push(output);
+            weights.push(w);
+        }
+        let heads: Vector2D = Vector2D::concat_columns(&outputs);
+        (self.project(&heads, 3), SampleCache { x, q, k, v, weights, heads })
+    }
+
+    fn run(&self, input: &Vector2D) -> (Vector2D, Vec<SampleCache>) {
+        let length: usize = tokens("MultiHeadAttention", input, self.dimension());
+        let mut values: Vec<f64> = vec![];
+        let mut caches: Vec<SampleCache> = vec![];
+        for row in 0..input.shape[0] {
+            let x: Vector2D = reshape(&input.get_mat_row_values(row), [length, self.dimension()]);
+            let (output, cache) = self.attend(x);
+            values.extend(output.values);
+            caches.push(cache);
+        }
+        (Vector2D::new(values, input.shape), caches)
+    }
+}
+
+impl Layer for MultiHeadAttention {
+    fn name(&self) -> &'static str {
+        "multi_head_attention"
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        let (output, caches) = self.run(input);
+        self.caches = caches;
+        output
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.run(input).0
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let samples: usize = gradient.shape[0];
+        let mut weights_gradients: [Vector2D; 4] = self.weights.clone().map(|w| Vector2D::zeros(w.shape));
+        let mut biases_gradients: [Vector2D; 4] = self.biases.clone().map(|b| Vector2D::zeros(b.shape));
+        let mut values: Vec<f64> = vec![];
+
+        for (row, cache) in self.caches.iter().enumerate() {
+            let d_output: Vector2D = reshape(&gradient.get_mat_row_values(row), cache.x.shape);
+            let d_heads: Vector2D = d_output.dot(&self.weights[3].transpose());
+            let mut d_projections: [Vec<Vector2D>; 3] = [vec![], vec![], vec![]];
+            for head in 0..self.heads {
+                let (d_q, d_k, d_v) = attention_backward(
+                    &self.head(&cache.q, head), &self.head(&cache.k, head), &self.head(&cache.v, head),
+                    &cache.weights[head], &self.head(&d_heads, head),
+                );
+                d_projections[0].push(d_q);
+                d_projections[1].push(d_k);
+                d_projections[2].push(d_v);
+            }
+
+            let mut d_x: Vector2D = Vector2D::zeros(cache.x.shape);
+            let inputs = [&cache.x, &cache.x, &cache.x, &cache.heads];
+            let d_projections: Vec<Vector2D> = d_projections.iter().map(|parts| Vector2D::concat_columns(parts)).chain([d_output]).collect();
+            for (index, d_projection) in d_projections.iter().enumerate() {
+                weights_gradients[index] = &weights_gradients[index] + inputs[index].transpose().dot(d_projection);
+                biases_gradients[index] = &biases_gradients[index] + d_projection.mean(0) * d_projection.shape[0] as f64;
+                if index < 3 {
+                    d_x = d_x + d_projection.dot(&self.weights[index].transpose());
+                }
+            }
+            values.extend(d_x.values);
+        }
+
+        self.weights_gradients = weights_gradients.map(|g| g / samples as f64);
+        self.biases_gradients = biases_gradients.map(|g| g / samples as f64);
+        Vector2D::new(values, gradient.shape)
+    }
+
+    fn initialize(&mut self, rng: &mut dyn RngCore) {
+        for weights in self.weights.iter_mut() {
+            *weights = Initializer::XavierUniform.initialize(weights.shape, rng);
+        }
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        self.weights.iter().zip(self.biases.iter()).flat_map(|(w, b)| [w, b]).collect()
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        self.weights_gradients.iter().zip(self.biases_gradients.iter()).flat_map(|(w, b)| [w, b]).collect()
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        let parameters: Vec<&mut Vector2D> = self.weights.iter_mut().zip(self.biases.iter_mut()).flat_map(|(w, b)| [w, b]).collect();
+        let gradients: Vec<&Vector2D> = self.weights_gradients.iter().zip(self.biases_gradients.iter()).flat_map(|(w, b)| [w, b]).collect();
+        (parameters, gradients)
+    }
+}
+
+// x + attention(norm(x)), followed by x + feed_forward(norm(x)), where the feed-forward
+// network is two dense layers applied to every token on its own.
+pub struct TransformerEncoder {
+    pub attention_norm: LayerNorm,
+    pub attention: MultiHeadAttention,
+    pub feed_forward_norm: LayerNorm,
+    pub feed_forward: [Dense; 2],
+    tokens: usize,
+}
+
+impl TransformerEncoder {
+    pub fn new(dimension: usize, heads: usize, hidden: usize) -> TransformerEncoder {
+        TransformerEncoder {
+            attention_norm: LayerNorm::new(dimension),
+            attention: MultiHeadAttention::new(dimension, heads),
+            feed_forward_norm: LayerNorm::new(dimension),
+            feed_forward: [Dense::new(dimension, hidden, Activation::Relu), Dense::new(hidden, dimension, Activation::Identity)],
+            tokens: 0,
+        }
+    }
+
+    fn layers(&self) -> [&dyn Layer; 5] {
+        [&self.attention_norm, &self.attention, &self.feed_forward_norm, &self.feed_forward[0], &self.feed_forward[1]]
+    }
+
+    fn layers_mut(&mut self) -> [&mut dyn Layer; 5] {
+        let [first, second] = &mut self.feed_forward;
+        [&mut self.attention_norm, &mut self.attention, &mut self.feed_forward_norm, first, second]
+    }
+}
+
+impl Layer for TransformerEncoder {
+    fn name(&self) -> &'static str {
+        "transformer_encoder"
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn forward(&mut self, input: &Vector2D, training: bool) -> Vector2D {
+        let dimension: usize = self.attention.dimension();
+        self.tokens = tokens("TransformerEncoder", input, dimension);
+        let [attention_norm, attention, feed_forward_norm, first, second] = self.layers_mut();
+        // the norms and the feed-forward network see one token per row, the attention one
+        // sequence per row
+        let x: Vector2D = reshape(input, [input.len() / dimension, dimension]);
+        let attended: Vector2D = attention.forward(&reshape(&attention_norm.forward(&x, training), input.shape), training);
+        let x: Vector2D = x + reshape(&attended, [input.len() / dimension, dimension]);
+        let hidden: Vector2D = first.forward(&feed_forward_norm.forward(&x, training), training);
+        let y: Vector2D = &x + second.forward(&hidden, training);
+        reshape(&y, input.shape)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        let dimension: usize = self.attention.dimension();
+        tokens("TransformerEncoder", input, dimension);
+        let [attention_norm, attention, feed_forward_norm, first, second] = self.layers();
+        let x: Vector2D = reshape(input, [input.len() / dimension, dimension]);
+        let attended: Vector2D = attention.predict(&reshape(&attention_norm.predict(&x), input.shape));
+        let x: Vector2D = x + reshape(&attended, [input.len() / dimension, dimension]);
+        let y: Vector2D = &x + second.predict(&first.predict(&feed_forward_norm.predict(&x)));
+        reshape(&y, input.shape)
+    }
+
+    // The token-wise sublayers average their parameter gradients over batch * tokens rows
+    // instead of the batch, so their gradients are scaled by the number of tokens going in
+    // and back out.
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let dimension: usize = self.attention.dimension();
+        let tokens: f64 = self.tokens as f64;
+        let rows: [usize; 2] = [gradient.len() / dimension, dimension];
+        let [attention_norm, attention, feed_forward_norm, first, second] = self.layers_mut();
+
+        let g: Vector2D = reshape(gradient, rows);
+        let d_hidden: Vector2D = first.backward(&second.backward(&(&g * tokens)));
+        let d_x: Vector2D = &g + feed_forward_norm.backward(&d_hidden) / tokens;
+        let d_attended: Vector2D = attention.backward(&reshape(&d_x, gradient.shape));
+        let d_input: Vector2D = &d_x + attention_norm.backward(&(reshape(&d_attended, rows) * tokens)) / tokens;
+        reshape(&d_input, gradient.shape)
+    }
+
+    fn initialize(&mut self, rng: &mut dyn RngCore) {
+        self.attention.initialize(rng);
+        for dense in self.feed_forward.iter_mut() {
+            dense.initialize(rng);
+        }
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        self.layers().into_iter().flat_map(|layer| layer.parameters()).collect()
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        self.layers().into_iter().flat_map(|layer| layer.gradients()).collect()
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        let mut parameters: Vec<&mut Vector2D> = vec![];
+        let mut gradients: Vec<&Vector2D> = vec![];
+        for layer in self.layers_mut() {
+            let (layer_parameters, layer_gradients) = layer.parameters_and_gradients();
+            parameters.extend(layer_parameters);
+            gradients.extend(layer_gradients);
+        }
+        (parameters, gradients)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::gradcheck::gradcheck_layer;
+
+    // two sequences of three tokens with four features
+    fn sequences() -> Vector2D {
+        Vector2D::new((0..24).map(|i| ((i * 7 % 11) as f64 - 5.) / 5.).collect(), [2, 12])
+    }
+
+    fn matrix(shape: [usize; 2], offset: usize) -> Vector2D {
+        Vector2D::new((0..shape[0] * shape[1]).map(|i| (((i + offset) * 5 % 7) as f64 - 3.) / 3.).collect(), shape)
+    }
+
+    #[test]
+    fn test_attention_weights() {
+        let (q, k, v) = (matrix([3, 2], 0), matrix([3, 2], 1), matrix([3, 2], 2));
+        let (output, weights) = scaled_dot_product_attention(&q, &k, &v, None);
+        assert!(output.shape == [3, 2]);
+        for row in 0..3 {
+            assert!(((0..3).map(|c| weights[(row, c)]).sum::<f64>() - 1.).abs() < 1e-12);
+        }
+
+        // the first token can only attend to itself
+        let (output, weights) = scaled_dot_product_attention(&q, &k, &v, Some(&causal_mask(3)));
+        assert!(weights[(0, 0)] == 1. && weights[(0, 1)] == 0. && weights[(1, 2)] == 0.);
+        assert!(output.get_mat_row_values(0) == v.get_mat_row_values(0));
+    }
+
+    #[test]
+    fn test_attention_gradients() {
+        let (q, k, v) = (matrix([3, 2], 0), matrix([4, 2], 1), matrix([4, 3], 2));
+        let mask: Vector2D = Vector2D::new(vec![1., 1., 0., 1., 0., 1., 1., 1., 1., 0., 0., 1.], [3, 4]);
+        let gradient: Vector2D = matrix([3, 3], 3);
+        let loss = |q: &Vector2D, k: &Vector2D, v: &Vector2D| -> f64 {
+            let output: Vector2D = scaled_dot_product_attention(q, k, v, Some(&mask)).0;
+            (&output * &gradient).values.iter().sum()
+        };
+        let weights: Vector2D = scaled_dot_product_attention(&q, &k, &v, Some(&mask)).1;
+        let analytic = attention_backward(&q, &k, &v, &weights, &gradient);
+        for (input, analytic) in [(0, &analytic.0), (1, &analytic.1), (2, &analytic.2)] {
+            for idx in 0..analytic.len() {
+                let mut plus: [Vector2D; 3] = [q.clone(), k.clone(), v.clone()];
+                let mut minus: [Vector2D; 3] = plus.clone();
+                plus[input].values[idx] += 1e-6;
+                minus[input].values[idx] -= 1e-6;
+                let numeric: f64 = (loss(&plus[0], &plus[1], &plus[2]) - loss(&minus[0], &minus[1], &minus[2])) / 2e-6;
+                assert!((numeric - analytic.values[idx]).abs() < 1e-7);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sinusoidal_encoding() {
+        let encoding: Vector2D = sinusoidal_encoding(3, 4);
+        assert!(encoding.get_mat_row_values(0).values == vec![0., 1., 0., 1.]);
+        assert!(encoding[(2, 0)] == 2f64.sin());
+        assert!(encoding[(2, 3)] == (2. / 100.0f64).cos());
+
+        let output: Vector2D = PositionalEncoding::new(4).predict(&Vector2D::zeros([2, 12]));
+        assert!(output.get_mat_row_values(1).values == encoding.values);
+    }
+
+    // The key biases shift all scores of a query by the same amount, which the softmax
+    // ignores, so their gradient is zero and only its size is checked.
+    fn check(layer: &mut dyn Layer, key_biases: usize) {
+        let report = gradcheck_layer(layer, &sequences(), true, 1e-6);
+        assert!(layer.gradients()[key_biases].values.iter().all(|g| g.abs() < 1e-12));
+        let name: String = format!("{}[{}]", layer.name(), key_biases);
+        for error in report.errors.iter().filter(|error| error.name != name) {
+            assert!(error.relative_error < 1e-6, "{}", report);
+        }
+    }
+
+    #[test]
+    fn test_multi_head_attention_gradients() {
+        for mask in [None, Some(causal_mask(3))] {
+            let mut attention: MultiHeadAttention = MultiHeadAttention::new(4, 2);
+            attention.initialize(&mut StdRng::seed_from_u64(1));
+            attention.biases[0] = Vector2D::new(vec![0.1, -0.2, 0.3, 0.], [1, 4]);
+            attention.mask = mask;
+            check(&mut attention, 3);
+        }
+    }
+
+    #[test]
+    fn test_causal_attention_ignores_later_tokens() {
+        let mut attention: MultiHeadAttention = MultiHeadAttention::new(4, 2);
+        attention.mask = Some(causal_mask(3));
+        let mut changed: Vector2D = sequences();
+        changed.values[8] += 1.;
+        let (before, after) = (attention.predict(&sequences()), attention.predict(&changed));
+        assert!(before.select_columns(0, 8) == after.select_columns(0, 8));
+        assert!(before.select_columns(8, 12) != after.select_columns(8, 12));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot split dimension 6 into 4 heads")]
+    fn test_heads_have_to_split_dimension() {
+        MultiHeadAttention::new(6, 4);
+    }
+
+    #[test]
+    fn test_transformer_encoder() {
+        let mut encoder: TransformerEncoder = TransformerEncoder::new(4, 2, 8);
+        encoder.initialize(&mut StdRng::seed_from_u64(2));
+        let output: Vector2D = encoder.forward(&sequences(), true);
+        assert!(output.shape == [2, 12]);
+        assert!(output == encoder.predict(&sequences()));
+        // 4 * 2 norm parameters, 4 * (16 + 4) attention and 32 + 8 + 32 + 4 feed-forward
+        assert!(encoder.parameters().iter().map(|p| p.len()).sum::<usize>() == 8 + 80 + 8 + 76);
+
+        // behind gamma and beta of the first norm
+        check(&mut encoder, 5);
+    }
+}
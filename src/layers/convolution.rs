@@ -0,0 +1,255 @@
+// This file contains the 2D convolution. Images are stored one per row, flattened in
+// channel, height, width order, and every sample is convolved as im2col(input) . weights.
+use rand::{RngCore, thread_rng};
+use crate::{initializer::Initializer, layers::Layer, vectors::models::Vector2D};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConvConfig {
+    pub stride: usize,
+    // zeros added on every side
+    pub padding: usize,
+    // spacing between the kernel taps, 1 is a dense kernel
+    pub dilation: usize,
+}
+
+impl Default for ConvConfig {
+    fn default() -> Self {
+        ConvConfig { stride: 1, padding: 0, dilation: 1 }
+    }
+}
+
+// For every output position p, input channel c and kernel tap k the flat input index the
+// window reads, or None where it reads padding. Entry (p * channels + c) * taps + k, which is
+// also the row-major layout of the im2col matrix of shape [positions, channels * taps].
+pub(crate) fn window_indices(input_shape: [usize; 3], kernel: [usize; 2], config: &ConvConfig) -> ([usize; 2], Vec<Option<usize>>) {
+    strided_window_indices(input_shape, kernel, [config.stride; 2], config)
+}
+
+// The same with a separate [vertical, horizontal] stride, which replaces config.stride.
+pub(crate) fn strided_window_indices(input_shape: [usize; 3], kernel: [usize; 2], stride: [usize; 2], config: &ConvConfig) -> ([usize; 2], Vec<Option<usize>>) {
+    let [channels, height, width] = input_shape;
+    let span = |k: usize| config.dilation * (k - 1) + 1;
+    if stride.contains(&0) || config.dilation == 0 || span(kernel[0]) > height + 2 * config.padding || span(kernel[1]) > width + 2 * config.padding {
+        panic!("Kernel {:?} with stride {:?}, padding {} and dilation {} does not fit an input of shape {:?}.", kernel, stride, config.padding, config.dilation, input_shape);
+    }
+    let output: [usize; 2] = [
+        (height + 2 * config.padding - span(kernel[0])) / stride[0] + 1,
+        (width + 2 * config.padding - span(kernel[1])) / stride[1] + 1,
+    ];
+
+    let mut indices: Vec<Option<usize>> = vec![];
+    for oy in 0..output[0] {
+        for ox in 0..output[1] {
+            for c in 0..channels {
+                for ky in 0..kernel[0] {
+                    for kx in 0..kernel[1] {
+                        let y: isize = (oy * stride[0] + ky * config.dilation) as isize - config.padding as isize;
+                        let x: isize = (ox * stride[1] + kx * config.dilation) as isize - config.padding as isize;
+                        let inside: bool = y >= 0 && x >= 0 && (y as usize) < height && (x as usize) < width;
+                        indices.push(if inside { Some((c * height + y as usize) * width + x as usize) } else { None });
+                    }
+                }
+            }
+        }
+    }
+    (output, indices)
+}
+
+pub(crate) fn check_input(name: &str, input: &Vector2D, input_shape: [usize; 3]) {
+    if input.shape[1] != input_shape.iter().product::<usize>() {
+        panic!("{} expects rows of {:?} = {} values but got {}.", name, input_shape, input_shape.iter().product::<usize>(), input.shape[1]);
+    }
+}
+
+pub struct Conv2D {
+    pub input_shape: [usize; 3],
+    pub filters: usize,
+    pub kernel: [usize; 2],
+    pub config: ConvConfig,
+    // [channels * kernel height * kernel width, filters]
+    pub weights: Vector2D,
+    pub biases: Vector2D,
+    output_size: [usize; 2],
+    indices: Vec<Option<usize>>,
+    columns: Vec<Vector2D>,
+    weights_gradient: Vector2D,
+    biases_gradient: Vector2D,
+}
+
+impl Conv2D {
+    pub fn new(input_shape: [usize; 3], filters: usize, kernel: [usize; 2]) -> Conv2D {
+        Conv2D::with_config(input_shape, filters, kernel, ConvConfig::default())
+    }
+
+    pub fn with_config(input_shape: [usize; 3], filters: usize, kernel: [usize; 2], config: ConvConfig) -> Conv2D {
+        let (output_size, indices) = window_indices(input_shape, kernel, &config);
+        let fan_in: usize = input_shape[0] * kernel[0] * kernel[1];
+        let mut conv: Conv2D = Conv2D {
+            input_shape, filters, kernel, config,
+            weights: Vector2D::zeros([fan_in, filters]),
+            biases: Vector2D::zeros([1, filters]),
+            output_size, indices,
+            columns: vec![],
+            weights_gradient: Vector2D::zeros([fan_in, filters]),
+            biases_gradient: Vector2D::zeros([1, filters]),
+        };
+        conv.initialize(&mut thread_rng());
+        conv
+    }
+
+    // [filters, output height, output width]
+    pub fn output_shape(&self) -> [usize; 3] {
+        [self.filters, self.output_size[0], self.output_size[1]]
+    }
+
+    fn im2col(&self, sample: &[f64]) -> Vector2D {
+        let values: Vec<f64> = self.indices.iter().map(|idx| idx.map_or(0., |i| sample[i])).collect();
+        let width: usize = self.weights.shape[0];
+        Vector2D::new(values, [self.indices.len() / width, width])
+    }
+
+    // Returns the output and the im2col matrix of every sample.
+    fn convolve(&self, input: &Vector2D) -> (Vector2D, Vec<Vector2D>) {
+        check_input("Conv2D", input, self.input_shape);
+        let mut values: Vec<f64> = vec![];
+        let mut columns: Vec<Vector2D> = vec![];
+        for row in 0..input.shape[0] {
+            let cols: Vector2D = self.im2col(&input.values[row * input.shape[1]..(row + 1) * input.shape[1]]);
+            // [positions, filters] to the channel-first layout [filters, positions]
+            values.extend(cols.dot(&self.weights).row_add(&self.biases).transpose().values);
+            columns.push(cols);
+        }
+        let size: usize = self.output_shape().iter().product();
+        (Vector2D::new(values, [input.shape[0], size]), columns)
+    }
+}
+
+impl Layer for Conv2D {
+    fn name(&self) -> &'static str {
+        "conv2d"
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        let (output, columns) = self.convolve(input);
+        self.columns = columns;
+        output
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.convolve(input).0
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.input_shape.iter().product())
+    }
+
+    fn output_size_for(&self, _input_size: usize) -> Option<usize> {
+        Some(self.output_shape().iter().product())
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let samples: usize = gradient.shape[0];
+        let positions: usize = self.output_size[0] * self.output_size[1];
+        let input_size: usize = self.input_shape.iter().product();
+        let mut weights_gradient: Vector2D = Vector2D::zeros(self.weights.shape);
+        let mut biases_gradient: Vector2D = Vector2D::zeros(self.biases.shape);
+        let mut input_gradient: Vec<f64> = vec![0.; samples * input_size];
+        let weights_t: Vector2D = self.weights.transpose();
+
+        for row in 0..samples {
+            let values: Vec<f64> = gradient.values[row * gradient.shape[1]..(row + 1) * gradient.shape[1]].to_vec();
+            let d_out: Vector2D = Vector2D::new(values, [self.filters, positions]).transpose();
+            weights_gradient = weights_gradient + self.columns[row].transpose().dot(&d_out);
+            biases_gradient = biases_gradient + d_out.mean(0) * positions as f64;
+
+            // col2im: every im2col entry flows back to the input value it was read from
+            let d_columns: Vector2D = d_out.dot(&weights_t);
+            for (entry, idx) in self.indices.iter().enumerate() {
+                if let Some(i) = idx {
+                    input_gradient[row * input_size + i] += d_columns.values[entry];
+                }
+            }
+        }
+        self.weights_gradient = weights_gradient / samples as f64;
+        self.biases_gradient = biases_gradient / samples as f64;
+        Vector2D::new(input_gradient, [samples, input_size])
+    }
+
+    // He uniform weights (the layer is usually followed by a relu) and zero biases.
+    fn initialize(&mut self, rng: &mut dyn RngCore) {
+        self.weights = Initializer::HeUniform.initialize(self.weights.shape, rng);
+        self.biases = Vector2D::zeros(self.biases.shape);
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        vec![&self.weights, &self.biases]
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        vec![&self.weights_gradient, &self.biases_gradient]
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        (vec![&mut self.weights, &mut self.biases], vec![&self.weights_gradient, &self.biases_gradient])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::gradcheck::gradcheck_layer;
+
+    fn image(shape: [usize; 3], samples: usize) -> Vector2D {
+        let size: usize = shape.iter().product();
+        Vector2D::new((0..samples * size).map(|i| ((i * 7 % 11) as f64 - 5.) / 5.).collect(), [samples, size])
+    }
+
+    #[test]
+    fn test_output_shapes() {
+        assert!(Conv2D::new([1, 5, 5], 2, [3, 3]).output_shape() == [2, 3, 3]);
+        let padded: ConvConfig = ConvConfig { padding: 1, ..ConvConfig::default() };
+        assert!(Conv2D::with_config([3, 5, 5], 4, [3, 3], padded).output_shape() == [4, 5, 5]);
+        let strided: ConvConfig = ConvConfig { stride: 2, padding: 1, dilation: 2 };
+        assert!(Conv2D::with_config([1, 7, 6], 1, [3, 2], strided).output_shape() == [1, 3, 3]);
+    }
+
+    #[test]
+    fn test_known_convolution() {
+        // a 2x2 kernel summing its window over a 3x3 image
+        let mut conv: Conv2D = Conv2D::new([1, 3, 3], 1, [2, 2]);
+        conv.weights = Vector2D::new(vec![1.; 4], [4, 1]);
+        conv.biases = Vector2D::new(vec![0.5], [1, 1]);
+        let input: Vector2D = Vector2D::new((1..=9).map(|v| v as f64).collect(), [1, 9]);
+        assert!(conv.predict(&input).values == vec![12.5, 16.5, 24.5, 28.5]);
+
+        // padding reads zeros, dilation skips every other value
+        let config: ConvConfig = ConvConfig { stride: 1, padding: 1, dilation: 2 };
+        let mut conv: Conv2D = Conv2D::with_config([1, 3, 3], 1, [2, 2], config);
+        conv.weights = Vector2D::new(vec![1.; 4], [4, 1]);
+        assert!(conv.predict(&input).values == vec![5., 10., 5., 10., 20., 10., 5., 10., 5.]);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit an input")]
+    fn test_kernel_too_large() {
+        Conv2D::new([1, 3, 3], 1, [4, 4]);
+    }
+
+    #[test]
+    fn test_gradients() {
+        let configs: [ConvConfig; 3] = [
+            ConvConfig::default(),
+            ConvConfig { stride: 2, padding: 1, dilation: 1 },
+            ConvConfig { stride: 1, padding: 2, dilation: 2 },
+        ];
+        for config in configs {
+            let mut conv: Conv2D = Conv2D::with_config([2, 5, 4], 3, [3, 2], config);
+            conv.weights = Initializer::HeUniform.initialize(conv.weights.shape, &mut StdRng::seed_from_u64(1));
+            conv.biases = Vector2D::new(vec![0.1, -0.2, 0.3], [1, 3]);
+            let report = gradcheck_layer(&mut conv, &image([2, 5, 4], 3), true, 1e-6);
+            assert!(report.passed(1e-6), "{}", report);
+        }
+    }
+}
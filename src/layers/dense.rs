@@ -0,0 +1,117 @@
+// This file contains a fully connected layer as a Layer, for models built from layers, e.g.
+// the feed-forward part of transformer blocks. It computes the same as one layer of the
+// NeuralNetwork: activation(input . weights + biases).
+use rand::{RngCore, thread_rng};
+use crate::{activation::Activation, initializer::Initializer, layers::Layer, vectors::models::Vector2D};
+
+pub struct Dense {
+    pub activation: Activation,
+    // [inputs, outputs]
+    pub weights: Vector2D,
+    pub biases: Vector2D,
+    input: Vector2D,
+    z: Vector2D,
+    weights_gradient: Vector2D,
+    biases_gradient: Vector2D,
+}
+
+impl Dense {
+    pub fn new(inputs: usize, outputs: usize, activation: Activation) -> Dense {
+        let mut dense: Dense = Dense {
+            activation,
+            weights: Vector2D::zeros([inputs, outputs]),
+            biases: Vector2D::zeros([1, outputs]),
+            input: Vector2D::default(),
+            z: Vector2D::default(),
+            weights_gradient: Vector2D::zeros([inputs, outputs]),
+            biases_gradient: Vector2D::zeros([1, outputs]),
+        };
+        dense.initialize(&mut thread_rng());
+        dense
+    }
+
+    fn check(&self, input: &Vector2D) {
+        if input.shape[1] != self.weights.shape[0] {
+            panic!("Dense layer expects {} inputs but got {}.", self.weights.shape[0], input.shape[1]);
+        }
+    }
+}
+
+impl Layer for Dense {
+    fn name(&self) -> &'static str {
+        "dense"
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        self.check(input);
+        self.input = input.clone();
+        self.z = input.dot(&self.weights).row_add(&self.biases);
+        self.activation.apply(&self.z)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.check(input);
+        self.activation.apply(&input.dot(&self.weights).row_add(&self.biases))
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.weights.shape[0])
+    }
+
+    fn output_size_for(&self, _input_size: usize) -> Option<usize> {
+        Some(self.weights.shape[1])
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let d_z: Vector2D = gradient * &self.activation.derivative(&self.z);
+        self.weights_gradient = self.input.transpose().dot(&d_z) / gradient.shape[0] as f64;
+        self.biases_gradient = d_z.mean(0);
+        d_z.dot(&self.weights.transpose())
+    }
+
+    // Xavier normal weights and zero biases, like the layers of the NeuralNetwork.
+    fn initialize(&mut self, rng: &mut dyn RngCore) {
+        self.weights = Initializer::XavierNormal.initialize(self.weights.shape, rng);
+        self.biases = Vector2D::zeros(self.biases.shape);
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        vec![&self.weights, &self.biases]
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        vec![&self.weights_gradient, &self.biases_gradient]
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        (vec![&mut self.weights, &mut self.biases], vec![&self.weights_gradient, &self.biases_gradient])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::gradcheck::gradcheck_layer;
+
+    #[test]
+    fn test_known_output() {
+        let mut dense: Dense = Dense::new(2, 1, Activation::Identity);
+        dense.weights = Vector2D::new(vec![2., -1.], [2, 1]);
+        dense.biases = Vector2D::new(vec![0.5], [1, 1]);
+        assert!(dense.predict(&Vector2D::new(vec![1., 1., 3., 2.], [2, 2])).values == vec![1.5, 4.5]);
+    }
+
+    #[test]
+    fn test_gradients() {
+        let input: Vector2D = Vector2D::new(vec![0.5, -1., 2., 1.5, 0.3, -0.7], [2, 3]);
+        for activation in [Activation::Sigmoid, Activation::Tanh, Activation::Identity] {
+            let mut dense: Dense = Dense::new(3, 2, activation);
+            dense.initialize(&mut StdRng::seed_from_u64(1));
+            dense.biases = Vector2D::new(vec![0.1, -0.2], [1, 2]);
+            let report = gradcheck_layer(&mut dense, &input, true, 1e-6);
+            assert!(report.passed(1e-6), "{:?}\n{}", activation, report);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// This file contains inverted dropout: during training every value is zeroed with
+// probability rate and the rest is scaled by 1 / (1 - rate), at inference it does nothing.
+use rand::{Rng, SeedableRng, thread_rng, rngs::StdRng};
+use crate::{layers::Layer, vectors::models::Vector2D};
+
+pub struct Dropout {
+    pub rate: f64,
+    rng: StdRng,
+    mask: Vector2D,
+}
+
+impl Dropout {
+    pub fn new(rate: f64) -> Dropout {
+        Dropout::with_seed(rate, thread_rng().gen())
+    }
+
+    pub fn with_seed(rate: f64, seed: u64) -> Dropout {
+        if !(0. ..1.).contains(&rate) {
+            panic!("Dropout rate has to be in [0, 1) but is {}.", rate);
+        }
+        Dropout { rate, rng: StdRng::seed_from_u64(seed), mask: Vector2D::default() }
+    }
+}
+
+impl Layer for Dropout {
+    fn name(&self) -> &'static str {
+        "dropout"
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn forward(&mut self, input: &Vector2D, training: bool) -> Vector2D {
+        if !training || self.rate == 0. {
+            self.mask = Vector2D::new(vec![1.; input.values.len()], input.shape);
+            return input.clone();
+        }
+        let keep: f64 = 1. - self.rate;
+        let values: Vec<f64> = (0..input.values.len())
+            .map(|_| if self.rng.gen::<f64>() < keep { 1. / keep } else { 0. })
+            .collect();
+        self.mask = Vector2D::new(values, input.shape);
+        input * &self.mask
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        input.clone()
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        gradient * &self.mask
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_training_masks_and_scales() {
+        let mut dropout: Dropout = Dropout::with_seed(0.25, 7);
+        let input: Vector2D = Vector2D::new(vec![1.; 4000], [1000, 4]);
+        let output: Vector2D = dropout.forward(&input, true);
+        let dropped: usize = output.values.iter().filter(|v| **v == 0.).count();
+        assert!(output.values.iter().all(|v| *v == 0. || *v == 1. / 0.75));
+        assert!((dropped as f64 / 4000. - 0.25).abs() < 0.03);
+
+        // the gradient goes through the same mask
+        let gradient: Vector2D = dropout.backward(&input);
+        assert!(gradient == output);
+    }
+
+    #[test]
+    fn test_identity_at_inference() {
+        let mut dropout: Dropout = Dropout::with_seed(0.5, 7);
+        let input: Vector2D = Vector2D::new(vec![1., 2., 3., 4.], [2, 2]);
+        assert!(dropout.predict(&input) == input);
+        assert!(dropout.forward(&input, false) == input);
+        assert!(dropout.backward(&input) == input);
+    }
+
+    #[test]
+    fn test_seeded_masks_repeat() {
+        let input: Vector2D = Vector2D::new(vec![1.; 100], [10, 10]);
+        let first: Vector2D = Dropout::with_seed(0.5, 3).forward(&input, true);
+        assert!(Dropout::with_seed(0.5, 3).forward(&input, true) == first);
+        assert!(Dropout::with_seed(0.5, 4).forward(&input, true) != first);
+    }
+
+    #[test]
+    #[should_panic(expected = "Dropout rate has to be in [0, 1)")]
+    fn test_invalid_rate() {
+        Dropout::new(1.);
+    }
+}
@@ -0,0 +1,197 @@
+// This file contains the embedding lookup. Every input row is a sequence of token ids (stored
+// as f64) and every token is replaced by its row of the table, so [batch, tokens] becomes
+// [batch, tokens * dimension], the sequence layout of the recurrent and attention layers.
+use std::collections::BTreeMap;
+use rand::{RngCore, thread_rng};
+use crate::{initializer::Initializer, layers::Layer, vectors::models::Vector2D};
+
+pub struct Embedding {
+    // [vocabulary, dimension]
+    pub weights: Vector2D,
+    // Sparse embeddings are not handed to the optimizer: sparse_update changes only the rows
+    // of the tokens in the last batch, with plain SGD. Dense embeddings are trained by the
+    // optimizer like any other parameter.
+    pub sparse: bool,
+    tokens: Vec<usize>,
+    sparse_gradient: Vec<(usize, Vec<f64>)>,
+    dense_gradient: Vector2D,
+}
+
+impl Embedding {
+    pub fn new(vocabulary: usize, dimension: usize) -> Embedding {
+        let mut embedding: Embedding = Embedding {
+            weights: Vector2D::zeros([vocabulary, dimension]),
+            sparse: true,
+            tokens: vec![],
+            sparse_gradient: vec![],
+            dense_gradient: Vector2D::zeros([vocabulary, dimension]),
+        };
+        embedding.initialize(&mut thread_rng());
+        embedding
+    }
+
+    pub fn vocabulary(&self) -> usize {
+        self.weights.shape[0]
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.weights.shape[1]
+    }
+
+    // The gradient of every token of the last batch, sorted by token.
+    pub fn sparse_gradient(&self) -> &[(usize, Vec<f64>)] {
+        &self.sparse_gradient
+    }
+
+    fn tokens(&self, input: &Vector2D) -> Vec<usize> {
+        input.values.iter().map(|value| {
+            let token: f64 = value.round();
+            if !token.is_finite() || token < 0. || token >= self.vocabulary() as f64 {
+                panic!("Token {} is outside the vocabulary of {} tokens.", value, self.vocabulary());
+            }
+            token as usize
+        }).collect()
+    }
+
+    fn lookup(&self, tokens: &[usize], shape: [usize; 2]) -> Vector2D {
+        let dimension: usize = self.dimension();
+        let mut values: Vec<f64> = vec![];
+        for token in tokens {
+            values.extend_from_slice(&self.weights.values[token * dimension..(token + 1) * dimension]);
+        }
+        Vector2D::new(values, [shape[0], shape[1] * dimension])
+    }
+}
+
+impl Layer for Embedding {
+    fn name(&self) -> &'static str {
+        "embedding"
+    }
+
+    // Every token becomes a row of the table.
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size * self.dimension())
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        self.tokens = self.tokens(input);
+        self.lookup(&self.tokens, input.shape)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.lookup(&self.tokens(input), input.shape)
+    }
+
+    // Token ids have no gradient, so the returned input gradient is zero.
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let dimension: usize = self.dimension();
+        let samples: usize = gradient.shape[0];
+        let mut rows: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+        for (position, token) in self.tokens.iter().enumerate() {
+            let row: &mut Vec<f64> = rows.entry(*token).or_insert_with(|| vec![0.; dimension]);
+            for (d, value) in row.iter_mut().enumerate() {
+                *value += gradient.values[position * dimension + d] / samples as f64;
+            }
+        }
+        self.sparse_gradient = rows.into_iter().collect();
+
+        if !self.sparse {
+            self.dense_gradient = Vector2D::zeros(self.weights.shape);
+            for (token, row) in &self.sparse_gradient {
+                self.dense_gradient.values[token * dimension..(token + 1) * dimension].copy_from_slice(row);
+            }
+        }
+        Vector2D::zeros([samples, self.tokens.len() / samples])
+    }
+
+    fn initialize(&mut self, rng: &mut dyn RngCore) {
+        self.weights = Initializer::XavierNormal.initialize(self.weights.shape, rng);
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        vec![&self.weights]
+    }
+
+    // Empty for sparse embeddings, which update themselves.
+    fn gradients(&self) -> Vec<&Vector2D> {
+        if self.sparse { vec![] } else { vec![&self.dense_gradient] }
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        if self.sparse {
+            return (vec![], vec![]);
+        }
+        (vec![&mut self.weights], vec![&self.dense_gradient])
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Vector2D> {
+        vec![&mut self.weights]
+    }
+
+    fn sparse_update(&mut self, learning_rate: f64) {
+        if !self.sparse {
+            return;
+        }
+        let dimension: usize = self.dimension();
+        for (token, row) in std::mem::take(&mut self.sparse_gradient) {
+            for (d, value) in row.iter().enumerate() {
+                self.weights.values[token * dimension + d] -= learning_rate * value;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::gradcheck_layer;
+
+    fn table() -> Embedding {
+        let mut embedding: Embedding = Embedding::new(4, 2);
+        embedding.weights = Vector2D::new((0..8).map(|v| v as f64).collect(), [4, 2]);
+        embedding
+    }
+
+    #[test]
+    fn test_lookup() {
+        let output: Vector2D = table().predict(&Vector2D::new(vec![3., 0., 1., 1.], [2, 2]));
+        assert!(output.shape == [2, 4]);
+        assert!(output.values == vec![6., 7., 0., 1., 2., 3., 2., 3.]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token 4 is outside the vocabulary of 4 tokens")]
+    fn test_unknown_token() {
+        table().predict(&Vector2D::new(vec![4.], [1, 1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Token NaN is outside the vocabulary of 4 tokens")]
+    fn test_nan_token() {
+        table().predict(&Vector2D::new(vec![f64::NAN], [1, 1]));
+    }
+
+    #[test]
+    fn test_sparse_update_only_changes_seen_tokens() {
+        let mut embedding: Embedding = table();
+        embedding.forward(&Vector2D::new(vec![1., 3., 1., 1.], [2, 2]), true);
+        embedding.backward(&Vector2D::new(vec![1.; 8], [2, 4]));
+        // token 1 appears three times in a batch of two samples
+        assert!(embedding.sparse_gradient() == [(1, vec![1.5, 1.5]), (3, vec![0.5, 0.5])]);
+        assert!(embedding.parameters_and_gradients().0.is_empty());
+
+        embedding.sparse_update(1.);
+        assert!(embedding.weights.values == vec![0., 1., 0.5, 1.5, 4., 5., 5.5, 6.5]);
+        assert!(embedding.sparse_gradient().is_empty());
+    }
+
+    #[test]
+    fn test_dense_gradients() {
+        let mut embedding: Embedding = table();
+        embedding.sparse = false;
+        let report = gradcheck_layer(&mut embedding, &Vector2D::new(vec![1., 3., 1., 0., 2., 2.], [2, 3]), true, 1e-6);
+        assert!(report.passed(1e-6), "{}", report);
+        assert!(report.errors.len() == 2);
+    }
+}
@@ -0,0 +1,78 @@
+// This file contains the Layer trait for layers that are inserted before the first dense
+// layer, e.g. convolutions or recurrent layers, or behind the activation of a dense layer,
+// e.g. dropout.
+pub mod dropout;
+pub mod normalization;
+pub mod convolution;
+pub mod pooling;
+pub mod recurrent;
+pub mod dense;
+pub mod embedding;
+pub mod attention;
+
+use rand::RngCore;
+use crate::vectors::models::Vector2D;
+
+// The network passes gradients with respect to every sample's own loss (the loss derivative
+// is not divided by the batch size), so layers return the input gradient as it is and
+// average their parameter gradients over the rows of the batch.
+pub trait Layer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Caches whatever backward needs. Layers like dropout only act if training is set.
+    fn forward(&mut self, input: &Vector2D, training: bool) -> Vector2D;
+
+    // Inference without caching, so networks can predict through a shared reference.
+    fn predict(&self, input: &Vector2D) -> Vector2D;
+
+    // Takes the gradient with respect to the output of the last forward call, stores the
+    // parameter gradients and returns the gradient with respect to the input.
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D;
+
+    // Number of input values per sample if the layer fixes it, which lets summaries infer
+    // the shapes of the input layers. Sequence layers accept any number of timesteps.
+    fn input_size(&self) -> Option<usize> {
+        None
+    }
+
+    // Number of output values per sample for inputs of input_size values, derived from the
+    // configuration of the layer. None if the layer can not tell.
+    fn output_size_for(&self, _input_size: usize) -> Option<usize> {
+        None
+    }
+
+    // Draws new random parameters. The network calls it with a generator derived from its seed
+    // when the layer is added, layers without random parameters keep the default.
+    fn initialize(&mut self, _rng: &mut dyn RngCore) {}
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        vec![]
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        vec![]
+    }
+
+    // Both at once, in the same order, for the optimizer.
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        (vec![], vec![])
+    }
+
+    // The parameters again, in the same order, to restore saved values. Layers that keep
+    // parameters away from the optimizer have to override it.
+    fn parameters_mut(&mut self) -> Vec<&mut Vector2D> {
+        self.parameters_and_gradients().0
+    }
+
+    // State that forward changes but the optimizer does not, e.g. the running statistics of
+    // batch normalization, so it can be saved and put back.
+    fn buffers(&self) -> Vec<Vector2D> {
+        vec![]
+    }
+
+    fn load_buffers(&mut self, _buffers: &[Vector2D]) {}
+
+    // Called after the optimizer step for parameters the layer keeps away from the optimizer,
+    // e.g. embeddings that only change the rows of the tokens in the batch.
+    fn sparse_update(&mut self, _learning_rate: f64) {}
+}
@@ -0,0 +1,253 @@
+// This file contains batch normalization, which normalizes every feature over the batch, and
+// layer normalization, which normalizes every sample over its features. Both scale and shift
+// the result by the learnable gamma and beta.
+use crate::{layers::Layer, vectors::models::Vector2D};
+
+pub struct BatchNorm {
+    pub momentum: f64,
+    pub epsilon: f64,
+    pub gamma: Vector2D,
+    pub beta: Vector2D,
+    // used instead of the batch statistics at inference
+    pub running_mean: Vector2D,
+    pub running_var: Vector2D,
+    gamma_gradient: Vector2D,
+    beta_gradient: Vector2D,
+    x_hat: Vector2D,
+    std_inv: Vector2D,
+    batch_statistics: bool,
+}
+
+impl BatchNorm {
+    pub fn new(features: usize) -> BatchNorm {
+        BatchNorm::with_momentum(features, 0.9, 1e-5)
+    }
+
+    pub fn with_momentum(features: usize, momentum: f64, epsilon: f64) -> BatchNorm {
+        BatchNorm {
+            momentum, epsilon,
+            gamma: Vector2D::new(vec![1.; features], [1, features]),
+            beta: Vector2D::zeros([1, features]),
+            running_mean: Vector2D::zeros([1, features]),
+            running_var: Vector2D::new(vec![1.; features], [1, features]),
+            gamma_gradient: Vector2D::zeros([1, features]),
+            beta_gradient: Vector2D::zeros([1, features]),
+            x_hat: Vector2D::default(),
+            std_inv: Vector2D::default(),
+            batch_statistics: false,
+        }
+    }
+
+    fn std_inv(&self, var: &Vector2D) -> Vector2D {
+        var.map(|v| 1. / (v + self.epsilon).sqrt())
+    }
+
+    fn normalize(&self, input: &Vector2D) -> Vector2D {
+        let x_hat: Vector2D = input.row_add(&(&self.running_mean * -1.)).row_mul(&self.std_inv(&self.running_var));
+        x_hat.row_mul(&self.gamma).row_add(&self.beta)
+    }
+}
+
+impl Layer for BatchNorm {
+    fn name(&self) -> &'static str {
+        "batch_norm"
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn forward(&mut self, input: &Vector2D, training: bool) -> Vector2D {
+        self.batch_statistics = training;
+        if !training {
+            self.std_inv = self.std_inv(&self.running_var);
+            self.x_hat = input.row_add(&(&self.running_mean * -1.)).row_mul(&self.std_inv);
+            return self.normalize(input);
+        }
+
+        let mean: Vector2D = input.mean(0);
+        let centered: Vector2D = input.row_add(&(&mean * -1.));
+        let var: Vector2D = (&centered * &centered).mean(0);
+        self.std_inv = self.std_inv(&var);
+        self.x_hat = centered.row_mul(&self.std_inv);
+
+        self.running_mean = self.momentum * &self.running_mean + (1. - self.momentum) * mean;
+        self.running_var = self.momentum * &self.running_var + (1. - self.momentum) * var;
+        self.x_hat.row_mul(&self.gamma).row_add(&self.beta)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.normalize(input)
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        self.beta_gradient = gradient.mean(0);
+        self.gamma_gradient = (gradient * &self.x_hat).mean(0);
+        let dx_hat: Vector2D = gradient.row_mul(&self.gamma);
+        if !self.batch_statistics {
+            return dx_hat.row_mul(&self.std_inv);
+        }
+        // the batch mean and variance depend on every sample of the batch
+        let mean_term: Vector2D = dx_hat.mean(0);
+        let var_term: Vector2D = (&dx_hat * &self.x_hat).mean(0);
+        (dx_hat.row_add(&(mean_term * -1.)) - self.x_hat.row_mul(&var_term)).row_mul(&self.std_inv)
+    }
+
+    fn buffers(&self) -> Vec<Vector2D> {
+        vec![self.running_mean.clone(), self.running_var.clone()]
+    }
+
+    fn load_buffers(&mut self, buffers: &[Vector2D]) {
+        self.running_mean = buffers[0].clone();
+        self.running_var = buffers[1].clone();
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        vec![&self.gamma, &self.beta]
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        vec![&self.gamma_gradient, &self.beta_gradient]
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        (vec![&mut self.gamma, &mut self.beta], vec![&self.gamma_gradient, &self.beta_gradient])
+    }
+}
+
+// Behaves the same while training and at inference.
+pub struct LayerNorm {
+    pub epsilon: f64,
+    pub gamma: Vector2D,
+    pub beta: Vector2D,
+    gamma_gradient: Vector2D,
+    beta_gradient: Vector2D,
+    x_hat: Vector2D,
+    std_inv: Vector2D,
+}
+
+impl LayerNorm {
+    pub fn new(features: usize) -> LayerNorm {
+        LayerNorm {
+            epsilon: 1e-5,
+            gamma: Vector2D::new(vec![1.; features], [1, features]),
+            beta: Vector2D::zeros([1, features]),
+            gamma_gradient: Vector2D::zeros([1, features]),
+            beta_gradient: Vector2D::zeros([1, features]),
+            x_hat: Vector2D::default(),
+            std_inv: Vector2D::default(),
+        }
+    }
+
+    // Returns the normalized input and the inverse standard deviation of every row.
+    fn normalize(&self, input: &Vector2D) -> (Vector2D, Vector2D) {
+        let centered: Vector2D = input.column_add(&(input.mean(1) * -1.));
+        let std_inv: Vector2D = (&centered * &centered).mean(1).map(|v| 1. / (v + self.epsilon).sqrt());
+        (centered.column_mul(&std_inv), std_inv)
+    }
+}
+
+impl Layer for LayerNorm {
+    fn name(&self) -> &'static str {
+        "layer_norm"
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        (self.x_hat, self.std_inv) = self.normalize(input);
+        self.x_hat.row_mul(&self.gamma).row_add(&self.beta)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.normalize(input).0.row_mul(&self.gamma).row_add(&self.beta)
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        self.beta_gradient = gradient.mean(0);
+        self.gamma_gradient = (gradient * &self.x_hat).mean(0);
+        let dx_hat: Vector2D = gradient.row_mul(&self.gamma);
+        let mean_term: Vector2D = dx_hat.mean(1);
+        let var_term: Vector2D = (&dx_hat * &self.x_hat).mean(1);
+        (dx_hat.column_add(&(mean_term * -1.)) - self.x_hat.column_mul(&var_term)).column_mul(&self.std_inv)
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        vec![&self.gamma, &self.beta]
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        vec![&self.gamma_gradient, &self.beta_gradient]
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        (vec![&mut self.gamma, &mut self.beta], vec![&self.gamma_gradient, &self.beta_gradient])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::gradcheck_layer;
+
+    fn input() -> Vector2D {
+        Vector2D::new(vec![0.5, -1., 2., 1.5, 0.3, -0.7, -2., 0.8, 1.1, 0.1, 0.4, -0.2], [4, 3])
+    }
+
+    #[test]
+    fn test_batch_norm_normalizes_features() {
+        let mut bn: BatchNorm = BatchNorm::new(3);
+        let output: Vector2D = bn.forward(&input(), true);
+        for column in 0..3 {
+            let values: Vec<f64> = (0..4).map(|row| output[(row, column)]).collect();
+            let mean: f64 = values.iter().sum::<f64>() / 4.;
+            let var: f64 = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 4.;
+            assert!(mean.abs() < 1e-12);
+            assert!((var - 1.).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_batch_norm_running_statistics() {
+        let mut bn: BatchNorm = BatchNorm::with_momentum(3, 0., 0.);
+        let x: Vector2D = input();
+        let training: Vector2D = bn.forward(&x, true);
+        assert!(bn.running_mean == x.mean(0));
+        // with momentum 0 the running statistics are those of the last batch
+        let inference: Vector2D = bn.predict(&x);
+        for (a, b) in training.values.iter().zip(inference.values.iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+        assert!(bn.forward(&x, false) == inference);
+    }
+
+    #[test]
+    fn test_batch_norm_gradients() {
+        let mut bn: BatchNorm = BatchNorm::new(3);
+        bn.gamma = Vector2D::new(vec![1.5, -0.5, 0.8], [1, 3]);
+        bn.beta = Vector2D::new(vec![0.1, 0.2, -0.3], [1, 3]);
+        assert!(gradcheck_layer(&mut bn, &input(), true, 1e-6).passed(1e-6));
+    }
+
+    #[test]
+    fn test_layer_norm_normalizes_samples() {
+        let mut ln: LayerNorm = LayerNorm::new(3);
+        let output: Vector2D = ln.forward(&input(), true);
+        assert!(output == ln.predict(&input()));
+        for row in 0..4 {
+            let mean: f64 = (0..3).map(|column| output[(row, column)]).sum::<f64>() / 3.;
+            assert!(mean.abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_layer_norm_gradients() {
+        let mut ln: LayerNorm = LayerNorm::new(3);
+        ln.gamma = Vector2D::new(vec![1.5, -0.5, 0.8], [1, 3]);
+        ln.beta = Vector2D::new(vec![0.1, 0.2, -0.3], [1, 3]);
+        assert!(gradcheck_layer(&mut ln, &input(), true, 1e-6).passed(1e-6));
+    }
+}
@@ -0,0 +1,346 @@
+// This file contains the pooling layers and Flatten for images stored one per row in channel,
+// height, width order. None of them has parameters.
+use crate::{layers::{Layer, convolution::{ConvConfig, check_input, strided_window_indices}}, vectors::models::Vector2D};
+
+// The windows of the pooling layers, built with the same index map as the convolution.
+struct Windows {
+    input_shape: [usize; 3],
+    output_size: [usize; 2],
+    taps: usize,
+    indices: Vec<Option<usize>>,
+}
+
+impl Windows {
+    fn new(input_shape: [usize; 3], pool: [usize; 2], stride: [usize; 2], config: &ConvConfig) -> Windows {
+        let (output_size, indices) = strided_window_indices(input_shape, pool, stride, config);
+        Windows { input_shape, output_size, taps: pool[0] * pool[1], indices }
+    }
+
+    fn output_shape(&self) -> [usize; 3] {
+        [self.input_shape[0], self.output_size[0], self.output_size[1]]
+    }
+
+    // Input indices of the window of channel c at output position p, without padding.
+    fn window(&self, c: usize, p: usize) -> impl Iterator<Item = usize> + '_ {
+        let start: usize = (p * self.input_shape[0] + c) * self.taps;
+        self.indices[start..start + self.taps].iter().filter_map(|idx| *idx)
+    }
+
+    // Calls f(sample, output index, window) for every output value, in output order.
+    fn for_each<F: FnMut(usize, usize, &mut dyn Iterator<Item = usize>)>(&self, samples: usize, mut f: F) {
+        let positions: usize = self.output_size[0] * self.output_size[1];
+        for sample in 0..samples {
+            for c in 0..self.input_shape[0] {
+                for p in 0..positions {
+                    f(sample, c * positions + p, &mut self.window(c, p));
+                }
+            }
+        }
+    }
+}
+
+fn output_length(windows: &Windows) -> usize {
+    windows.output_shape().iter().product()
+}
+
+pub struct MaxPool2D {
+    windows: Windows,
+    // input index of the maximum of every output value
+    argmax: Vec<usize>,
+    input_rows: usize,
+}
+
+impl MaxPool2D {
+    // The stride along each axis is the pool size along it, so the windows do not overlap.
+    pub fn new(input_shape: [usize; 3], pool: [usize; 2]) -> MaxPool2D {
+        MaxPool2D { windows: Windows::new(input_shape, pool, pool, &ConvConfig::default()), argmax: vec![], input_rows: 0 }
+    }
+
+    pub fn with_config(input_shape: [usize; 3], pool: [usize; 2], config: ConvConfig) -> MaxPool2D {
+        MaxPool2D { windows: Windows::new(input_shape, pool, [config.stride; 2], &config), argmax: vec![], input_rows: 0 }
+    }
+
+    pub fn output_shape(&self) -> [usize; 3] {
+        self.windows.output_shape()
+    }
+
+    fn pool(&self, input: &Vector2D) -> (Vector2D, Vec<usize>) {
+        check_input("MaxPool2D", input, self.windows.input_shape);
+        let mut values: Vec<f64> = vec![];
+        let mut argmax: Vec<usize> = vec![];
+        self.windows.for_each(input.shape[0], |sample, _, window| {
+            let offset: usize = sample * input.shape[1];
+            let best: usize = window.fold(None, |best: Option<usize>, i| match best {
+                Some(b) if input.values[offset + b] >= input.values[offset + i] => Some(b),
+                _ => Some(i),
+            }).expect("Pooling window lies completely in the padding.");
+            values.push(input.values[offset + best]);
+            argmax.push(best);
+        });
+        (Vector2D::new(values, [input.shape[0], output_length(&self.windows)]), argmax)
+    }
+}
+
+impl Layer for MaxPool2D {
+    fn name(&self) -> &'static str {
+        "max_pool2d"
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        let (output, argmax) = self.pool(input);
+        self.argmax = argmax;
+        self.input_rows = input.shape[0];
+        output
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.pool(input).0
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.windows.input_shape.iter().product())
+    }
+
+    fn output_size_for(&self, _input_size: usize) -> Option<usize> {
+        Some(output_length(&self.windows))
+    }
+
+    // Only the maximum of every window gets the gradient.
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let input_size: usize = self.windows.input_shape.iter().product();
+        let outputs: usize = output_length(&self.windows);
+        let mut values: Vec<f64> = vec![0.; self.input_rows * input_size];
+        for (entry, i) in self.argmax.iter().enumerate() {
+            values[entry / outputs * input_size + i] += gradient.values[entry];
+        }
+        Vector2D::new(values, [self.input_rows, input_size])
+    }
+}
+
+// Padding is not counted in the averages.
+pub struct AvgPool2D {
+    windows: Windows,
+    input_rows: usize,
+}
+
+impl AvgPool2D {
+    // Non-overlapping windows like MaxPool2D::new.
+    pub fn new(input_shape: [usize; 3], pool: [usize; 2]) -> AvgPool2D {
+        AvgPool2D { windows: Windows::new(input_shape, pool, pool, &ConvConfig::default()), input_rows: 0 }
+    }
+
+    pub fn with_config(input_shape: [usize; 3], pool: [usize; 2], config: ConvConfig) -> AvgPool2D {
+        AvgPool2D { windows: Windows::new(input_shape, pool, [config.stride; 2], &config), input_rows: 0 }
+    }
+
+    pub fn output_shape(&self) -> [usize; 3] {
+        self.windows.output_shape()
+    }
+}
+
+impl Layer for AvgPool2D {
+    fn name(&self) -> &'static str {
+        "avg_pool2d"
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        self.input_rows = input.shape[0];
+        self.predict(input)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        check_input("AvgPool2D", input, self.windows.input_shape);
+        let mut values: Vec<f64> = vec![];
+        self.windows.for_each(input.shape[0], |sample, _, window| {
+            let window: Vec<usize> = window.collect();
+            let sum: f64 = window.iter().map(|i| input.values[sample * input.shape[1] + i]).sum();
+            values.push(sum / window.len() as f64);
+        });
+        Vector2D::new(values, [input.shape[0], output_length(&self.windows)])
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.windows.input_shape.iter().product())
+    }
+
+    fn output_size_for(&self, _input_size: usize) -> Option<usize> {
+        Some(output_length(&self.windows))
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let input_size: usize = self.windows.input_shape.iter().product();
+        let mut values: Vec<f64> = vec![0.; self.input_rows * input_size];
+        self.windows.for_each(self.input_rows, |sample, output, window| {
+            let window: Vec<usize> = window.collect();
+            let share: f64 = gradient[(sample, output)] / window.len() as f64;
+            for i in window {
+                values[sample * input_size + i] += share;
+            }
+        });
+        Vector2D::new(values, [self.input_rows, input_size])
+    }
+}
+
+// Averages every channel over the whole image, [channels, height, width] to [channels].
+pub struct GlobalAveragePool {
+    pub input_shape: [usize; 3],
+}
+
+impl GlobalAveragePool {
+    pub fn new(input_shape: [usize; 3]) -> GlobalAveragePool {
+        GlobalAveragePool { input_shape }
+    }
+}
+
+impl Layer for GlobalAveragePool {
+    fn name(&self) -> &'static str {
+        "global_average_pool"
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        self.predict(input)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        check_input("GlobalAveragePool", input, self.input_shape);
+        let [channels, height, width] = self.input_shape;
+        // every row of the reshaped input is one channel of one sample
+        let means: Vector2D = Vector2D::new(input.values.clone(), [input.shape[0] * channels, height * width]).mean(1);
+        Vector2D::new(means.values, [input.shape[0], channels])
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.input_shape.iter().product())
+    }
+
+    fn output_size_for(&self, _input_size: usize) -> Option<usize> {
+        Some(self.input_shape[0])
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let [channels, height, width] = self.input_shape;
+        let area: usize = height * width;
+        let values: Vec<f64> = (0..gradient.shape[0] * channels * area).map(|i| gradient.values[i / area] / area as f64).collect();
+        Vector2D::new(values, [gradient.shape[0], channels * area])
+    }
+}
+
+// Images are already flat rows, so Flatten only checks the size and marks where the image
+// layers end and the dense layers begin.
+pub struct Flatten {
+    pub input_shape: [usize; 3],
+}
+
+impl Flatten {
+    pub fn new(input_shape: [usize; 3]) -> Flatten {
+        Flatten { input_shape }
+    }
+}
+
+impl Layer for Flatten {
+    fn name(&self) -> &'static str {
+        "flatten"
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        self.predict(input)
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        check_input("Flatten", input, self.input_shape);
+        input.clone()
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.input_shape.iter().product())
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        gradient.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::gradcheck_layer;
+
+    fn image() -> Vector2D {
+        // two samples of two 4x4 channels with distinct values, so maxima are unique
+        Vector2D::new((0..64).map(|i| ((i * 37 % 64) as f64) / 10.).collect(), [2, 32])
+    }
+
+    #[test]
+    fn test_max_pool() {
+        let input: Vector2D = Vector2D::new((1..=16).map(|v| v as f64).collect(), [1, 16]);
+        let mut pool: MaxPool2D = MaxPool2D::new([1, 4, 4], [2, 2]);
+        assert!(pool.output_shape() == [1, 2, 2]);
+        assert!(pool.forward(&input, true).values == vec![6., 8., 14., 16.]);
+
+        let gradient: Vector2D = pool.backward(&Vector2D::new(vec![1., 2., 3., 4.], [1, 4]));
+        let mut expected: Vec<f64> = vec![0.; 16];
+        expected[5] = 1.;
+        expected[7] = 2.;
+        expected[13] = 3.;
+        expected[15] = 4.;
+        assert!(gradient.values == expected);
+    }
+
+    #[test]
+    fn test_rectangular_pools_do_not_overlap() {
+        let input: Vector2D = Vector2D::new((1..=24).map(|v| v as f64).collect(), [1, 24]);
+        let max: MaxPool2D = MaxPool2D::new([1, 4, 6], [2, 3]);
+        assert!(max.output_shape() == [1, 2, 2]);
+        assert!(max.predict(&input).values == vec![9., 12., 21., 24.]);
+        let avg: AvgPool2D = AvgPool2D::new([1, 4, 6], [1, 3]);
+        assert!(avg.output_shape() == [1, 4, 2]);
+        assert!(avg.predict(&input).values == vec![2., 5., 8., 11., 14., 17., 20., 23.]);
+    }
+
+    #[test]
+    fn test_avg_pool_ignores_padding() {
+        let input: Vector2D = Vector2D::new((1..=4).map(|v| v as f64).collect(), [1, 4]);
+        let config: ConvConfig = ConvConfig { stride: 1, padding: 1, dilation: 1 };
+        let pool: AvgPool2D = AvgPool2D::with_config([1, 2, 2], [2, 2], config);
+        assert!(pool.output_shape() == [1, 3, 3]);
+        assert!(pool.predict(&input).values == vec![1., 1.5, 2., 2., 2.5, 3., 3., 3.5, 4.]);
+    }
+
+    #[test]
+    fn test_global_average_pool_and_flatten() {
+        let input: Vector2D = image();
+        let output: Vector2D = GlobalAveragePool::new([2, 4, 4]).predict(&input);
+        assert!(output.shape == [2, 2]);
+        let first: f64 = input.values[..16].iter().sum::<f64>() / 16.;
+        assert!((output[(0, 0)] - first).abs() < 1e-12);
+        assert!(Flatten::new([2, 4, 4]).predict(&input) == input);
+    }
+
+    #[test]
+    #[should_panic(expected = "Flatten expects rows of [2, 3, 3] = 18 values but got 32")]
+    fn test_flatten_checks_size() {
+        Flatten::new([2, 3, 3]).predict(&image());
+    }
+
+    #[test]
+    fn test_gradients() {
+        let overlapping: ConvConfig = ConvConfig { stride: 1, padding: 1, dilation: 1 };
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(MaxPool2D::new([2, 4, 4], [2, 2])),
+            Box::new(MaxPool2D::with_config([2, 4, 4], [3, 3], overlapping)),
+            Box::new(AvgPool2D::new([2, 4, 4], [2, 2])),
+            Box::new(AvgPool2D::with_config([2, 4, 4], [3, 3], overlapping)),
+            Box::new(GlobalAveragePool::new([2, 4, 4])),
+            Box::new(Flatten::new([2, 4, 4])),
+        ];
+        for mut layer in layers {
+            let report = gradcheck_layer(layer.as_mut(), &image(), true, 1e-6);
+            assert!(report.passed(1e-6), "{}\n{}", layer.name(), report);
+        }
+    }
+}
@@ -0,0 +1,460 @@
+// This file contains the recurrent layers RNN, LSTM and GRU. Every row of the input is one
+// sequence, flattened in timestep, feature order, so a batch of sequences with 5 timesteps of
+// 3 features has shape [batch, 15]. The layers either return the hidden state of every
+// timestep, [batch, timesteps * hidden], or only the last one, [batch, hidden].
+use std::marker::PhantomData;
+use rand::{RngCore, thread_rng};
+use crate::{activation::{sigmoid, tanh}, initializer::Initializer, layers::Layer, vectors::models::Vector2D};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RecurrentConfig {
+    // the hidden state of every timestep instead of only the last one
+    pub return_sequences: bool,
+    // truncated backpropagation through time: gradients do not flow back over the start of
+    // every chunk of this many timesteps
+    pub truncation: Option<usize>,
+    // rescales the parameter gradients of the layer to at most this global norm
+    pub clip_norm: Option<f64>,
+}
+
+// The gates of a cell are stacked along the columns, in the order the cell documents.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecurrentWeights {
+    // [features, gates * hidden]
+    pub input: Vector2D,
+    // [hidden, gates * hidden]
+    pub hidden: Vector2D,
+    // [1, gates * hidden]
+    pub biases: Vector2D,
+}
+
+impl RecurrentWeights {
+    fn zeros(features: usize, hidden: usize, gates: usize) -> RecurrentWeights {
+        RecurrentWeights {
+            input: Vector2D::zeros([features, gates * hidden]),
+            hidden: Vector2D::zeros([hidden, gates * hidden]),
+            biases: Vector2D::zeros([1, gates * hidden]),
+        }
+    }
+
+    // Adds the gradients of one timestep, given the gradient of the gate pre-activations and
+    // the already computed gradient of the hidden weights.
+    fn accumulate(&mut self, x: &Vector2D, d_gates: &Vector2D, hidden: Vector2D) {
+        self.input = &self.input + x.transpose().dot(d_gates);
+        self.hidden = &self.hidden + hidden;
+        self.biases = &self.biases + d_gates.mean(0) * d_gates.shape[0] as f64;
+    }
+
+    fn norm(&self) -> f64 {
+        [&self.input, &self.hidden, &self.biases].iter().flat_map(|v| v.values.iter()).map(|v| v * v).sum::<f64>().sqrt()
+    }
+
+    fn scale(&self, factor: f64) -> RecurrentWeights {
+        RecurrentWeights { input: &self.input * factor, hidden: &self.hidden * factor, biases: &self.biases * factor }
+    }
+}
+
+fn sigmoid_gradient(s: &Vector2D) -> Vector2D {
+    s * &(1. - s)
+}
+
+fn tanh_gradient(t: &Vector2D) -> Vector2D {
+    1. - &(t * t)
+}
+
+fn gate(v: &Vector2D, index: usize, hidden: usize) -> Vector2D {
+    v.select_columns(index * hidden, (index + 1) * hidden)
+}
+
+// One timestep of a recurrent layer. The state is the hidden state, followed by the cell
+// state for LSTMs.
+pub trait Cell: Send + Sync {
+    const NAME: &'static str;
+    const GATES: usize;
+    const STATES: usize;
+    type Cache: Send + Sync;
+
+    fn step(weights: &RecurrentWeights, x: &Vector2D, state: &[Vector2D]) -> (Vec<Vector2D>, Self::Cache);
+
+    // Takes the gradient with respect to the new state, adds the parameter gradients and
+    // returns the gradients with respect to the input and the previous state.
+    fn backward(weights: &RecurrentWeights, cache: &Self::Cache, d_state: &[Vector2D], gradients: &mut RecurrentWeights) -> (Vector2D, Vec<Vector2D>);
+
+    fn biases(hidden: usize) -> Vector2D {
+        Vector2D::zeros([1, Self::GATES * hidden])
+    }
+}
+
+// h = tanh(x Wx + h Wh + b)
+pub struct RnnCell;
+
+impl Cell for RnnCell {
+    const NAME: &'static str = "rnn";
+    const GATES: usize = 1;
+    const STATES: usize = 1;
+    // input, previous and new hidden state
+    type Cache = [Vector2D; 3];
+
+    fn step(weights: &RecurrentWeights, x: &Vector2D, state: &[Vector2D]) -> (Vec<Vector2D>, Self::Cache) {
+        let h: Vector2D = tanh(&(x.dot(&weights.input) + state[0].dot(&weights.hidden)).row_add(&weights.biases));
+        (vec![h.clone()], [x.clone(), state[0].clone(), h])
+    }
+
+    fn backward(weights: &RecurrentWeights, cache: &Self::Cache, d_state: &[Vector2D], gradients: &mut RecurrentWeights) -> (Vector2D, Vec<Vector2D>) {
+        let [x, h_previous, h] = cache;
+        let d_a: Vector2D = &d_state[0] * &tanh_gradient(h);
+        gradients.accumulate(x, &d_a, h_previous.transpose().dot(&d_a));
+        (d_a.dot(&weights.input.transpose()), vec![d_a.dot(&weights.hidden.transpose())])
+    }
+}
+
+// Gates in the order input, forget, candidate, output. The forget biases start at 1 so the
+// cell state is kept at the start of training.
+pub struct LstmCell;
+
+pub struct LstmCache {
+    x: Vector2D,
+    h_previous: Vector2D,
+    c_previous: Vector2D,
+    gates: [Vector2D; 4],
+    c_tanh: Vector2D,
+}
+
+impl Cell for LstmCell {
+    const NAME: &'static str = "lstm";
+    const GATES: usize = 4;
+    const STATES: usize = 2;
+    type Cache = LstmCache;
+
+    fn step(weights: &RecurrentWeights, x: &Vector2D, state: &[Vector2D]) -> (Vec<Vector2D>, Self::Cache) {
+        let hidden: usize = weights.hidden.shape[0];
+        let z: Vector2D = (x.dot(&weights.input) + state[0].dot(&weights.hidden)).row_add(&weights.biases);
+        let i: Vector2D = sigmoid(&gate(&z, 0, hidden));
+        let f: Vector2D = sigmoid(&gate(&z, 1, hidden));
+        let g: Vector2D = tanh(&gate(&z, 2, hidden));
+        let o: Vector2D = sigmoid(&gate(&z, 3, hidden));
+        let c: Vector2D = &f * &state[1] + &i * &g;
+        let c_tanh: Vector2D = tanh(&c);
+        let h: Vector2D = &o * &c_tanh;
+        let cache: LstmCache = LstmCache { x: x.clone(), h_previous: state[0].clone(), c_previous: state[1].clone(), gates: [i, f, g, o], c_tanh };
+        (vec![h, c], cache)
+    }
+
+    fn backward(weights: &RecurrentWeights, cache: &Self::Cache, d_state: &[Vector2D], gradients: &mut RecurrentWeights) -> (Vector2D, Vec<Vector2D>) {
+        let [i, f, g, o] = &cache.gates;
+        let d_h: &Vector2D = &d_state[0];
+        let d_c: Vector2D = &d_state[1] + &(d_h * o) * &tanh_gradient(&cache.c_tanh);
+        let d_gates: Vector2D = Vector2D::concat_columns(&[
+            &(&d_c * g) * &sigmoid_gradient(i),
+            &(&d_c * &cache.c_previous) * &sigmoid_gradient(f),
+            &(&d_c * i) * &tanh_gradient(g),
+            &(d_h * &cache.c_tanh) * &sigmoid_gradient(o),
+        ]);
+        gradients.accumulate(&cache.x, &d_gates, cache.h_previous.transpose().dot(&d_gates));
+        let d_h_previous: Vector2D = d_gates.dot(&weights.hidden.transpose());
+        (d_gates.dot(&weights.input.transpose()), vec![d_h_previous, &d_c * f])
+    }
+
+    fn biases(hidden: usize) -> Vector2D {
+        Vector2D::new((0..4 * hidden).map(|idx| if idx / hidden == 1 { 1. } else { 0. }).collect(), [1, 4 * hidden])
+    }
+}
+
+// Gates in the order update, reset, candidate. The reset gate is applied to the previous
+// hidden state before it is multiplied with the candidate weights.
+pub struct GruCell;
+
+pub struct GruCache {
+    x: Vector2D,
+    h_previous: Vector2D,
+    z: Vector2D,
+    r: Vector2D,
+    n: Vector2D,
+}
+
+impl Cell for GruCell {
+    const NAME: &'static str = "gru";
+    const GATES: usize = 3;
+    const STATES: usize = 1;
+    type Cache = GruCache;
+
+    fn step(weights: &RecurrentWeights, x: &Vector2D, state: &[Vector2D]) -> (Vec<Vector2D>, Self::Cache) {
+        let hidden: usize = weights.hidden.shape[0];
+        let h: &Vector2D = &state[0];
+        let x_w: Vector2D = x.dot(&weights.input).row_add(&weights.biases);
+        let h_w: Vector2D = h.dot(&weights.hidden.select_columns(0, 2 * hidden));
+        let z: Vector2D = sigmoid(&(gate(&x_w, 0, hidden) + gate(&h_w, 0, hidden)));
+        let r: Vector2D = sigmoid(&(gate(&x_w, 1, hidden) + gate(&h_w, 1, hidden)));
+        let n: Vector2D = tanh(&(gate(&x_w, 2, hidden) + (&r * h).dot(&gate(&weights.hidden, 2, hidden))));
+        let h_new: Vector2D = &n + &z * &(h - &n);
+        (vec![h_new], GruCache { x: x.clone(), h_previous: h.clone(), z, r, n })
+    }
+
+    fn backward(weights: &RecurrentWeights, cache: &Self::Cache, d_state: &[Vector2D], gradients: &mut RecurrentWeights) -> (Vector2D, Vec<Vector2D>) {
+        let hidden: usize = weights.hidden.shape[0];
+        let GruCache { x, h_previous, z, r, n } = cache;
+        let d_h: &Vector2D = &d_state[0];
+        let d_n: Vector2D = &(d_h * &(1. - z)) * &tanh_gradient(n);
+        let d_rh: Vector2D = d_n.dot(&gate(&weights.hidden, 2, hidden).transpose());
+        let d_z: Vector2D = &(d_h * &(h_previous - n)) * &sigmoid_gradient(z);
+        let d_r: Vector2D = &(&d_rh * h_previous) * &sigmoid_gradient(r);
+
+        let d_zr: Vector2D = Vector2D::concat_columns(&[d_z, d_r]);
+        let d_gates: Vector2D = Vector2D::concat_columns(&[d_zr.clone(), d_n.clone()]);
+        let hidden_gradient: Vector2D = Vector2D::concat_columns(&[h_previous.transpose().dot(&d_zr), (r * h_previous).transpose().dot(&d_n)]);
+        gradients.accumulate(x, &d_gates, hidden_gradient);
+
+        let d_h_previous: Vector2D = d_h * z + &d_rh * r + d_zr.dot(&weights.hidden.select_columns(0, 2 * hidden).transpose());
+        (d_gates.dot(&weights.input.transpose()), vec![d_h_previous])
+    }
+}
+
+pub struct Recurrent<C: Cell> {
+    pub features: usize,
+    pub hidden: usize,
+    pub config: RecurrentConfig,
+    pub weights: RecurrentWeights,
+    gradients: RecurrentWeights,
+    caches: Vec<C::Cache>,
+    cell: PhantomData<C>,
+}
+
+pub type Rnn = Recurrent<RnnCell>;
+pub type Lstm = Recurrent<LstmCell>;
+pub type Gru = Recurrent<GruCell>;
+
+impl<C: Cell> Recurrent<C> {
+    pub fn new(features: usize, hidden: usize) -> Recurrent<C> {
+        Recurrent::with_config(features, hidden, RecurrentConfig::default())
+    }
+
+    pub fn with_config(features: usize, hidden: usize, config: RecurrentConfig) -> Recurrent<C> {
+        if features == 0 || hidden == 0 {
+            panic!("A {} layer needs at least one feature and one hidden unit.", C::NAME);
+        }
+        if config.truncation == Some(0) || config.clip_norm.is_some_and(|norm| norm <= 0.) {
+            panic!("Invalid {:?}: truncation and clip_norm have to be positive.", config);
+        }
+        let mut layer: Recurrent<C> = Recurrent {
+            features, hidden, config,
+            weights: RecurrentWeights::default(),
+            gradients: RecurrentWeights::zeros(features, hidden, C::GATES),
+            caches: vec![],
+            cell: PhantomData,
+        };
+        layer.initialize(&mut thread_rng());
+        layer
+    }
+
+    // The number of values in every output row for sequences with this many timesteps.
+    pub fn output_size(&self, timesteps: usize) -> usize {
+        if self.config.return_sequences { timesteps * self.hidden } else { self.hidden }
+    }
+
+    fn timesteps(&self, input: &Vector2D) -> usize {
+        if input.shape[1] == 0 || !input.shape[1].is_multiple_of(self.features) {
+            panic!("{} expects rows of timesteps * {} features but got {} values.", C::NAME, self.features, input.shape[1]);
+        }
+        input.shape[1] / self.features
+    }
+
+    // Returns the output and the caches of every timestep.
+    fn run(&self, input: &Vector2D) -> (Vector2D, Vec<C::Cache>) {
+        let timesteps: usize = self.timesteps(input);
+        let mut state: Vec<Vector2D> = vec![Vector2D::zeros([input.shape[0], self.hidden]); C::STATES];
+        let mut states: Vec<Vector2D> = vec![];
+        let mut caches: Vec<C::Cache> = vec![];
+        for t in 0..timesteps {
+            let x: Vector2D = input.select_columns(t * self.features, (t + 1) * self.features);
+            let cache: C::Cache;
+            (state, cache) = C::step(&self.weights, &x, &state);
+            states.push(state[0].clone());
+            caches.push(cache);
+        }
+        let output: Vector2D = if self.config.return_sequences {
+            Vector2D::concat_columns(&states)
+        } else {
+            states.pop().unwrap()
+        };
+        (output, caches)
+    }
+}
+
+impl<C: Cell> Layer for Recurrent<C> {
+    fn name(&self) -> &'static str {
+        C::NAME
+    }
+
+    fn output_size_for(&self, input_size: usize) -> Option<usize> {
+        if input_size == 0 || !input_size.is_multiple_of(self.features) {
+            return None;
+        }
+        Some(self.output_size(input_size / self.features))
+    }
+
+    fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+        let (output, caches) = self.run(input);
+        self.caches = caches;
+        output
+    }
+
+    fn predict(&self, input: &Vector2D) -> Vector2D {
+        self.run(input).0
+    }
+
+    fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+        let samples: usize = gradient.shape[0];
+        let timesteps: usize = self.caches.len();
+        let zeros: Vector2D = Vector2D::zeros([samples, self.hidden]);
+        let mut gradients: RecurrentWeights = RecurrentWeights::zeros(self.features, self.hidden, C::GATES);
+        let mut d_state: Vec<Vector2D> = vec![zeros.clone(); C::STATES];
+        let mut d_inputs: Vec<Vector2D> = vec![];
+
+        for t in (0..timesteps).rev() {
+            if self.config.return_sequences {
+                d_state[0] = &d_state[0] + gradient.select_columns(t * self.hidden, (t + 1) * self.hidden);
+            } else if t == timesteps - 1 {
+                d_state[0] = &d_state[0] + gradient;
+            }
+            let (d_input, d_previous) = C::backward(&self.weights, &self.caches[t], &d_state, &mut gradients);
+            d_inputs.push(d_input);
+            d_state = match self.config.truncation {
+                Some(steps) if t % steps == 0 => vec![zeros.clone(); C::STATES],
+                _ => d_previous,
+            };
+        }
+        d_inputs.reverse();
+
+        self.gradients = gradients.scale(1. / samples as f64);
+        if let Some(max_norm) = self.config.clip_norm {
+            let norm: f64 = self.gradients.norm();
+            if norm > max_norm {
+                self.gradients = self.gradients.scale(max_norm / norm);
+            }
+        }
+        Vector2D::concat_columns(&d_inputs)
+    }
+
+    // Xavier uniform input weights and orthogonal hidden weights, separately for every gate.
+    fn initialize(&mut self, rng: &mut dyn RngCore) {
+        let input: Vec<Vector2D> = (0..C::GATES).map(|_| Initializer::XavierUniform.initialize([self.features, self.hidden], rng)).collect();
+        let hidden: Vec<Vector2D> = (0..C::GATES).map(|_| Initializer::Orthogonal { gain: 1. }.initialize([self.hidden, self.hidden], rng)).collect();
+        self.weights = RecurrentWeights {
+            input: Vector2D::concat_columns(&input),
+            hidden: Vector2D::concat_columns(&hidden),
+            biases: C::biases(self.hidden),
+        };
+    }
+
+    fn parameters(&self) -> Vec<&Vector2D> {
+        vec![&self.weights.input, &self.weights.hidden, &self.weights.biases]
+    }
+
+    fn gradients(&self) -> Vec<&Vector2D> {
+        vec![&self.gradients.input, &self.gradients.hidden, &self.gradients.biases]
+    }
+
+    fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+        let RecurrentWeights { input, hidden, biases } = &mut self.weights;
+        (vec![input, hidden, biases], vec![&self.gradients.input, &self.gradients.hidden, &self.gradients.biases])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::gradcheck::gradcheck_layer;
+
+    // three sequences of four timesteps with two features
+    fn sequences() -> Vector2D {
+        Vector2D::new((0..24).map(|i| ((i * 7 % 11) as f64 - 5.) / 5.).collect(), [3, 8])
+    }
+
+    fn seeded<C: Cell>(config: RecurrentConfig) -> Recurrent<C> {
+        let mut layer: Recurrent<C> = Recurrent::with_config(2, 3, config);
+        layer.initialize(&mut StdRng::seed_from_u64(3));
+        layer.weights.biases = layer.weights.biases.map(|b| b + 0.1);
+        layer
+    }
+
+    fn check<C: Cell>(config: RecurrentConfig) {
+        let mut layer: Recurrent<C> = seeded(config);
+        // the long chains of products lose some precision, bugs show errors far above 1e-5
+        let report = gradcheck_layer(&mut layer, &sequences(), true, 1e-5);
+        assert!(report.passed(1e-5), "{} {:?}\n{}", C::NAME, config, report);
+    }
+
+    #[test]
+    fn test_known_rnn_states() {
+        let mut rnn: Rnn = Rnn::with_config(1, 1, RecurrentConfig { return_sequences: true, ..RecurrentConfig::default() });
+        rnn.weights.input = Vector2D::new(vec![1.], [1, 1]);
+        rnn.weights.hidden = Vector2D::new(vec![0.5], [1, 1]);
+        let output: Vector2D = rnn.predict(&Vector2D::new(vec![1., 0.], [1, 2]));
+        assert!(output.values == vec![1f64.tanh(), (0.5 * 1f64.tanh()).tanh()]);
+
+        rnn.config.return_sequences = false;
+        assert!(rnn.predict(&Vector2D::new(vec![1., 0.], [1, 2])).values == vec![(0.5 * 1f64.tanh()).tanh()]);
+    }
+
+    #[test]
+    fn test_output_shapes() {
+        let sequences: Vector2D = sequences();
+        assert!(Lstm::new(2, 5).predict(&sequences).shape == [3, 5]);
+        let gru: Gru = Gru::with_config(2, 5, RecurrentConfig { return_sequences: true, ..RecurrentConfig::default() });
+        assert!(gru.output_size(4) == 20);
+        assert!(gru.predict(&sequences).shape == [3, 20]);
+    }
+
+    #[test]
+    #[should_panic(expected = "lstm expects rows of timesteps * 3 features but got 8 values")]
+    fn test_sequence_length_mismatch() {
+        Lstm::new(3, 2).predict(&sequences());
+    }
+
+    #[test]
+    fn test_lstm_forget_bias() {
+        let lstm: Lstm = Lstm::new(2, 2);
+        assert!(lstm.weights.biases.values == vec![0., 0., 1., 1., 0., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn test_gradients() {
+        for return_sequences in [false, true] {
+            let config: RecurrentConfig = RecurrentConfig { return_sequences, ..RecurrentConfig::default() };
+            check::<RnnCell>(config);
+            check::<LstmCell>(config);
+            check::<GruCell>(config);
+        }
+    }
+
+    #[test]
+    fn test_truncated_backpropagation() {
+        let config: RecurrentConfig = RecurrentConfig { truncation: Some(2), ..RecurrentConfig::default() };
+        let mut lstm: Lstm = seeded(config);
+        lstm.forward(&sequences(), true);
+        let gradient: Vector2D = lstm.backward(&Vector2D::new(vec![1.; 9], [3, 3]));
+        // only the last chunk of two timesteps gets a gradient from the last state
+        assert!(gradient.select_columns(0, 4).values.iter().all(|v| *v == 0.));
+        assert!(gradient.select_columns(4, 8).values.iter().all(|v| *v != 0.));
+    }
+
+    #[test]
+    fn test_gradient_clipping() {
+        let mut gru: Gru = seeded(RecurrentConfig::default());
+        gru.forward(&sequences(), true);
+        gru.backward(&Vector2D::new(vec![1.; 9], [3, 3]));
+        let unclipped: RecurrentWeights = gru.gradients.clone();
+
+        let mut clipped: Gru = seeded(RecurrentConfig { clip_norm: Some(0.01), ..RecurrentConfig::default() });
+        clipped.forward(&sequences(), true);
+        clipped.backward(&Vector2D::new(vec![1.; 9], [3, 3]));
+        assert!(unclipped.norm() > 0.01);
+        assert!((clipped.gradients.norm() - 0.01).abs() < 1e-12);
+        let expected: RecurrentWeights = unclipped.scale(0.01 / unclipped.norm());
+        for (a, b) in clipped.gradients.input.values.iter().zip(expected.input.values.iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+}
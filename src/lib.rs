@@ -0,0 +1,24 @@
+pub mod gaussian;
+pub mod data;
+pub mod loss;
+pub mod activation;
+pub mod vectors;
+pub mod neuralnetwork;
+pub mod optimizer;
+pub mod scheduler;
+pub mod initializer;
+pub mod serialization;
+pub mod checkpoint;
+pub mod early_stopping;
+pub mod history;
+pub mod callbacks;
+pub mod metrics;
+pub mod regularizer;
+pub mod layers;
+pub mod gradcheck;
+pub mod autograd;
+pub mod graph;
+pub mod clipping;
+pub mod guard;
+pub mod builder;
+pub mod summary;
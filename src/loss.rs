@@ -12,6 +12,160 @@ pub fn cross_entropy_derivative(h: Vector2D, y: &Vector2D) -> Vector2D {
     -(y / &h) + (1. - y) / (1. - h)
 }
 
+pub fn mean_squared_error(h: &Vector2D, y: &Vector2D) -> Vector2D {
+    let difference: Vector2D = h - y;
+    let value: f64 = difference.values.iter().map(|d| d * d).sum::<f64>() / y.values.len() as f64;
+    Vector2D::new(vec![value], [1, 1])
+}
+
+pub fn mean_squared_error_derivative(h: Vector2D, y: &Vector2D) -> Vector2D {
+    2. * (h - y) / y.shape[1] as f64
+}
+
+// The derivatives are taken with respect to the prediction of a single sample, the network
+// averages the resulting gradients over the batch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Loss {
+    CrossEntropy,
+    MeanSquaredError,
+}
+
+impl Loss {
+    pub fn loss(&self, h: &Vector2D, y: &Vector2D) -> f64 {
+        match self {
+            Loss::CrossEntropy => {
+                let mut value: f64 = 0.;
+                for (p, t) in h.values.iter().zip(y.values.iter()) {
+                    value += t * p.ln() + (1. - t) * (1. - p).ln();
+                }
+                -value / y.values.len() as f64
+            },
+            Loss::MeanSquaredError => *mean_squared_error(h, y).get_value(0),
+        }
+    }
+
+    pub fn derivative(&self, h: Vector2D, y: &Vector2D) -> Vector2D {
+        match self {
+            // loss() averages over the outputs as well, so each sample's loss is divided by their count
+            Loss::CrossEntropy => cross_entropy_derivative(h, y) / y.shape[1] as f64,
+            Loss::MeanSquaredError => mean_squared_error_derivative(h, y),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Loss::CrossEntropy => "cross_entropy",
+            Loss::MeanSquaredError => "mean_squared_error",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Loss> {
+        [Loss::CrossEntropy, Loss::MeanSquaredError]
+            .into_iter()
+            .find(|loss| loss.name() == name)
+    }
+}
+
+
+// Regression metrics to judge a trained network. Every column of a multi-output target is
+// scored on its own, the aggregate is the unweighted mean of the column scores.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegressionMetric {
+    R2,
+    MeanSquaredError,
+    RootMeanSquaredError,
+    MeanAbsoluteError,
+    MeanAbsolutePercentageError,
+    ExplainedVariance,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegressionScore {
+    pub columns: Vec<f64>,
+    pub average: f64,
+}
+
+fn column_values(v: &Vector2D, column: usize) -> Vec<f64> {
+    (0..v.shape[0]).map(|row| v[(row, column)]).collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn variance(values: &[f64]) -> f64 {
+    let m: f64 = mean(values);
+    values.iter().map(|v| (v - m) * (v - m)).sum::<f64>() / values.len() as f64
+}
+
+// 1 - residual / total, where a constant target scores 1 if it is predicted exactly and 0 otherwise.
+fn explained_fraction(residual: f64, total: f64) -> f64 {
+    if total != 0. {
+        1. - residual / total
+    } else if residual == 0. {
+        1.
+    } else {
+        0.
+    }
+}
+
+impl RegressionMetric {
+    pub const ALL: [RegressionMetric; 6] = [
+        RegressionMetric::R2,
+        RegressionMetric::MeanSquaredError,
+        RegressionMetric::RootMeanSquaredError,
+        RegressionMetric::MeanAbsoluteError,
+        RegressionMetric::MeanAbsolutePercentageError,
+        RegressionMetric::ExplainedVariance,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegressionMetric::R2 => "r2",
+            RegressionMetric::MeanSquaredError => "mse",
+            RegressionMetric::RootMeanSquaredError => "rmse",
+            RegressionMetric::MeanAbsoluteError => "mae",
+            RegressionMetric::MeanAbsolutePercentageError => "mape",
+            RegressionMetric::ExplainedVariance => "explained_variance",
+        }
+    }
+
+    fn column_score(&self, h: &[f64], y: &[f64]) -> f64 {
+        let errors: Vec<f64> = h.iter().zip(y.iter()).map(|(p, t)| t - p).collect();
+        let squared: f64 = mean(&errors.iter().map(|e| e * e).collect::<Vec<f64>>());
+        match self {
+            RegressionMetric::R2 => explained_fraction(squared, variance(y)),
+            RegressionMetric::MeanSquaredError => squared,
+            RegressionMetric::RootMeanSquaredError => squared.sqrt(),
+            RegressionMetric::MeanAbsoluteError => mean(&errors.iter().map(|e| e.abs()).collect::<Vec<f64>>()),
+            // targets of zero would divide by zero, so they are clamped to machine epsilon
+            RegressionMetric::MeanAbsolutePercentageError => mean(
+                &errors.iter().zip(y.iter()).map(|(e, t)| e.abs() / t.abs().max(f64::EPSILON)).collect::<Vec<f64>>()
+            ),
+            RegressionMetric::ExplainedVariance => explained_fraction(variance(&errors), variance(y)),
+        }
+    }
+
+    pub fn score(&self, h: &Vector2D, y: &Vector2D) -> RegressionScore {
+        if h.shape != y.shape {
+            panic!("Predictions of shape {:?} do not match targets of shape {:?}.", h.shape, y.shape);
+        }
+        if y.shape[0] == 0 {
+            panic!("Cannot score an empty set of predictions.");
+        }
+        let columns: Vec<f64> = (0..y.shape[1])
+            .map(|column| self.column_score(&column_values(h, column), &column_values(y, column)))
+            .collect();
+        let average: f64 = mean(&columns);
+        RegressionScore { columns, average }
+    }
+}
+
+// All regression metrics, e.g. for printing after training.
+pub fn regression_report(h: &Vector2D, y: &Vector2D) -> Vec<(&'static str, RegressionScore)> {
+    RegressionMetric::ALL.iter().map(|metric| (metric.name(), metric.score(h, y))).collect()
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -53,8 +207,8 @@ mod tests {
         );
         let r: Vector2D = cross_entropy_loss(&h, &y);
         assert!(r.shape == [1, 1]);
-        assert!(r.values[0] <= 0.9562 as f64);
-        assert!(r.values[0] >= 0.9561 as f64);
+        assert!(r.values[0] <= 0.9562);
+        assert!(r.values[0] >= 0.9561);
     }
 
     #[test]
@@ -82,4 +236,61 @@ mod tests {
         assert!(d[1] <= 5.001);
         assert!(d[1] >= 4.999);
     }
+
+    #[test]
+    fn test_loss_enum_matches_functions() {
+        let h: Vector2D = Vector2D::new(vec![0.1, 0.25, 0.8, 0.9], [4, 1]);
+        let y: Vector2D = Vector2D::new(vec![0., 1., 0., 1.], [4, 1]);
+        let expected: f64 = *cross_entropy_loss(&h, &y).get_value(0);
+        assert!((Loss::CrossEntropy.loss(&h, &y) - expected).abs() < 1e-12);
+        assert!(Loss::CrossEntropy.derivative(h.clone(), &y).values == cross_entropy_derivative(h.clone(), &y).values);
+    }
+
+    #[test]
+    fn test_mean_squared_error() {
+        let h: Vector2D = Vector2D::new(vec![1., 2., 3., 4.], [2, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 1., 1., 1.], [2, 2]);
+        assert!(Loss::MeanSquaredError.loss(&h, &y) == 3.5);
+        assert!(mean_squared_error_derivative(h, &y).values == vec![0., 1., 2., 3.]);
+    }
+
+    #[test]
+    fn test_loss_names() {
+        for loss in [Loss::CrossEntropy, Loss::MeanSquaredError] {
+            assert!(Loss::from_name(loss.name()) == Some(loss));
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn test_regression_metrics() {
+        let h: Vector2D = Vector2D::new(vec![2.5, 0., 2., 8.], [4, 1]);
+        let y: Vector2D = Vector2D::new(vec![3., -0.5, 2., 7.], [4, 1]);
+        assert!(close(RegressionMetric::MeanSquaredError.score(&h, &y).average, 0.375));
+        assert!(close(RegressionMetric::RootMeanSquaredError.score(&h, &y).average, 0.375_f64.sqrt()));
+        assert!(close(RegressionMetric::MeanAbsoluteError.score(&h, &y).average, 0.5));
+        assert!(close(RegressionMetric::R2.score(&h, &y).average, 1. - 0.375 / 7.296875));
+        assert!(close(RegressionMetric::ExplainedVariance.score(&h, &y).average, 1. - 0.3125 / 7.296875));
+        let mape: f64 = (0.5 / 3. + 0.5 / 0.5 + 0. + 1. / 7.) / 4.;
+        assert!(close(RegressionMetric::MeanAbsolutePercentageError.score(&h, &y).average, mape));
+    }
+
+    #[test]
+    fn test_regression_metrics_multi_output() {
+        let h: Vector2D = Vector2D::new(vec![1., 10., 2., 20., 3., 30.], [3, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 10., 2., 20., 3., 33.], [3, 2]);
+        let score: RegressionScore = RegressionMetric::MeanAbsoluteError.score(&h, &y);
+        assert!(score.columns == vec![0., 1.]);
+        assert!(score.average == 0.5);
+        assert!(RegressionMetric::R2.score(&h, &y).columns[0] == 1.);
+
+        // a constant target only scores if it is predicted exactly
+        let constant: Vector2D = Vector2D::new(vec![2., 2.], [2, 1]);
+        assert!(RegressionMetric::R2.score(&constant, &constant).average == 1.);
+        assert!(RegressionMetric::R2.score(&Vector2D::new(vec![1., 2.], [2, 1]), &constant).average == 0.);
+        assert!(regression_report(&h, &y).len() == 6);
+    }
 }
@@ -1,19 +1,13 @@
-mod gaussian;
-mod data;
-mod loss;
-mod activation;
-mod vectors;
-mod neuralnetwork;
-
-use data::XnorDataset;
-use neuralnetwork::NeuralNetwork;
+use rust_network::data::XnorDataset;
+use rust_network::neuralnetwork::NeuralNetwork;
+use rust_network::metrics::{ConfusionMatrix, roc_auc};
 
 
 fn main() {  
     let data: XnorDataset = XnorDataset::new(200);
     data.print(5);  // print some samples
     let mut nn = NeuralNetwork::new(vec![2, 3, 1]);
-    nn.training(data.x, data.y, 1000, false);  // set verbose true to see training loss
+    nn.training(data.x.clone(), data.y.clone(), None, 1000, false);  // set verbose true to see training loss
 
     println!("\n---------------- Post-training parameters ----------------");
     for n in 0..nn.parameters.weights.len() {
@@ -23,4 +17,14 @@ fn main() {
         println!("\nBias[{}]: ", n); 
         nn.parameters.biases[n].print();
     }
+
+    println!("\n---------------- Post-training metrics ----------------");
+    let predictions = nn.predict(&data.x);
+    let matrix = ConfusionMatrix::new(&predictions, &data.y);
+    println!("\n{}", matrix);
+    println!("{}", matrix.report());
+    match roc_auc(&predictions, &data.y) {
+        Some(auc) => println!("ROC AUC: {:.3}", auc),
+        None => println!("ROC AUC: undefined, the labels contain only one class"),
+    }
 }
\ No newline at end of file
@@ -0,0 +1,398 @@
+// This file contains classification metrics computed from predictions and labels.
+// Both can either be a single column (probabilities or 0/1 labels, thresholded at 0.5) or
+// one column per class (probabilities or one-hot labels, the largest entry is the class).
+use std::fmt;
+use crate::vectors::models::Vector2D;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Average {
+    // scores of class 1, only valid for two classes
+    Binary,
+    // unweighted mean over the classes
+    Macro,
+    // computed from the summed counts of all classes
+    Micro,
+    // mean over the classes weighted by their support
+    Weighted,
+}
+
+fn to_classes(v: &Vector2D) -> Vec<usize> {
+    if v.shape[1] == 1 {
+        return v.values.iter().map(|p| if *p >= 0.5 { 1 } else { 0 }).collect();
+    }
+    (0..v.shape[0]).map(|row| {
+        let values: &[f64] = &v.values[row * v.shape[1]..(row + 1) * v.shape[1]];
+        let mut best: usize = 0;
+        for (idx, value) in values.iter().enumerate() {
+            if *value > values[best] {
+                best = idx;
+            }
+        }
+        best
+    }).collect()
+}
+
+fn check_rows(predictions: &Vector2D, labels: &Vector2D) {
+    if predictions.shape[0] != labels.shape[0] {
+        panic!("Got {} predictions but {} labels.", predictions.shape[0], labels.shape[0]);
+    }
+}
+
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator == 0. { 0. } else { numerator / denominator }
+}
+
+pub fn accuracy(predictions: &Vector2D, labels: &Vector2D) -> f64 {
+    check_rows(predictions, labels);
+    let correct: usize = to_classes(predictions).iter().zip(to_classes(labels).iter()).filter(|(p, y)| p == y).count();
+    correct as f64 / labels.shape[0] as f64
+}
+
+pub fn precision(predictions: &Vector2D, labels: &Vector2D, average: Average) -> f64 {
+    ConfusionMatrix::new(predictions, labels).precision(average)
+}
+
+pub fn recall(predictions: &Vector2D, labels: &Vector2D, average: Average) -> f64 {
+    ConfusionMatrix::new(predictions, labels).recall(average)
+}
+
+pub fn f1_score(predictions: &Vector2D, labels: &Vector2D, average: Average) -> f64 {
+    ConfusionMatrix::new(predictions, labels).f1_score(average)
+}
+
+// counts[true class][predicted class]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfusionMatrix {
+    pub counts: Vec<Vec<usize>>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(predictions: &Vector2D, labels: &Vector2D) -> ConfusionMatrix {
+        check_rows(predictions, labels);
+        let classes: usize = [predictions.shape[1], labels.shape[1], 2].into_iter().max().unwrap();
+        let mut counts: Vec<Vec<usize>> = vec![vec![0; classes]; classes];
+        for (p, y) in to_classes(predictions).into_iter().zip(to_classes(labels)) {
+            counts[y][p] += 1;
+        }
+        ConfusionMatrix { counts }
+    }
+
+    pub fn classes(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().flatten().sum()
+    }
+
+    pub fn true_positives(&self, class: usize) -> usize {
+        self.counts[class][class]
+    }
+
+    pub fn false_positives(&self, class: usize) -> usize {
+        self.counts.iter().map(|row| row[class]).sum::<usize>() - self.counts[class][class]
+    }
+
+    pub fn false_negatives(&self, class: usize) -> usize {
+        self.counts[class].iter().sum::<usize>() - self.counts[class][class]
+    }
+
+    pub fn support(&self, class: usize) -> usize {
+        self.counts[class].iter().sum()
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let correct: usize = (0..self.classes()).map(|c| self.true_positives(c)).sum();
+        ratio(correct as f64, self.total() as f64)
+    }
+
+    pub fn class_precision(&self, class: usize) -> f64 {
+        let tp: f64 = self.true_positives(class) as f64;
+        ratio(tp, tp + self.false_positives(class) as f64)
+    }
+
+    pub fn class_recall(&self, class: usize) -> f64 {
+        let tp: f64 = self.true_positives(class) as f64;
+        ratio(tp, tp + self.false_negatives(class) as f64)
+    }
+
+    pub fn class_f1_score(&self, class: usize) -> f64 {
+        let (p, r) = (self.class_precision(class), self.class_recall(class));
+        ratio(2. * p * r, p + r)
+    }
+
+    fn average<F: Fn(usize) -> f64>(&self, average: Average, score: F, micro: f64) -> f64 {
+        match average {
+            Average::Binary => {
+                if self.classes() != 2 {
+                    panic!("Binary average needs two classes but there are {}, use Macro, Micro or Weighted instead.", self.classes());
+                }
+                score(1)
+            },
+            Average::Macro => (0..self.classes()).map(score).sum::<f64>() / self.classes() as f64,
+            Average::Micro => micro,
+            Average::Weighted => {
+                let weighted: f64 = (0..self.classes()).map(|c| score(c) * self.support(c) as f64).sum();
+                ratio(weighted, self.total() as f64)
+            },
+        }
+    }
+
+    // Micro averaged precision, recall and F1 all equal the accuracy for single-label data.
+    pub fn precision(&self, average: Average) -> f64 {
+        self.average(average, |c| self.class_precision(c), self.accuracy())
+    }
+
+    pub fn recall(&self, average: Average) -> f64 {
+        self.average(average, |c| self.class_recall(c), self.accuracy())
+    }
+
+    pub fn f1_score(&self, average: Average) -> f64 {
+        self.average(average, |c| self.class_f1_score(c), self.accuracy())
+    }
+
+    pub fn report(&self) -> ClassificationReport {
+        ClassificationReport { matrix: self.clone() }
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width: usize = self.counts.iter().flatten().map(|c| c.to_string().len()).max().unwrap_or(1).max(4);
+        write!(f, "{:>10}", "true\\pred")?;
+        for class in 0..self.classes() {
+            write!(f, " {:>width$}", class, width = width)?;
+        }
+        writeln!(f)?;
+        for (class, row) in self.counts.iter().enumerate() {
+            write!(f, "{:>10}", class)?;
+            for count in row {
+                write!(f, " {:>width$}", count, width = width)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// Per class precision, recall, F1 and support plus the averages, printed like
+// sklearn's classification_report.
+pub struct ClassificationReport {
+    pub matrix: ConfusionMatrix,
+}
+
+impl fmt::Display for ClassificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m: &ConfusionMatrix = &self.matrix;
+        writeln!(f, "{:>12} {:>9} {:>9} {:>9} {:>9}", "", "precision", "recall", "f1-score", "support")?;
+        writeln!(f)?;
+        for class in 0..m.classes() {
+            writeln!(
+                f, "{:>12} {:>9.2} {:>9.2} {:>9.2} {:>9}",
+                class, m.class_precision(class), m.class_recall(class), m.class_f1_score(class), m.support(class)
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>12} {:>9} {:>9} {:>9.2} {:>9}", "accuracy", "", "", m.accuracy(), m.total())?;
+        for (name, average) in [("macro avg", Average::Macro), ("weighted avg", Average::Weighted)] {
+            writeln!(
+                f, "{:>12} {:>9.2} {:>9.2} {:>9.2} {:>9}",
+                name, m.precision(average), m.recall(average), m.f1_score(average), m.total()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Scores of the positive class: the only column, or the second one of [p(0), p(1)] outputs.
+fn binary_scores(scores: &Vector2D, labels: &Vector2D) -> Vec<(f64, bool)> {
+    check_rows(scores, labels);
+    let column: usize = match scores.shape[1] {
+        1 => 0,
+        2 => 1,
+        n => panic!("Curves need binary scores with one or two columns, found {} columns.", n),
+    };
+    let positives: Vec<bool> = to_classes(labels).into_iter().map(|y| y == 1).collect();
+    let mut pairs: Vec<(f64, bool)> = (0..scores.shape[0]).map(|row| (scores[(row, column)], positives[row])).collect();
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+    pairs
+}
+
+// Cumulative (threshold, true positives, false positives) when lowering the threshold
+// through every distinct score.
+fn cumulative_counts(pairs: &[(f64, bool)]) -> Vec<(f64, usize, usize)> {
+    let mut counts: Vec<(f64, usize, usize)> = vec![];
+    let (mut tp, mut fp) = (0, 0);
+    for (idx, (score, positive)) in pairs.iter().enumerate() {
+        if *positive { tp += 1 } else { fp += 1 }
+        if idx + 1 == pairs.len() || pairs[idx + 1].0 != *score {
+            counts.push((*score, tp, fp));
+        }
+    }
+    counts
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RocCurve {
+    pub false_positive_rates: Vec<f64>,
+    pub true_positive_rates: Vec<f64>,
+    // decreasing, the first one is infinite and gives the point (0, 0)
+    pub thresholds: Vec<f64>,
+}
+
+impl RocCurve {
+    pub fn auc(&self) -> f64 {
+        let (x, y) = (&self.false_positive_rates, &self.true_positive_rates);
+        (1..x.len()).map(|i| (x[i] - x[i - 1]) * (y[i] + y[i - 1]) / 2.).sum()
+    }
+}
+
+// None if the labels contain only one class: without positives (or negatives) the true
+// (or false) positive rate is undefined.
+pub fn roc_curve(scores: &Vector2D, labels: &Vector2D) -> Option<RocCurve> {
+    let pairs: Vec<(f64, bool)> = binary_scores(scores, labels);
+    let positives: f64 = pairs.iter().filter(|(_, p)| *p).count() as f64;
+    let negatives: f64 = pairs.len() as f64 - positives;
+    if positives == 0. || negatives == 0. {
+        return None;
+    }
+
+    let mut curve: RocCurve = RocCurve { false_positive_rates: vec![0.], true_positive_rates: vec![0.], thresholds: vec![f64::INFINITY] };
+    for (threshold, tp, fp) in cumulative_counts(&pairs) {
+        curve.false_positive_rates.push(fp as f64 / negatives);
+        curve.true_positive_rates.push(tp as f64 / positives);
+        curve.thresholds.push(threshold);
+    }
+    Some(curve)
+}
+
+pub fn roc_auc(scores: &Vector2D, labels: &Vector2D) -> Option<f64> {
+    roc_curve(scores, labels).map(|curve| curve.auc())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrecisionRecallCurve {
+    pub precisions: Vec<f64>,
+    // increasing
+    pub recalls: Vec<f64>,
+    pub thresholds: Vec<f64>,
+}
+
+impl PrecisionRecallCurve {
+    // Sum of the precisions weighted by the increase in recall at each threshold.
+    pub fn average_precision(&self) -> f64 {
+        let mut previous_recall: f64 = 0.;
+        let mut value: f64 = 0.;
+        for (precision, recall) in self.precisions.iter().zip(self.recalls.iter()) {
+            value += (recall - previous_recall) * precision;
+            previous_recall = *recall;
+        }
+        value
+    }
+}
+
+pub fn precision_recall_curve(scores: &Vector2D, labels: &Vector2D) -> PrecisionRecallCurve {
+    let pairs: Vec<(f64, bool)> = binary_scores(scores, labels);
+    let positives: f64 = pairs.iter().filter(|(_, p)| *p).count() as f64;
+
+    let mut curve: PrecisionRecallCurve = PrecisionRecallCurve { precisions: vec![], recalls: vec![], thresholds: vec![] };
+    for (threshold, tp, fp) in cumulative_counts(&pairs) {
+        curve.precisions.push(tp as f64 / (tp + fp) as f64);
+        curve.recalls.push(ratio(tp as f64, positives));
+        curve.thresholds.push(threshold);
+    }
+    curve
+}
+
+pub fn average_precision(scores: &Vector2D, labels: &Vector2D) -> f64 {
+    precision_recall_curve(scores, labels).average_precision()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn test_binary_scores() {
+        let predictions: Vector2D = Vector2D::new(vec![0.9, 0.2, 0.6, 0.4, 0.8, 0.1], [6, 1]);
+        let labels: Vector2D = Vector2D::new(vec![1., 0., 0., 1., 1., 0.], [6, 1]);
+        let matrix: ConfusionMatrix = ConfusionMatrix::new(&predictions, &labels);
+        assert!(matrix.counts == vec![vec![2, 1], vec![1, 2]]);
+        assert!(close(accuracy(&predictions, &labels), 4. / 6.));
+        assert!(close(precision(&predictions, &labels, Average::Binary), 2. / 3.));
+        assert!(close(recall(&predictions, &labels, Average::Binary), 2. / 3.));
+        assert!(close(f1_score(&predictions, &labels, Average::Binary), 2. / 3.));
+    }
+
+    #[test]
+    fn test_multiclass_averages() {
+        // true classes 0, 0, 1, 2, 2, 2 predicted as 0, 1, 1, 2, 2, 0
+        let labels: Vector2D = Vector2D::new(vec![
+            1., 0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0., 0., 1., 0., 0., 1.,
+        ], [6, 3]);
+        let predictions: Vector2D = Vector2D::new(vec![
+            0.8, 0.1, 0.1, 0.3, 0.6, 0.1, 0.2, 0.7, 0.1, 0.1, 0.1, 0.8, 0.2, 0.3, 0.5, 0.5, 0.2, 0.3,
+        ], [6, 3]);
+        let matrix: ConfusionMatrix = ConfusionMatrix::new(&predictions, &labels);
+        assert!(matrix.counts == vec![vec![1, 1, 0], vec![0, 1, 0], vec![1, 0, 2]]);
+
+        // per class precision 1/2, 1/2, 1 and recall 1/2, 1, 2/3
+        assert!(close(matrix.precision(Average::Macro), 2. / 3.));
+        assert!(close(matrix.recall(Average::Macro), (0.5 + 1. + 2. / 3.) / 3.));
+        assert!(close(matrix.precision(Average::Micro), 4. / 6.));
+        assert!(close(matrix.recall(Average::Weighted), 4. / 6.));
+        assert!(close(matrix.precision(Average::Weighted), (2. * 0.5 + 0.5 + 3.) / 6.));
+        assert!(close(matrix.class_f1_score(2), 0.8));
+    }
+
+    #[test]
+    #[should_panic(expected = "Binary average needs two classes")]
+    fn test_binary_average_needs_two_classes() {
+        let labels: Vector2D = Vector2D::new(vec![1., 0., 0., 0., 0., 1.], [2, 3]);
+        precision(&labels, &labels, Average::Binary);
+    }
+
+    #[test]
+    fn test_roc_curve() {
+        let scores: Vector2D = Vector2D::new(vec![0.1, 0.4, 0.35, 0.8], [4, 1]);
+        let labels: Vector2D = Vector2D::new(vec![0., 0., 1., 1.], [4, 1]);
+        let curve: RocCurve = roc_curve(&scores, &labels).unwrap();
+        assert!(curve.false_positive_rates == vec![0., 0., 0.5, 0.5, 1.]);
+        assert!(curve.true_positive_rates == vec![0., 0.5, 0.5, 1., 1.]);
+        assert!(close(curve.auc(), 0.75));
+
+        let perfect: Vector2D = Vector2D::new(vec![0.1, 0.2, 0.7, 0.9], [4, 1]);
+        assert!(close(roc_auc(&perfect, &labels).unwrap(), 1.));
+    }
+
+    #[test]
+    fn test_roc_curve_needs_both_classes() {
+        let scores: Vector2D = Vector2D::new(vec![0.1, 0.4, 0.8], [3, 1]);
+        assert!(roc_curve(&scores, &Vector2D::new(vec![1., 1., 1.], [3, 1])).is_none());
+        assert!(roc_auc(&scores, &Vector2D::new(vec![0., 0., 0.], [3, 1])).is_none());
+    }
+
+    #[test]
+    fn test_precision_recall_curve() {
+        let scores: Vector2D = Vector2D::new(vec![0.1, 0.4, 0.35, 0.8], [4, 1]);
+        let labels: Vector2D = Vector2D::new(vec![0., 0., 1., 1.], [4, 1]);
+        let curve: PrecisionRecallCurve = precision_recall_curve(&scores, &labels);
+        assert!(curve.recalls == vec![0.5, 0.5, 1., 1.]);
+        assert!(close(curve.precisions[2], 2. / 3.));
+        assert!(close(average_precision(&scores, &labels), 0.5 + 0.5 * 2. / 3.));
+    }
+
+    #[test]
+    fn test_report() {
+        let predictions: Vector2D = Vector2D::new(vec![0.9, 0.2, 0.6, 0.4], [4, 1]);
+        let labels: Vector2D = Vector2D::new(vec![1., 0., 0., 1.], [4, 1]);
+        let report: String = ConfusionMatrix::new(&predictions, &labels).report().to_string();
+        assert!(report.contains("precision"));
+        assert!(report.contains("    accuracy                          0.50         4"));
+    }
+}
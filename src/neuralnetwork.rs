@@ -1,30 +1,30 @@
 // This file contains all neural network implementation related functions.
 
-use crate::{vectors::models::Vector2D, gaussian::Gaussian, activation::{self, sigmoid_derivative}, loss};
+use std::{fmt, time::Instant};
+use rand::{Rng, SeedableRng, thread_rng, rngs::StdRng, seq::SliceRandom};
+use crate::{vectors::models::Vector2D, activation::Activation, loss::Loss, optimizer::{Optimizer, Sgd}, scheduler::LrScheduler, initializer::Initializer, checkpoint::Checkpointing, early_stopping::EarlyStopping, history::{EpochRecord, History}, regularizer::Regularizer, metrics, layers::Layer, callbacks::{BatchLogs, Callback, Control}, clipping::{GradientClipping, global_norm}, guard::{NonFiniteAction, NonFiniteError, NonFiniteGuard, Snapshot, first_non_finite}, serialization::ModelError, summary::{LayerSummary, Summary}};
 
 
-fn initialize_weights(shape: &Vec<usize>) -> Vec<Vector2D> {
-    let mut g: Gaussian = Gaussian::new(0., 1.);
+pub(crate) fn initialize_weights<R: Rng>(shape: &[usize], initializer: &Initializer, rng: &mut R) -> Vec<Vector2D> {
     let mut weights: Vec<Vector2D> = vec![];
     for idx in 0..shape.len() - 1 {
         let weight_shape: [usize; 2] = [shape[idx], shape[idx+1]];
-        let weight_values: Vec<f64> = g.samples(shape[idx] * shape[idx+1]);
-        let mut weight: Vector2D = Vector2D::new(weight_values, weight_shape);
-        weight = 2. * weight - 1.;
-        weights.push(weight);
+        weights.push(initializer.initialize(weight_shape, rng));
     }
-    return weights;
+    weights
 }
 
-fn initialize_biases(shape: &Vec<usize>) -> Vec<Vector2D> {
+pub(crate) fn initialize_biases<R: Rng>(shape: &[usize], initializer: &Initializer, rng: &mut R) -> Vec<Vector2D> {
     let mut biases: Vec<Vector2D> = vec![];
     for idx in 0..shape.len() - 1 {
         let bias_shape: [usize; 2] = [1, shape[idx+1]];
-        let bias_values: Vec<f64> = vec![0.; shape[idx+1]];
-        let bias: Vector2D = Vector2D::new(bias_values, bias_shape);
-        biases.push(bias);
+        biases.push(initializer.initialize(bias_shape, rng));
     }
-    return biases;
+    biases
+}
+
+fn parameter_count(layer: &dyn Layer) -> usize {
+    layer.parameters().iter().map(|parameter| parameter.values.len()).sum()
 }
 
 pub struct Parameters {
@@ -52,13 +52,35 @@ impl Parameters {
 pub struct HyperParameters {
     pub shape: Vec<usize>,
     pub learning_rate: f64,
-    pub layers: usize
+    pub layers: usize,
+    // one activation per weight layer
+    pub activations: Vec<Activation>,
+    pub loss: Loss,
+    // None trains on the full dataset at once
+    pub batch_size: Option<usize>,
+    pub drop_last: bool,
+    pub seed: u64,
+    // one optional penalty per weight layer, added to the training loss and weight gradients
+    pub regularizers: Vec<Option<Regularizer>>,
+    // also apply the penalties and weight decay to the biases
+    pub regularize_biases: bool,
+    // decoupled weight decay, parameters shrink by learning_rate * weight_decay every update;
+    // cannot be combined with an optimizer that decays itself (AdamW)
+    pub weight_decay: f64,
+    // applied to the weight and bias gradients between backward and update
+    pub clipping: Option<GradientClipping>,
 }
 
 impl HyperParameters {
     pub fn new(shape: Vec<usize>, learning_rate: f64) -> HyperParameters {
         let layers: usize = shape.len();
-        HyperParameters { shape, learning_rate, layers }
+        let activations: Vec<Activation> = vec![Activation::Sigmoid; layers-1];
+        HyperParameters {
+            shape, learning_rate, layers, activations, loss: Loss::CrossEntropy,
+            batch_size: None, drop_last: false, seed: thread_rng().gen(),
+            regularizers: vec![None; layers-1], regularize_biases: false, weight_decay: 0.,
+            clipping: None,
+        }
     }
 }
 
@@ -80,64 +102,1289 @@ impl Gradients {
     }
 }
 
+// Progress of the training that carries over between calls of NeuralNetwork::training
+// and is stored in checkpoints.
+pub struct TrainingState {
+    // number of completed epochs
+    pub epoch: usize,
+    // monitored loss of the last completed epoch, fed to the scheduler
+    pub previous_loss: Option<f64>,
+    pub best_loss: f64,
+}
+
+impl TrainingState {
+    pub fn new() -> TrainingState {
+        TrainingState { epoch: 0, previous_loss: None, best_loss: f64::INFINITY }
+    }
+}
+
+impl Default for TrainingState {
+    fn default() -> Self {
+        TrainingState::new()
+    }
+}
+
+// Layers like dropout behave differently while training. predict and evaluate always run
+// in inference mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Train,
+    Eval,
+}
+
 pub struct NeuralNetwork {
     pub parameters: Parameters,
     pub hyperparameters: HyperParameters,
     pub gradients: Gradients,
+    pub optimizer: Box<dyn Optimizer>,
+    pub scheduler: Option<Box<dyn LrScheduler>>,
+    pub state: TrainingState,
+    pub checkpointing: Option<Checkpointing>,
+    pub early_stopping: Option<EarlyStopping>,
+    // checks the loss and gradients of every batch for NaN and infinity
+    pub guard: Option<NonFiniteGuard>,
+    pub callbacks: Vec<Box<dyn Callback>>,
+    pub mode: Mode,
+    // layers applied behind the activation of each dense layer, in order; their parameters
+    // are trained but not written to saved models or checkpoints
+    pub extra_layers: Vec<Vec<Box<dyn Layer>>>,
+    // layers applied to the input before the first dense layer, e.g. convolutions
+    pub input_layers: Vec<Box<dyn Layer>>,
+}
+
+// Why try_training ended before all epochs were trained.
+#[derive(Debug)]
+pub enum TrainingError {
+    NonFinite(NonFiniteError),
+    // the guard left out every batch of the epoch, so there is no loss to report
+    NoFiniteBatch { epoch: usize },
+    Checkpoint(ModelError),
+    // settings that contradict each other, found before the first epoch
+    Configuration(String),
+}
+
+impl fmt::Display for TrainingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrainingError::NonFinite(e) => write!(f, "{}", e),
+            TrainingError::NoFiniteBatch { epoch } => write!(f, "Every batch of epoch {} was non-finite and left out.", epoch),
+            TrainingError::Checkpoint(e) => write!(f, "Could not write a checkpoint: {}", e),
+            TrainingError::Configuration(message) => write!(f, "Invalid training configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TrainingError {}
+
+pub struct Evaluation {
+    pub loss: f64,
+    // only computed for cross entropy, i.e. classification
+    pub accuracy: Option<f64>,
 }
 
 impl NeuralNetwork {
     pub fn new(shape: Vec<usize>) -> NeuralNetwork {
-        let learning_rate: f64 = 1.;
-        let hyperparameters: HyperParameters = HyperParameters::new(shape, learning_rate);
+        NeuralNetwork::with_optimizer(shape, 1., Box::new(Sgd::new()))
+    }
+
+    pub fn with_optimizer(shape: Vec<usize>, learning_rate: f64, optimizer: Box<dyn Optimizer>) -> NeuralNetwork {
+        NeuralNetwork::with_seed(shape, learning_rate, optimizer, thread_rng().gen())
+    }
+
+    // All random parameters, of the dense layers and of the layers added later, are drawn
+    // from generators derived from the seed, so equal seeds give equal networks.
+    pub fn with_seed(shape: Vec<usize>, learning_rate: f64, optimizer: Box<dyn Optimizer>, seed: u64) -> NeuralNetwork {
+        let mut hyperparameters: HyperParameters = HyperParameters::new(shape, learning_rate);
+        hyperparameters.seed = seed;
+
+        let mut rng: StdRng = StdRng::seed_from_u64(seed);
+        let weights = initialize_weights(&hyperparameters.shape, &Initializer::XavierNormal, &mut rng);
+        let biases = initialize_biases(&hyperparameters.shape, &Initializer::Zeros, &mut rng);
+
+        let mut nn: NeuralNetwork = NeuralNetwork::from_parameters(hyperparameters, weights, biases);
+        nn.optimizer = optimizer;
+        nn
+    }
+
+    pub fn from_parameters(hyperparameters: HyperParameters, weights: Vec<Vector2D>, biases: Vec<Vector2D>) -> NeuralNetwork {
+        let layers: usize = hyperparameters.layers;
+        let parameters: Parameters = Parameters::new(layers, weights, biases);
+        let gradients = Gradients::new(layers);
+        NeuralNetwork {
+            parameters, gradients, hyperparameters,
+            optimizer: Box::new(Sgd::new()),
+            scheduler: None,
+            state: TrainingState::new(),
+            checkpointing: None,
+            early_stopping: None,
+            guard: None,
+            callbacks: vec![],
+            mode: Mode::Train,
+            extra_layers: (0..layers-1).map(|_| vec![]).collect(),
+            input_layers: vec![],
+        }
+    }
+
+    pub fn train(&mut self) {
+        self.mode = Mode::Train;
+    }
+
+    pub fn eval(&mut self) {
+        self.mode = Mode::Eval;
+    }
 
-        let weights = initialize_weights(&hyperparameters.shape);
-        let biases = initialize_biases(&hyperparameters.shape);
-        let parameters: Parameters = Parameters::new(hyperparameters.layers, weights, biases);
+    // Adds a layer behind the activation of dense layer `after` (and behind the layers
+    // already added there). Its parameters are initialized from the seed.
+    pub fn add_layer(&mut self, after: usize, mut layer: Box<dyn Layer>) {
+        if after >= self.extra_layers.len() {
+            panic!("Cannot add a layer after dense layer {}, the network only has {}.", after, self.extra_layers.len());
+        }
+        layer.initialize(&mut self.added_layer_rng());
+        self.extra_layers[after].push(layer);
+    }
+
+    // Adds a layer in front of the first dense layer (and behind the input layers already
+    // added). The last input layer has to output shape[0] values per sample. Its parameters
+    // are initialized from the seed.
+    pub fn add_input_layer(&mut self, mut layer: Box<dyn Layer>) {
+        layer.initialize(&mut self.added_layer_rng());
+        self.input_layers.push(layer);
+    }
+
+    // Generator for initializing layer `index` from the seed: the dense layers come first,
+    // the added layers follow in the order they were added. The index is spread over the
+    // bits of the seed so the streams differ from the per-epoch shuffling in batches.
+    fn layer_rng(&self, index: usize) -> StdRng {
+        StdRng::seed_from_u64(self.hyperparameters.seed ^ (index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    fn added_layer_rng(&self) -> StdRng {
+        let added: usize = self.input_layers.len() + self.extra_layers.iter().map(|layers| layers.len()).sum::<usize>();
+        self.layer_rng(self.hyperparameters.layers - 1 + added)
+    }
+
+    // The parameters of all added layers, input layers first, each in the order of
+    // Layer::parameters.
+    pub fn layer_parameters(&self) -> Vec<Vector2D> {
+        self.input_layers.iter().chain(self.extra_layers.iter().flatten())
+            .flat_map(|layer| layer.parameters().into_iter().cloned())
+            .collect()
+    }
 
-        let gradients = Gradients::new(hyperparameters.layers);
+    pub fn load_layer_parameters(&mut self, parameters: &[Vector2D]) {
+        let mut targets: Vec<&mut Vector2D> = self.input_layers.iter_mut().chain(self.extra_layers.iter_mut().flatten())
+            .flat_map(|layer| layer.parameters_mut())
+            .collect();
+        if targets.len() != parameters.len() {
+            panic!("The added layers have {} parameters but {} were given.", targets.len(), parameters.len());
+        }
+        for (target, parameter) in targets.iter_mut().zip(parameters.iter()) {
+            if target.shape != parameter.shape {
+                panic!("Can not load a parameter of shape {:?} into one of shape {:?}.", parameter.shape, target.shape);
+            }
+            **target = parameter.clone();
+        }
+    }
 
-        NeuralNetwork { parameters, gradients, hyperparameters }
+    pub fn initialize_layer(&mut self, layer: usize, weights: &Initializer, biases: &Initializer) {
+        let mut rng: StdRng = self.layer_rng(layer);
+        let weight_shape: [usize; 2] = self.parameters.weights[layer].shape;
+        let bias_shape: [usize; 2] = self.parameters.biases[layer].shape;
+        self.parameters.weights[layer] = weights.initialize(weight_shape, &mut rng);
+        self.parameters.biases[layer] = biases.initialize(bias_shape, &mut rng);
     }
 
     pub fn forward(&mut self, input: &Vector2D) -> Vector2D {
-        self.parameters.a[0] = input.clone();
+        let training: bool = self.mode == Mode::Train;
+        let mut a: Vector2D = input.clone();
+        for layer in self.input_layers.iter_mut() {
+            a = layer.forward(&a, training);
+        }
+        self.parameters.a[0] = a;
         for layer in 0..self.hyperparameters.layers-1 {
             self.parameters.z[layer] = self.parameters.a[layer].dot(&self.parameters.weights[layer]).row_add(&self.parameters.biases[layer]);
-            self.parameters.a[layer+1] = activation::sigmoid(&self.parameters.z[layer]);
+            let mut a: Vector2D = self.hyperparameters.activations[layer].apply(&self.parameters.z[layer]);
+            for extra in self.extra_layers[layer].iter_mut() {
+                a = extra.forward(&a, training);
+            }
+            self.parameters.a[layer+1] = a;
+        }
+        self.parameters.h()
+    }
+
+    // Runs the network on the input without touching the cached activations used for
+    // training, so a trained network can be shared between threads.
+    pub fn predict(&self, input: &Vector2D) -> Vector2D {
+        let mut a: Vector2D = input.clone();
+        for layer in &self.input_layers {
+            a = layer.predict(&a);
+        }
+        for layer in 0..self.hyperparameters.layers-1 {
+            let z: Vector2D = a.dot(&self.parameters.weights[layer]).row_add(&self.parameters.biases[layer]);
+            a = self.hyperparameters.activations[layer].apply(&z);
+            for extra in &self.extra_layers[layer] {
+                a = extra.predict(&a);
+            }
+        }
+        a
+    }
+
+    // Class probabilities. A single sigmoid output is expanded to the two columns
+    // [P(y=0), P(y=1)], wider outputs are returned as they are.
+    pub fn predict_proba(&self, input: &Vector2D) -> Vector2D {
+        let h: Vector2D = self.predict(input);
+        if h.shape[1] != 1 {
+            return h;
+        }
+        let mut values: Vec<f64> = vec![];
+        for p in &h.values {
+            values.push(1. - p);
+            values.push(*p);
+        }
+        Vector2D::new(values, [h.shape[0], 2])
+    }
+
+    pub fn predict_classes(&self, input: &Vector2D, threshold: f64) -> Vector2D {
+        self.predict(input).map(|p| if p >= threshold { 1. } else { 0. })
+    }
+
+    pub fn evaluate(&self, input: &Vector2D, true_output: &Vector2D) -> Evaluation {
+        let h: Vector2D = self.predict(input);
+        let loss: f64 = self.hyperparameters.loss.loss(&h, true_output);
+        let accuracy: Option<f64> = match self.hyperparameters.loss {
+            // thresholded at 0.5 for a single output, the largest output is the class otherwise
+            Loss::CrossEntropy => Some(metrics::accuracy(&h, true_output)),
+            _ => None,
+        };
+        Evaluation { loss, accuracy }
+    }
+
+    // A table of all layers with their shapes, activations and trainable parameters, and an
+    // estimate of the memory the network needs; print it with {}. Shapes come from the layer
+    // configurations, input layers that accept any length (e.g. recurrent layers) get their
+    // sizes from the layers around them.
+    pub fn summary(&self) -> Summary {
+        let shape: &Vec<usize> = &self.hyperparameters.shape;
+        let mut layers: Vec<LayerSummary> = vec![];
+
+        let mut size: Option<usize> = None;
+        for (idx, layer) in self.input_layers.iter().enumerate() {
+            let input: Option<usize> = layer.input_size().or(size);
+            let output: Option<usize> = if idx == self.input_layers.len() - 1 {
+                Some(shape[0])
+            } else {
+                input.and_then(|input| layer.output_size_for(input))
+            };
+            layers.push(LayerSummary { name: layer.name().to_string(), input, output, activation: None, parameters: parameter_count(layer.as_ref()) });
+            size = output;
+        }
+        // a layer that fixes its input also fixes the output of the layer in front of it
+        for idx in (1..layers.len()).rev() {
+            if layers[idx-1].output.is_none() {
+                layers[idx-1].output = layers[idx].input;
+            }
         }
-        return self.parameters.h()
+
+        for layer in 0..self.hyperparameters.layers-1 {
+            layers.push(LayerSummary {
+                name: format!("dense {}", layer),
+                input: Some(shape[layer]),
+                output: Some(shape[layer+1]),
+                activation: Some(self.hyperparameters.activations[layer]),
+                parameters: self.parameters.weights[layer].values.len() + self.parameters.biases[layer].values.len(),
+            });
+            for extra in &self.extra_layers[layer] {
+                let input: usize = shape[layer+1];
+                let output: Option<usize> = extra.output_size_for(input);
+                layers.push(LayerSummary { name: extra.name().to_string(), input: Some(input), output, activation: None, parameters: parameter_count(extra.as_ref()) });
+            }
+        }
+
+        Summary { layers, optimizer_slots: self.optimizer.state().slots.len() }
     }
 
     pub fn backward(&mut self, true_output: &Vector2D) {
-        self.gradients.a[self.hyperparameters.layers-1] = loss::cross_entropy_derivative(self.parameters.h(), true_output);
+        self.gradients.a[self.hyperparameters.layers-1] = self.hyperparameters.loss.derivative(self.parameters.h(), true_output);
         for layer in (0..self.hyperparameters.layers-1).rev() {
-            self.gradients.z[layer] = &self.gradients.a[layer+1] * sigmoid_derivative(&self.parameters.z[layer]);
+            let mut gradient: Vector2D = self.gradients.a[layer+1].clone();
+            for extra in self.extra_layers[layer].iter_mut().rev() {
+                gradient = extra.backward(&gradient);
+            }
+            self.gradients.z[layer] = gradient * self.hyperparameters.activations[layer].derivative(&self.parameters.z[layer]);
             self.gradients.a[layer] = self.gradients.z[layer].dot(&self.parameters.weights[layer].transpose());
             
             self.gradients.biases[layer] = self.gradients.z[layer].mean(0);
-            self.gradients.weights[layer] = self.parameters.a[layer].transpose().dot(&self.gradients.z[layer]) / self.parameters.h().len() as f64;
+            self.gradients.weights[layer] = self.parameters.a[layer].transpose().dot(&self.gradients.z[layer]) / true_output.shape[0] as f64;
+
+            if let Some(regularizer) = &self.hyperparameters.regularizers[layer] {
+                self.gradients.weights[layer] = &self.gradients.weights[layer] + regularizer.gradient(&self.parameters.weights[layer]);
+                if self.hyperparameters.regularize_biases {
+                    self.gradients.biases[layer] = &self.gradients.biases[layer] + regularizer.gradient(&self.parameters.biases[layer]);
+                }
+            }
+        }
+
+        let mut gradient: Vector2D = self.gradients.a[0].clone();
+        for layer in self.input_layers.iter_mut().rev() {
+            gradient = layer.backward(&gradient);
+        }
+    }
+
+    // Sum of all regularization penalties, part of the reported training loss.
+    pub fn penalty(&self) -> f64 {
+        let mut penalty: f64 = 0.;
+        for (layer, regularizer) in self.hyperparameters.regularizers.iter().enumerate() {
+            if let Some(regularizer) = regularizer {
+                penalty += regularizer.penalty(&self.parameters.weights[layer]);
+                if self.hyperparameters.regularize_biases {
+                    penalty += regularizer.penalty(&self.parameters.biases[layer]);
+                }
+            }
         }
+        penalty
     }
 
-    pub fn update(&mut self) {
+    // Clips the weight and bias gradients if clipping is configured and returns their global
+    // norm before clipping. The gradients of added layers are left to the layers.
+    pub fn clip_gradients(&mut self) -> f64 {
+        let gradients: Vec<&mut Vector2D> = self.gradients.weights.iter_mut().chain(self.gradients.biases.iter_mut()).collect();
+        match &self.hyperparameters.clipping {
+            Some(clipping) => clipping.clip(gradients),
+            None => global_norm(&gradients.iter().map(|g| &**g).collect::<Vec<&Vector2D>>()),
+        }
+    }
+
+    // Where the loss or the gradients of the last backward call are not finite, and the value.
+    pub fn find_non_finite(&self, loss: f64) -> Option<(String, f64)> {
+        if !loss.is_finite() {
+            return Some(("loss".to_string(), loss));
+        }
         for layer in 0..self.hyperparameters.layers-1 {
-            self.parameters.weights[layer] = &self.parameters.weights[layer] - self.hyperparameters.learning_rate * &self.gradients.weights[layer];
-            self.parameters.biases[layer] = &self.parameters.biases[layer] - self.hyperparameters.learning_rate * &self.gradients.biases[layer];
+            if let Some(value) = first_non_finite(&self.gradients.weights[layer]) {
+                return Some((format!("weight gradient of layer {}", layer), value));
+            }
+            if let Some(value) = first_non_finite(&self.gradients.biases[layer]) {
+                return Some((format!("bias gradient of layer {}", layer), value));
+            }
+            for extra in &self.extra_layers[layer] {
+                if let Some(value) = extra.gradients().into_iter().find_map(first_non_finite) {
+                    return Some((format!("{} gradient behind layer {}", extra.name(), layer), value));
+                }
+            }
+        }
+        for (idx, layer) in self.input_layers.iter().enumerate() {
+            if let Some(value) = layer.gradients().into_iter().find_map(first_non_finite) {
+                return Some((format!("{} gradient of input layer {}", layer.name(), idx), value));
+            }
+        }
+        None
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            weights: self.parameters.weights.clone(),
+            biases: self.parameters.biases.clone(),
+            layers: self.layer_parameters(),
+            optimizer: self.optimizer.state(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.parameters.weights = snapshot.weights.clone();
+        self.parameters.biases = snapshot.biases.clone();
+        self.load_layer_parameters(&snapshot.layers);
+        self.optimizer.load_state(&snapshot.optimizer).expect("The snapshot was taken from this optimizer.");
+    }
+
+    pub fn update(&mut self, learning_rate: f64) {
+        // the decay is decoupled from the gradients, so adaptive optimizers do not rescale it
+        if self.hyperparameters.weight_decay != 0. {
+            let factor: f64 = 1. - learning_rate * self.hyperparameters.weight_decay;
+            for weights in self.parameters.weights.iter_mut() {
+                *weights = &*weights * factor;
+            }
+            if self.hyperparameters.regularize_biases {
+                for biases in self.parameters.biases.iter_mut() {
+                    *biases = &*biases * factor;
+                }
+            }
+        }
+        let mut parameters: Vec<&mut Vector2D> = vec![];
+        for (weights, biases) in self.parameters.weights.iter_mut().zip(self.parameters.biases.iter_mut()) {
+            parameters.push(weights);
+            parameters.push(biases);
+        }
+        let mut gradients: Vec<&Vector2D> = vec![];
+        for (weights, biases) in self.gradients.weights.iter().zip(self.gradients.biases.iter()) {
+            gradients.push(weights);
+            gradients.push(biases);
+        }
+        for extra in self.extra_layers.iter_mut().flatten().chain(self.input_layers.iter_mut()) {
+            let (extra_parameters, extra_gradients) = extra.parameters_and_gradients();
+            parameters.extend(extra_parameters);
+            gradients.extend(extra_gradients);
+        }
+        self.optimizer.step(learning_rate, parameters, gradients);
+        for extra in self.extra_layers.iter_mut().flatten().chain(self.input_layers.iter_mut()) {
+            extra.sparse_update(learning_rate);
+        }
+    }
+
+    pub fn learning_rate(&mut self, epoch: usize, metric: Option<f64>) -> f64 {
+        match &mut self.scheduler {
+            Some(scheduler) => scheduler.learning_rate(epoch, self.hyperparameters.learning_rate, metric),
+            None => self.hyperparameters.learning_rate,
+        }
+    }
+
+    // Splits the sample indices into (shuffled) mini-batches. The shuffling only depends on
+    // the seed and the epoch, so every epoch can be reproduced on its own.
+    pub fn batches(&self, samples: usize, epoch: usize) -> Vec<Vec<usize>> {
+        let mut indices: Vec<usize> = (0..samples).collect();
+        let batch_size: usize = match self.hyperparameters.batch_size {
+            Some(batch_size) => batch_size,
+            None => return vec![indices],
+        };
+        if batch_size == 0 {
+            panic!("Batch size has to be at least 1, use None to train on the full dataset at once.");
+        }
+        let mut rng: StdRng = StdRng::seed_from_u64(self.hyperparameters.seed.wrapping_add(epoch as u64));
+        indices.shuffle(&mut rng);
+
+        let mut batches: Vec<Vec<usize>> = vec![];
+        for batch in indices.chunks(batch_size) {
+            if self.hyperparameters.drop_last && batch.len() < batch_size {
+                continue;
+            }
+            batches.push(batch.to_vec());
+        }
+        if batches.is_empty() {
+            panic!("Batch size {} is larger than the {} samples and drop_last is set, so there is no batch left to train on.", batch_size, samples);
+        }
+        batches
+    }
+
+    // Trains for the given number of additional epochs, continuing the epoch count (and with
+    // it shuffling and learning rate schedule) of previous calls or a resumed checkpoint.
+    // If validation data is given, its loss is monitored by the scheduler, checkpointing and
+    // early stopping instead of the training loss.
+    // Panics with the TrainingError if the configuration is contradictory, a guard with
+    // NonFiniteAction::Abort stops the training, the guard leaves out every batch of an epoch
+    // or a checkpoint cannot be written, use try_training to handle it instead.
+    pub fn training(&mut self, input: Vector2D, true_output: Vector2D, validation: Option<(Vector2D, Vector2D)>, epochs: usize, verbose: bool) -> History {
+        match self.try_training(input, true_output, validation, epochs, verbose) {
+            Ok(history) => history,
+            Err(e) => panic!("{}", e),
         }
     }
 
-    pub fn training(&mut self, input: Vector2D, true_output: Vector2D, epochs: usize, verbose: bool) {
-        for epoch in 0..epochs {
-            let h: Vector2D = self.forward(&input);
-            let loss: Vector2D = loss::cross_entropy_loss(&h, &true_output);
+    // Settings that can only be checked together, before anything is trained.
+    fn check_configuration(&self) -> Result<(), TrainingError> {
+        if self.hyperparameters.weight_decay != 0. && self.optimizer.weight_decay() != 0. {
+            return Err(TrainingError::Configuration(format!(
+                "weight decay is set on both the network ({}) and the optimizer ({}), which would decay the parameters twice",
+                self.hyperparameters.weight_decay, self.optimizer.weight_decay()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn try_training(&mut self, input: Vector2D, true_output: Vector2D, validation: Option<(Vector2D, Vector2D)>, epochs: usize, verbose: bool) -> Result<History, TrainingError> {
+        self.check_configuration()?;
+        let mut error: Option<TrainingError> = None;
+        let mut history: History = History::new();
+        if let Some(early_stopping) = &mut self.early_stopping {
+            early_stopping.start();
+        }
+        // the callbacks are moved out for the duration of the training so they can see the network
+        let mut callbacks: Vec<Box<dyn Callback>> = std::mem::take(&mut self.callbacks);
+        let mode: Mode = self.mode;
+        self.train();
+        for callback in callbacks.iter_mut() {
+            callback.on_train_begin(self);
+        }
+
+        for _ in 0..epochs {
+            let start: Instant = Instant::now();
+            let epoch: usize = self.state.epoch;
+            let learning_rate: f64 = self.learning_rate(epoch, self.state.previous_loss);
+            let mut epoch_loss: f64 = 0.;
+            let mut seen_samples: usize = 0;
+            let mut gradient_norms: Vec<f64> = vec![];
+            let mut stop: bool = false;
+            for callback in callbacks.iter_mut() {
+                callback.on_epoch_begin(epoch, self);
+            }
+
+            for (batch_idx, batch) in self.batches(input.shape[0], epoch).into_iter().enumerate() {
+                for callback in callbacks.iter_mut() {
+                    callback.on_batch_begin(epoch, batch_idx, self);
+                }
+                let (batch_input, batch_output) = match self.hyperparameters.batch_size {
+                    None => (input.clone(), true_output.clone()),
+                    Some(_) => (input.select_rows(&batch), true_output.select_rows(&batch)),
+                };
+                let h: Vector2D = self.forward(&batch_input);
+                let loss: f64 = self.hyperparameters.loss.loss(&h, &batch_output) + self.penalty();
+                self.backward(&batch_output);
+
+                // skipped batches do not reach on_batch_end
+                let non_finite: Option<(String, f64)> = self.guard.as_ref().and_then(|_| self.find_non_finite(loss));
+                if let Some(action) = self.guard.as_ref().map(|guard| guard.action) {
+                    if let Some((source, value)) = non_finite {
+                        if action == NonFiniteAction::Abort {
+                            error = Some(TrainingError::NonFinite(NonFiniteError { epoch, batch: batch_idx, source, value }));
+                            break;
+                        }
+                        let guard: &mut NonFiniteGuard = self.guard.as_mut().unwrap();
+                        guard.skipped_batches += 1;
+                        if let (NonFiniteAction::Rollback, Some(snapshot)) = (action, guard.snapshot.take()) {
+                            self.restore(&snapshot);
+                            self.guard.as_mut().unwrap().snapshot = Some(snapshot);
+                        }
+                        continue;
+                    }
+                    if action == NonFiniteAction::Rollback {
+                        let snapshot: Snapshot = self.snapshot();
+                        self.guard.as_mut().unwrap().snapshot = Some(snapshot);
+                    }
+                }
+                epoch_loss += loss * batch.len() as f64;
+                seen_samples += batch.len();
+                let gradient_norm: f64 = self.clip_gradients();
+                gradient_norms.push(gradient_norm);
+                self.update(learning_rate);
+
+                let logs: BatchLogs = BatchLogs { epoch, batch: batch_idx, size: batch.len(), loss, learning_rate, gradient_norm };
+                for callback in callbacks.iter_mut() {
+                    stop |= callback.on_batch_end(&logs, self) == Control::Stop;
+                }
+                if stop {
+                    break;
+                }
+            }
+            if error.is_none() && seen_samples == 0 {
+                error = Some(TrainingError::NoFiniteBatch { epoch });
+            }
+            if error.is_some() {
+                break;
+            }
+            epoch_loss /= seen_samples as f64;
+
+            let evaluation: Option<Evaluation> = validation.as_ref().map(|(x, y)| self.evaluate(x, y));
+            let monitored_loss: f64 = evaluation.as_ref().map_or(epoch_loss, |e| e.loss);
 
             if verbose {
-                println!("Epoch {}: {}", epoch, loss.get_value(0));
+                match &evaluation {
+                    Some(e) => println!(
+                        "Epoch {}: {} (validation loss {}, validation accuracy {:?}) (learning rate {})",
+                        epoch, epoch_loss, e.loss, e.accuracy, learning_rate
+                    ),
+                    None => println!("Epoch {}: {} (learning rate {})", epoch, epoch_loss, learning_rate),
+                }
+            }
+
+            let mut metrics: Vec<(String, f64)> = vec![];
+            if let Some(accuracy) = evaluation.as_ref().and_then(|e| e.accuracy) {
+                metrics.push(("validation_accuracy".to_string(), accuracy));
+            }
+            if self.hyperparameters.clipping.is_some() && !gradient_norms.is_empty() {
+                // the mean pre-clip norm, to see how often and how hard the clipping acts
+                metrics.push(("gradient_norm".to_string(), gradient_norms.iter().sum::<f64>() / gradient_norms.len() as f64));
+            }
+            history.push(EpochRecord {
+                epoch,
+                loss: epoch_loss,
+                validation_loss: evaluation.as_ref().map(|e| e.loss),
+                learning_rate,
+                seconds: start.elapsed().as_secs_f64(),
+                metrics,
+            });
+
+            self.state.epoch += 1;
+            self.state.previous_loss = Some(monitored_loss);
+            let improved: bool = monitored_loss < self.state.best_loss;
+            if improved {
+                self.state.best_loss = monitored_loss;
+            }
+            // the added layers are only copied if early stopping may have to restore them
+            let layers: Vec<Vector2D> = match &self.early_stopping {
+                Some(early_stopping) if early_stopping.restore_best_weights => self.layer_parameters(),
+                _ => vec![],
+            };
+            let early_stop: bool = match &mut self.early_stopping {
+                Some(early_stopping) => early_stopping.update(epoch, monitored_loss, &self.parameters.weights, &self.parameters.biases, &layers),
+                None => false,
+            };
+            if let Err(e) = self.write_checkpoints(improved) {
+                error = Some(TrainingError::Checkpoint(e));
+                break;
+            }
+
+            let record: &EpochRecord = history.epochs.last().unwrap();
+            for callback in callbacks.iter_mut() {
+                stop |= callback.on_epoch_end(record, self) == Control::Stop;
             }
+            if early_stop && verbose {
+                println!("Early stopping after epoch {}", epoch);
+            }
+            if stop || early_stop {
+                break;
+            }
+        }
+
+        if let Some((weights, biases, layers)) = self.early_stopping.as_ref().and_then(|e| e.best_parameters()) {
+            self.parameters.weights = weights;
+            self.parameters.biases = biases;
+            self.load_layer_parameters(&layers);
+        }
+        self.mode = mode;
+        for callback in callbacks.iter_mut() {
+            callback.on_train_end(&history, self);
+        }
+        self.callbacks = callbacks;
+        match error {
+            Some(error) => Err(error),
+            None => Ok(history),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::{convolution::Conv2D, dense::Dense, dropout::Dropout, normalization::{BatchNorm, LayerNorm}, pooling::{Flatten, GlobalAveragePool, MaxPool2D}, recurrent::Lstm, embedding::Embedding};
+    use crate::optimizer::Adam;
+
+    #[test]
+    fn test_predict_matches_forward() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        let input: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let prediction: Vector2D = nn.predict(&input);
+        assert!(prediction.values == nn.forward(&input).values);
+
+        let probabilities: Vector2D = nn.predict_proba(&input);
+        assert!(probabilities.shape == [4, 2]);
+        for row in 0..4 {
+            assert!(probabilities[(row, 1)] == prediction[row]);
+            assert!((probabilities[(row, 0)] + probabilities[(row, 1)] - 1.).abs() < 1e-12);
+        }
+
+        let classes: Vector2D = nn.predict_classes(&input, 0.5);
+        for (c, p) in classes.values.iter().zip(prediction.values.iter()) {
+            assert!(*c == if *p >= 0.5 { 1. } else { 0. });
+        }
+    }
+
+    #[test]
+    fn test_predict_from_multiple_threads() {
+        use std::{sync::Arc, thread};
+        let nn: Arc<NeuralNetwork> = Arc::new(NeuralNetwork::new(vec![2, 3, 1]));
+        let input: Vector2D = Vector2D::new(vec![0., 1.], [1, 2]);
+        let expected: Vec<f64> = nn.predict(&input).values;
+
+        let handles: Vec<thread::JoinHandle<Vec<f64>>> = (0..4).map(|_| {
+            let nn: Arc<NeuralNetwork> = Arc::clone(&nn);
+            let input: Vector2D = input.clone();
+            thread::spawn(move || nn.predict(&input).values)
+        }).collect();
+        for handle in handles {
+            assert!(handle.join().unwrap() == expected);
+        }
+        assert!(nn.parameters.a[0].is_empty());
+    }
+
+    fn xnor() -> (Vector2D, Vector2D) {
+        let x: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 0., 0., 1.], [4, 1]);
+        (x, y)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        let (x, y) = xnor();
+        let evaluation: Evaluation = nn.evaluate(&x, &y);
+        assert!(evaluation.loss == nn.hyperparameters.loss.loss(&nn.predict(&x), &y));
+        let accuracy: f64 = evaluation.accuracy.unwrap();
+        assert!((0. ..=1.).contains(&accuracy));
+    }
+
+    #[test]
+    fn test_evaluate_accuracy_of_one_hot_outputs() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 2]);
+        nn.parameters.weights[0] = Vector2D::new(vec![1., 0., 0., 1.], [2, 2]);
+        nn.parameters.biases[0] = Vector2D::zeros([1, 2]);
+        let x: Vector2D = Vector2D::new(vec![1., 0., 0., 1., 1., 0.], [3, 2]);
+        let y: Vector2D = Vector2D::new(vec![1., 0., 0., 1., 0., 1.], [3, 2]);
+        // one of three samples is misclassified, while half of the entries differ
+        assert!(nn.evaluate(&x, &y).accuracy == Some(2. / 3.));
+    }
+
+    #[test]
+    fn test_early_stopping_restores_best_weights() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.early_stopping = Some(EarlyStopping::new(3, 0., true));
+        // validating against the inverted labels makes the validation loss grow while training
+        let (x, y) = xnor();
+        let inverted: Vector2D = 1. - &y;
+        let history: History = nn.training(x.clone(), y, Some((x.clone(), inverted.clone())), 2000, false);
+
+        let early_stopping: &EarlyStopping = nn.early_stopping.as_ref().unwrap();
+        assert!(nn.state.epoch < 2000);
+        assert!(history.len() == nn.state.epoch);
+        assert!(history.epochs[early_stopping.best_epoch()].validation_loss == Some(early_stopping.best_loss()));
+        assert!(history.epochs.iter().all(|e| e.metric("validation_accuracy").is_some()));
+        assert!(nn.state.epoch == early_stopping.best_epoch() + 4);
+        assert!(nn.evaluate(&x, &inverted).loss == early_stopping.best_loss());
+    }
+
+    #[test]
+    fn test_early_stopping_restores_added_layers() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.add_layer(0, Box::new(Dense::new(3, 3, Activation::Tanh)));
+        nn.early_stopping = Some(EarlyStopping::new(3, 0., true));
+        let (x, y) = xnor();
+        let inverted: Vector2D = 1. - &y;
+        nn.training(x.clone(), y, Some((x.clone(), inverted.clone())), 2000, false);
+
+        let early_stopping: &EarlyStopping = nn.early_stopping.as_ref().unwrap();
+        let (_, _, layers) = early_stopping.best_parameters().unwrap();
+        assert!(layers.len() == 2 && nn.layer_parameters() == layers);
+        assert!(nn.evaluate(&x, &inverted).loss == early_stopping.best_loss());
+    }
+
+    #[test]
+    fn test_full_batch_by_default() {
+        let nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        assert!(nn.batches(5, 0) == vec![vec![0, 1, 2, 3, 4]]);
+    }
+
+    #[test]
+    fn test_mini_batches() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.batch_size = Some(4);
+        nn.hyperparameters.seed = 42;
+
+        let batches: Vec<Vec<usize>> = nn.batches(10, 0);
+        assert!(batches.iter().map(|b| b.len()).collect::<Vec<usize>>() == vec![4, 4, 2]);
+        let mut seen: Vec<usize> = batches.concat();
+        seen.sort();
+        assert!(seen == (0..10).collect::<Vec<usize>>());
+
+        assert!(nn.batches(10, 0) == batches);
+        assert!(nn.batches(10, 1) != batches);
+
+        nn.hyperparameters.drop_last = true;
+        assert!(nn.batches(10, 0).iter().all(|b| b.len() == 4));
+        assert!(nn.batches(10, 0).len() == 2);
+    }
+
+    #[test]
+    fn test_seed_fixes_all_parameters() {
+        let network = |seed: u64| -> NeuralNetwork {
+            let mut nn: NeuralNetwork = NeuralNetwork::with_seed(vec![3, 4, 1], 0.1, Box::new(Sgd::new()), seed);
+            nn.add_input_layer(Box::new(Lstm::new(2, 3)));
+            nn.add_layer(0, Box::new(Dense::new(4, 4, Activation::Relu)));
+            nn.initialize_layer(1, &Initializer::HeUniform, &Initializer::HeUniform);
+            nn
+        };
+        let (first, second, other) = (network(1), network(1), network(2));
+        assert!(first.parameters.weights == second.parameters.weights && first.parameters.biases == second.parameters.biases);
+        assert!(first.layer_parameters() == second.layer_parameters());
+        assert!(first.parameters.weights != other.parameters.weights);
+        assert!(first.parameters.biases[1] != other.parameters.biases[1]);
+        assert!(first.layer_parameters() != other.layer_parameters());
+    }
+
+    #[test]
+    #[should_panic(expected = "no batch left to train on")]
+    fn test_drop_last_without_full_batch_panics() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.batch_size = Some(16);
+        nn.hyperparameters.drop_last = true;
+        nn.batches(10, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch size has to be at least 1")]
+    fn test_zero_batch_size_panics() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.batch_size = Some(0);
+        nn.batches(10, 0);
+    }
+
+    fn total_loss(nn: &NeuralNetwork, x: &Vector2D, y: &Vector2D) -> f64 {
+        nn.hyperparameters.loss.loss(&nn.predict(x), y) + nn.penalty()
+    }
+
+    #[test]
+    fn test_regularized_gradients_match_penalized_loss() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.regularizers = vec![Some(Regularizer::L2(0.1)), Some(Regularizer::ElasticNet { l1: 0.05, l2: 0.02 })];
+        nn.hyperparameters.regularize_biases = true;
+        nn.parameters.biases[1] = Vector2D::new(vec![0.3], [1, 1]);
+        let (x, y) = xnor();
+        nn.forward(&x);
+        nn.backward(&y);
+
+        let eps: f64 = 1e-6;
+        for layer in 0..2 {
+            for idx in 0..nn.parameters.weights[layer].values.len() {
+                let original: f64 = nn.parameters.weights[layer].values[idx];
+                nn.parameters.weights[layer].values[idx] = original + eps;
+                let plus: f64 = total_loss(&nn, &x, &y);
+                nn.parameters.weights[layer].values[idx] = original - eps;
+                let minus: f64 = total_loss(&nn, &x, &y);
+                nn.parameters.weights[layer].values[idx] = original;
+                assert!(((plus - minus) / (2. * eps) - nn.gradients.weights[layer][idx]).abs() < 1e-6);
+            }
+        }
+        let original: f64 = nn.parameters.biases[1].values[0];
+        nn.parameters.biases[1].values[0] = original + eps;
+        let plus: f64 = total_loss(&nn, &x, &y);
+        nn.parameters.biases[1].values[0] = original - eps;
+        let minus: f64 = total_loss(&nn, &x, &y);
+        assert!(((plus - minus) / (2. * eps) - nn.gradients.biases[1][0]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_biases_are_not_regularized_by_default() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        let (x, y) = xnor();
+        nn.parameters.biases[0] = Vector2D::new(vec![1., 1., 1.], [1, 3]);
+        nn.forward(&x);
+        nn.backward(&y);
+        let plain: Vector2D = nn.gradients.biases[0].clone();
+        let penalty: f64 = nn.penalty();
+
+        nn.hyperparameters.regularizers = vec![Some(Regularizer::L1(0.5)); 2];
+        nn.backward(&y);
+        assert!(nn.gradients.biases[0] == plain);
+        assert!(nn.penalty() > penalty);
+    }
+
+    #[test]
+    fn test_decoupled_weight_decay() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.weight_decay = 0.5;
+        nn.gradients.weights = vec![Vector2D::zeros([2, 3]), Vector2D::zeros([3, 1])];
+        nn.gradients.biases = vec![Vector2D::zeros([1, 3]), Vector2D::zeros([1, 1])];
+        nn.parameters.biases[0] = Vector2D::new(vec![1., 1., 1.], [1, 3]);
+        let weights: Vec<Vector2D> = nn.parameters.weights.clone();
+
+        nn.update(0.1);
+        assert!(nn.parameters.weights[0] == &weights[0] * 0.95);
+        assert!(nn.parameters.biases[0].values == vec![1., 1., 1.]);
+    }
+
+    #[test]
+    fn test_weight_decay_is_not_applied_twice() {
+        let mut nn: NeuralNetwork = NeuralNetwork::with_optimizer(vec![2, 3, 1], 0.1, Box::new(Adam::adamw(0.01)));
+        nn.hyperparameters.weight_decay = 0.5;
+        let weights: Vec<Vector2D> = nn.parameters.weights.clone();
+        let (x, y) = xnor();
+        match nn.try_training(x, y, None, 1, false) {
+            Err(TrainingError::Configuration(message)) => assert!(message.starts_with("weight decay is set on both the network (0.5) and the optimizer (0.01)")),
+            _ => panic!("expected a configuration error"),
+        }
+        assert!(nn.state.epoch == 0 && nn.parameters.weights == weights);
+    }
+
+    #[test]
+    fn test_dropout_only_in_train_mode() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 50, 1]);
+        nn.add_layer(0, Box::new(Dropout::with_seed(0.5, 1)));
+        let (x, y) = xnor();
+
+        nn.eval();
+        assert!(nn.forward(&x) == nn.predict(&x));
+        nn.train();
+        assert!(nn.forward(&x) != nn.predict(&x));
+
+        // dropped units get no gradient
+        nn.backward(&y);
+        for row in 0..4 {
+            for unit in 0..50 {
+                if nn.parameters.a[1][(row, unit)] == 0. {
+                    assert!(nn.gradients.z[0][(row, unit)] == 0.);
+                }
+            }
+        }
+
+        nn.eval();
+        nn.training(x, y, None, 2, false);
+        assert!(nn.mode == Mode::Eval);
+    }
+
+    #[test]
+    fn test_normalization_layers_are_trained() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 4, 4, 1]);
+        nn.add_layer(0, Box::new(BatchNorm::new(4)));
+        nn.add_layer(1, Box::new(LayerNorm::new(4)));
+        let (x, y) = xnor();
+        let history: History = nn.training(x.clone(), y, None, 200, false);
+
+        assert!(history.losses()[199] < history.losses()[0]);
+        assert!(nn.extra_layers[0][0].parameters()[0].values != vec![1.; 4]);
+        assert!(nn.extra_layers[1][0].parameters()[1].values != vec![0.; 4]);
+        assert!(nn.predict(&x).shape == [4, 1]);
+    }
 
-            self.backward(&true_output);
-            self.update();
+    #[test]
+    fn test_small_cnn_learns_line_orientation() {
+        // 5x5 images with one vertical (label 1) or horizontal (label 0) line
+        let mut values: Vec<f64> = vec![];
+        let mut labels: Vec<f64> = vec![];
+        for position in 0..5 {
+            for vertical in [true, false] {
+                for idx in 0..25 {
+                    let on_line: bool = if vertical { idx % 5 == position } else { idx / 5 == position };
+                    values.push(if on_line { 1. } else { 0. });
+                }
+                labels.push(if vertical { 1. } else { 0. });
+            }
+        }
+        let x: Vector2D = Vector2D::new(values, [10, 25]);
+        let y: Vector2D = Vector2D::new(labels, [10, 1]);
+
+        let mut rng: StdRng = StdRng::seed_from_u64(5);
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 1]);
+        nn.hyperparameters.learning_rate = 0.5;
+        nn.parameters.weights[0] = Initializer::XavierNormal.initialize([2, 1], &mut rng);
+        nn.add_input_layer(Box::new(Conv2D::new([1, 5, 5], 2, [3, 3])));
+        nn.input_layers[0].initialize(&mut rng);
+        nn.add_input_layer(Box::new(MaxPool2D::new([2, 3, 3], [3, 3])));
+        nn.add_input_layer(Box::new(Flatten::new([2, 1, 1])));
+        assert!(nn.predict(&x).shape == [10, 1]);
+        nn.training(x.clone(), y.clone(), None, 300, false);
+        assert!(nn.predict_classes(&x, 0.5) == y);
+    }
+
+    #[test]
+    fn test_summary_infers_shapes_and_counts_parameters() {
+        let mut nn: NeuralNetwork = NeuralNetwork::with_optimizer(vec![2, 4, 1], 0.1, Box::new(Adam::new()));
+        nn.add_input_layer(Box::new(Conv2D::new([1, 5, 5], 2, [3, 3])));
+        nn.add_input_layer(Box::new(MaxPool2D::new([2, 3, 3], [3, 3])));
+        nn.add_input_layer(Box::new(Flatten::new([2, 1, 1])));
+        nn.add_layer(0, Box::new(BatchNorm::new(4)));
+        let summary: Summary = nn.summary();
+
+        let rows: Vec<(&str, Option<usize>, Option<usize>, usize)> = summary.layers.iter()
+            .map(|layer| (layer.name.as_str(), layer.input, layer.output, layer.parameters))
+            .collect();
+        assert!(rows == vec![
+            ("conv2d", Some(25), Some(18), 20),
+            ("max_pool2d", Some(18), Some(2), 0),
+            ("flatten", Some(2), Some(2), 0),
+            ("dense 0", Some(2), Some(4), 12),
+            ("batch_norm", Some(4), Some(4), 8),
+            ("dense 1", Some(4), Some(1), 5),
+        ]);
+        assert!(summary.layers[3].activation == Some(Activation::Sigmoid));
+        assert!(summary.parameters() == 45);
+        assert!(summary.training_bytes() == 45 * 8 * 4);
+    }
+
+    #[test]
+    fn test_summary_of_sequence_layers() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![3, 1]);
+        nn.add_input_layer(Box::new(Embedding::new(10, 4)));
+        nn.add_input_layer(Box::new(Lstm::new(4, 3)));
+        let summary: Summary = nn.summary();
+        // the number of tokens is not fixed, only the last hidden state is
+        assert!(summary.layers[0].input.is_none() && summary.layers[0].output.is_none());
+        assert!(summary.layers[1].output == Some(3));
+        assert!(summary.layers[0].parameters == 40 && summary.layers[1].parameters == 96);
+        assert!(summary.to_string().lines().nth(2).unwrap().starts_with("embedding  (None, ?)  (None, ?)"));
+    }
+
+    // A layer that must not be run, with no output size of its own.
+    struct Unrunnable;
+
+    impl Layer for Unrunnable {
+        fn name(&self) -> &'static str {
+            "unrunnable"
+        }
+
+        fn forward(&mut self, _input: &Vector2D, _training: bool) -> Vector2D {
+            panic!("forward was called");
+        }
+
+        fn predict(&self, _input: &Vector2D) -> Vector2D {
+            panic!("predict was called");
+        }
+
+        fn backward(&mut self, _gradient: &Vector2D) -> Vector2D {
+            panic!("backward was called");
+        }
+    }
+
+    #[test]
+    fn test_summary_sizes_come_from_layer_configurations() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![5, 2]);
+        nn.add_input_layer(Box::new(Conv2D::new([1, 4, 4], 3, [3, 3])));
+        nn.add_input_layer(Box::new(GlobalAveragePool::new([3, 2, 2])));
+        nn.add_input_layer(Box::new(Dense::new(3, 5, Activation::Relu)));
+        nn.add_layer(0, Box::new(Dropout::new(0.5)));
+        nn.add_layer(0, Box::new(Unrunnable));
+        let rows: Vec<(Option<usize>, Option<usize>)> = nn.summary().layers.iter().map(|layer| (layer.input, layer.output)).collect();
+        assert!(rows == vec![
+            (Some(16), Some(12)),
+            (Some(12), Some(3)),
+            (Some(3), Some(5)),
+            (Some(5), Some(2)),
+            (Some(2), Some(2)),
+            (Some(2), None),
+        ]);
+    }
+
+    #[test]
+    fn test_lstm_remembers_first_timestep() {
+        // sequences of five values whose label is the sign of the first one, followed by noise
+        let mut rng: StdRng = StdRng::seed_from_u64(2);
+        let mut values: Vec<f64> = vec![];
+        let mut labels: Vec<f64> = vec![];
+        for sample in 0..16 {
+            let positive: bool = sample % 2 == 0;
+            values.push(if positive { 1. } else { -1. });
+            values.extend((0..4).map(|_| rng.gen_range(-0.5..0.5)));
+            labels.push(if positive { 1. } else { 0. });
+        }
+        let x: Vector2D = Vector2D::new(values, [16, 5]);
+        let y: Vector2D = Vector2D::new(labels, [16, 1]);
+
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![4, 1]);
+        nn.hyperparameters.learning_rate = 0.5;
+        nn.parameters.weights[0] = Initializer::XavierNormal.initialize([4, 1], &mut rng);
+        nn.add_input_layer(Box::new(Lstm::new(1, 4)));
+        nn.input_layers[0].initialize(&mut rng);
+        nn.training(x.clone(), y.clone(), None, 200, false);
+        assert!(nn.predict_classes(&x, 0.5) == y);
+    }
+
+    #[test]
+    fn test_sparse_embedding_only_trains_seen_tokens() {
+        // token 4 never appears, tokens 0 and 2 are labeled 1
+        let x: Vector2D = Vector2D::new(vec![0., 1., 2., 3.], [4, 1]);
+        let y: Vector2D = Vector2D::new(vec![1., 0., 1., 0.], [4, 1]);
+        let mut rng: StdRng = StdRng::seed_from_u64(4);
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 1]);
+        nn.parameters.weights[0] = Initializer::XavierNormal.initialize([2, 1], &mut rng);
+        nn.add_input_layer(Box::new(Embedding::new(5, 2)));
+        nn.input_layers[0].initialize(&mut rng);
+        let unseen: Vector2D = nn.input_layers[0].parameters()[0].get_mat_row_values(4);
+        nn.training(x.clone(), y.clone(), None, 100, false);
+
+        assert!(nn.predict_classes(&x, 0.5) == y);
+        assert!(nn.input_layers[0].parameters()[0].get_mat_row_values(4) == unseen);
+    }
+
+    // Checks at the end of every batch that the gradients the update used were clipped.
+    struct ClipCheck {
+        max_norm: f64,
+        pre_clip: std::sync::Arc<std::sync::Mutex<Vec<f64>>>,
+    }
+
+    impl Callback for ClipCheck {
+        fn on_batch_end(&mut self, logs: &BatchLogs, nn: &NeuralNetwork) -> Control {
+            let gradients: Vec<&Vector2D> = nn.gradients.weights.iter().chain(nn.gradients.biases.iter()).collect();
+            assert!(global_norm(&gradients) <= self.max_norm + 1e-12);
+            self.pre_clip.lock().unwrap().push(logs.gradient_norm);
+            Control::Continue
+        }
+    }
+
+    #[test]
+    fn test_clipping_by_global_norm() {
+        let pre_clip = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        // large weights saturate the sigmoids and give large gradients
+        nn.parameters.weights[1] = Vector2D::new(vec![8., -8., 8.], [3, 1]);
+        nn.hyperparameters.clipping = Some(GradientClipping::Norm(0.01));
+        nn.hyperparameters.batch_size = Some(2);
+        nn.callbacks.push(Box::new(ClipCheck { max_norm: 0.01, pre_clip: std::sync::Arc::clone(&pre_clip) }));
+        let (x, y) = xnor();
+        let history: History = nn.training(x, y, None, 3, false);
+
+        let norms: Vec<f64> = pre_clip.lock().unwrap().clone();
+        assert!(norms.len() == 6 && norms.iter().all(|norm| *norm > 0.01));
+        let epoch_norm: f64 = history.epochs[0].metric("gradient_norm").unwrap();
+        assert!((epoch_norm - (norms[0] + norms[1]) / 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_clipping_by_value() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.parameters.weights[1] = Vector2D::new(vec![8., -8., 8.], [3, 1]);
+        nn.hyperparameters.clipping = Some(GradientClipping::Value(1e-3));
+        let (x, y) = xnor();
+        nn.forward(&x);
+        nn.backward(&y);
+        let unclipped: Vec<Vector2D> = nn.gradients.weights.clone();
+        let norm: f64 = nn.clip_gradients();
+        assert!(norm > 1e-3);
+        for (clipped, unclipped) in nn.gradients.weights.iter().zip(unclipped.iter()) {
+            for (c, u) in clipped.values.iter().zip(unclipped.values.iter()) {
+                assert!(*c == u.clamp(-1e-3, 1e-3));
+            }
+        }
+        // without clipping the norm is still reported but nothing changes
+        nn.hyperparameters.clipping = None;
+        let gradients: Vec<Vector2D> = nn.gradients.weights.clone();
+        nn.clip_gradients();
+        assert!(nn.gradients.weights == gradients);
+    }
+
+    fn saturated() -> NeuralNetwork {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        // predictions of exactly 0 give ln(0) terms, so the loss is not finite
+        nn.parameters.weights[1] = Vector2D::new(vec![-1e6; 3], [3, 1]);
+        nn
+    }
+
+    #[test]
+    fn test_guard_aborts_with_error() {
+        let mut nn: NeuralNetwork = saturated();
+        nn.guard = Some(NonFiniteGuard::new(NonFiniteAction::Abort));
+        nn.eval();
+        let weights: Vec<Vector2D> = nn.parameters.weights.clone();
+        let (x, y) = xnor();
+        let error: NonFiniteError = match nn.try_training(x, y, None, 5, false) {
+            Err(TrainingError::NonFinite(error)) => error,
+            _ => panic!("expected a non-finite error"),
+        };
+        assert!(error.source == "loss" && error.epoch == 0 && error.batch == 0);
+        assert!(error.value.is_nan());
+        // nothing was updated and the network is left as it was
+        assert!(nn.parameters.weights == weights);
+        assert!(nn.mode == Mode::Eval);
+    }
+
+    #[test]
+    #[should_panic(expected = "Non-finite loss (NaN) in epoch 0, batch 0.")]
+    fn test_guarded_training_panics() {
+        let mut nn: NeuralNetwork = saturated();
+        nn.guard = Some(NonFiniteGuard::new(NonFiniteAction::Abort));
+        let (x, y) = xnor();
+        nn.training(x, y, None, 5, false);
+    }
+
+    #[test]
+    fn test_non_finite_gradient_names_layer() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        let (x, y) = xnor();
+        nn.forward(&x);
+        nn.backward(&y);
+        assert!(nn.find_non_finite(0.5).is_none());
+        nn.gradients.biases[1].values[0] = f64::NAN;
+        let (source, value) = nn.find_non_finite(0.5).unwrap();
+        assert!(source == "bias gradient of layer 1" && value.is_nan());
+    }
+
+    #[test]
+    fn test_guard_skips_batches() {
+        // one sample is broken, so exactly one batch of every epoch is left out
+        let (mut x, y) = xnor();
+        x.values[2] = f64::NAN;
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.batch_size = Some(2);
+        nn.guard = Some(NonFiniteGuard::new(NonFiniteAction::SkipBatch));
+        let history: History = nn.training(x, y, None, 4, false);
+        assert!(nn.guard.as_ref().unwrap().skipped_batches == 4);
+        assert!(history.epochs.iter().all(|record| record.loss.is_finite()));
+        assert!(nn.parameters.weights.iter().all(|w| first_non_finite(w).is_none()));
+    }
+
+    #[test]
+    fn test_epoch_without_finite_batch_ends_training() {
+        let (mut x, y) = xnor();
+        x.values = vec![f64::NAN; 8];
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.batch_size = Some(2);
+        nn.hyperparameters.clipping = Some(GradientClipping::Norm(1.));
+        nn.guard = Some(NonFiniteGuard::new(NonFiniteAction::SkipBatch));
+        let error: TrainingError = nn.try_training(x, y, None, 3, false).unwrap_err();
+        assert!(error.to_string() == "Every batch of epoch 0 was non-finite and left out.");
+        assert!(nn.guard.as_ref().unwrap().skipped_batches == 2);
+        assert!(nn.state.epoch == 0 && nn.state.previous_loss.is_none());
+    }
+
+    #[test]
+    fn test_guard_rolls_back_exploding_update() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.learning_rate = 1e300;
+        nn.guard = Some(NonFiniteGuard::new(NonFiniteAction::Rollback));
+        let weights: Vec<Vector2D> = nn.parameters.weights.clone();
+        let (x, y) = xnor();
+        // the first update saturates the network, the second epoch undoes it and has no batch left
+        let error: TrainingError = nn.try_training(x, y, None, 2, false).unwrap_err();
+        assert!(matches!(error, TrainingError::NoFiniteBatch { epoch: 1 }));
+        assert!(nn.guard.as_ref().unwrap().skipped_batches == 1);
+        assert!(nn.parameters.weights == weights);
+    }
+
+    // Scales its input by a single weight; the gradient of that weight is NaN from the given
+    // backward call on.
+    struct Poisoned {
+        weight: Vector2D,
+        gradient: Vector2D,
+        input: Vector2D,
+        calls: usize,
+        poisoned_from: usize,
+    }
+
+    impl Layer for Poisoned {
+        fn name(&self) -> &'static str {
+            "poisoned"
+        }
+
+        fn forward(&mut self, input: &Vector2D, _training: bool) -> Vector2D {
+            self.input = input.clone();
+            self.predict(input)
+        }
+
+        fn predict(&self, input: &Vector2D) -> Vector2D {
+            input * self.weight.values[0]
+        }
+
+        fn backward(&mut self, gradient: &Vector2D) -> Vector2D {
+            let value: f64 = if self.calls >= self.poisoned_from { f64::NAN } else { (gradient * &self.input).overall_mean() };
+            self.gradient = Vector2D::new(vec![value], [1, 1]);
+            self.calls += 1;
+            gradient * self.weight.values[0]
         }
+
+        fn parameters(&self) -> Vec<&Vector2D> {
+            vec![&self.weight]
+        }
+
+        fn gradients(&self) -> Vec<&Vector2D> {
+            vec![&self.gradient]
+        }
+
+        fn parameters_and_gradients(&mut self) -> (Vec<&mut Vector2D>, Vec<&Vector2D>) {
+            (vec![&mut self.weight], vec![&self.gradient])
+        }
+    }
+
+    #[test]
+    fn test_guard_rolls_back_layer_parameters() {
+        let mut nn: NeuralNetwork = NeuralNetwork::with_optimizer(vec![2, 3, 1], 0.5, Box::new(Adam::new()));
+        nn.add_layer(0, Box::new(Poisoned {
+            weight: Vector2D::new(vec![1.], [1, 1]), gradient: Vector2D::zeros([1, 1]), input: Vector2D::default(), calls: 0, poisoned_from: 1,
+        }));
+        nn.hyperparameters.batch_size = Some(2);
+        nn.guard = Some(NonFiniteGuard::new(NonFiniteAction::Rollback));
+        let initial: Vec<Vector2D> = nn.layer_parameters();
+        let weights: Vec<Vector2D> = nn.parameters.weights.clone();
+        let (x, y) = xnor();
+        // the first batch updates every parameter, the NaN of the second rolls all of it back
+        nn.training(x, y, None, 1, false);
+        assert!(nn.guard.as_ref().unwrap().skipped_batches == 1);
+        assert!(nn.layer_parameters() == initial);
+        assert!(nn.parameters.weights == weights);
+        assert!(nn.optimizer.state().steps == 0);
     }
-}
\ No newline at end of file
+}
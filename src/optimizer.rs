@@ -0,0 +1,326 @@
+// This file contains optimizers that update the parameters of the neural network given their gradients
+use crate::vectors::models::Vector2D;
+
+// Parameters and gradients are handed over in the same, stable order on every step
+// (weights[0], biases[0], weights[1], biases[1], ...), so the optimizers can keep their
+// per-parameter state (velocity, moments, ...) by position.
+pub trait Optimizer: Send + Sync {
+    fn step(&mut self, learning_rate: f64, parameters: Vec<&mut Vector2D>, gradients: Vec<&Vector2D>);
+    fn state(&self) -> OptimizerState;
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), String>;
+
+    // Decoupled weight decay the optimizer applies itself (AdamW), so the network does not
+    // decay the parameters a second time.
+    fn weight_decay(&self) -> f64 {
+        0.
+    }
+}
+
+// Everything an optimizer needs to continue exactly where it stopped: the number of steps
+// taken so far and its per-parameter buffers (one slot per kind of buffer).
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptimizerState {
+    pub name: String,
+    pub steps: usize,
+    pub slots: Vec<Vec<Vector2D>>,
+}
+
+impl OptimizerState {
+    fn check(&self, name: &str, slots: usize) -> Result<(), String> {
+        if self.name != name {
+            return Err(format!("Can not load the state of optimizer {} into optimizer {}", self.name, name));
+        }
+        if self.slots.len() != slots {
+            return Err(format!("Optimizer {} expects {} state slots but got {}", name, slots, self.slots.len()));
+        }
+        Ok(())
+    }
+}
+
+fn initialize_state(state: &mut Vec<Vector2D>, parameters: &[&mut Vector2D]) {
+    if state.len() != parameters.len() {
+        *state = parameters.iter().map(|p| Vector2D::zeros(p.shape)).collect();
+    }
+}
+
+pub struct Sgd {
+    pub momentum: f64,
+    pub nesterov: bool,
+    velocity: Vec<Vector2D>,
+}
+
+impl Sgd {
+    pub fn new() -> Sgd {
+        Sgd::with_momentum(0.)
+    }
+
+    pub fn with_momentum(momentum: f64) -> Sgd {
+        Sgd { momentum, nesterov: false, velocity: vec![] }
+    }
+
+    pub fn nesterov(momentum: f64) -> Sgd {
+        Sgd { momentum, nesterov: true, velocity: vec![] }
+    }
+}
+
+impl Default for Sgd {
+    fn default() -> Self {
+        Sgd::new()
+    }
+}
+
+impl Optimizer for Sgd {
+    fn step(&mut self, learning_rate: f64, mut parameters: Vec<&mut Vector2D>, gradients: Vec<&Vector2D>) {
+        initialize_state(&mut self.velocity, &parameters);
+        for (idx, gradient) in gradients.iter().enumerate() {
+            self.velocity[idx] = self.momentum * &self.velocity[idx] + *gradient;
+            let direction: Vector2D = if self.nesterov {
+                *gradient + self.momentum * &self.velocity[idx]
+            } else {
+                self.velocity[idx].clone()
+            };
+            *parameters[idx] = &*parameters[idx] - learning_rate * direction;
+        }
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState { name: "sgd".to_string(), steps: 0, slots: vec![self.velocity.clone()] }
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), String> {
+        state.check("sgd", 1)?;
+        self.velocity = state.slots[0].clone();
+        Ok(())
+    }
+}
+
+pub struct Adam {
+    pub beta_1: f64,
+    pub beta_2: f64,
+    pub epsilon: f64,
+    // decoupled weight decay, only used by AdamW
+    pub weight_decay: f64,
+    t: usize,
+    m: Vec<Vector2D>,
+    v: Vec<Vector2D>,
+}
+
+impl Adam {
+    pub fn new() -> Adam {
+        Adam { beta_1: 0.9, beta_2: 0.999, epsilon: 1e-8, weight_decay: 0., t: 0, m: vec![], v: vec![] }
+    }
+
+    pub fn adamw(weight_decay: f64) -> Adam {
+        Adam { weight_decay, ..Adam::new() }
+    }
+}
+
+impl Default for Adam {
+    fn default() -> Self {
+        Adam::new()
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self, learning_rate: f64, mut parameters: Vec<&mut Vector2D>, gradients: Vec<&Vector2D>) {
+        initialize_state(&mut self.m, &parameters);
+        initialize_state(&mut self.v, &parameters);
+        self.t += 1;
+        let correction_1: f64 = 1. - self.beta_1.powi(self.t as i32);
+        let correction_2: f64 = 1. - self.beta_2.powi(self.t as i32);
+
+        for (idx, gradient) in gradients.iter().enumerate() {
+            self.m[idx] = self.beta_1 * &self.m[idx] + (1. - self.beta_1) * *gradient;
+            self.v[idx] = self.beta_2 * &self.v[idx] + (1. - self.beta_2) * gradient.map(|g| g * g);
+
+            let m_hat: Vector2D = &self.m[idx] / correction_1;
+            let v_hat: Vector2D = &self.v[idx] / correction_2;
+            let epsilon: f64 = self.epsilon;
+            let direction: Vector2D = m_hat / v_hat.map(|v| v.sqrt() + epsilon);
+
+            if self.weight_decay != 0. {
+                *parameters[idx] = &*parameters[idx] - learning_rate * self.weight_decay * &*parameters[idx];
+            }
+            *parameters[idx] = &*parameters[idx] - learning_rate * direction;
+        }
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState { name: "adam".to_string(), steps: self.t, slots: vec![self.m.clone(), self.v.clone()] }
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), String> {
+        state.check("adam", 2)?;
+        self.t = state.steps;
+        self.m = state.slots[0].clone();
+        self.v = state.slots[1].clone();
+        Ok(())
+    }
+
+    fn weight_decay(&self) -> f64 {
+        self.weight_decay
+    }
+}
+
+pub struct RmsProp {
+    pub rho: f64,
+    pub epsilon: f64,
+    square_average: Vec<Vector2D>,
+}
+
+impl RmsProp {
+    pub fn new(rho: f64) -> RmsProp {
+        RmsProp { rho, epsilon: 1e-8, square_average: vec![] }
+    }
+}
+
+impl Default for RmsProp {
+    fn default() -> Self {
+        RmsProp::new(0.9)
+    }
+}
+
+impl Optimizer for RmsProp {
+    fn step(&mut self, learning_rate: f64, mut parameters: Vec<&mut Vector2D>, gradients: Vec<&Vector2D>) {
+        initialize_state(&mut self.square_average, &parameters);
+        for (idx, gradient) in gradients.iter().enumerate() {
+            self.square_average[idx] = self.rho * &self.square_average[idx] + (1. - self.rho) * gradient.map(|g| g * g);
+
+            let epsilon: f64 = self.epsilon;
+            let direction: Vector2D = *gradient / &self.square_average[idx].map(|s| s.sqrt() + epsilon);
+            *parameters[idx] = &*parameters[idx] - learning_rate * direction;
+        }
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState { name: "rmsprop".to_string(), steps: 0, slots: vec![self.square_average.clone()] }
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), String> {
+        state.check("rmsprop", 1)?;
+        self.square_average = state.slots[0].clone();
+        Ok(())
+    }
+}
+
+pub struct AdaGrad {
+    pub epsilon: f64,
+    square_sum: Vec<Vector2D>,
+}
+
+impl AdaGrad {
+    pub fn new() -> AdaGrad {
+        AdaGrad { epsilon: 1e-8, square_sum: vec![] }
+    }
+}
+
+impl Default for AdaGrad {
+    fn default() -> Self {
+        AdaGrad::new()
+    }
+}
+
+impl Optimizer for AdaGrad {
+    fn step(&mut self, learning_rate: f64, mut parameters: Vec<&mut Vector2D>, gradients: Vec<&Vector2D>) {
+        initialize_state(&mut self.square_sum, &parameters);
+        for (idx, gradient) in gradients.iter().enumerate() {
+            self.square_sum[idx] = &self.square_sum[idx] + gradient.map(|g| g * g);
+
+            let epsilon: f64 = self.epsilon;
+            let direction: Vector2D = *gradient / &self.square_sum[idx].map(|s| s.sqrt() + epsilon);
+            *parameters[idx] = &*parameters[idx] - learning_rate * direction;
+        }
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState { name: "adagrad".to_string(), steps: 0, slots: vec![self.square_sum.clone()] }
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), String> {
+        state.check("adagrad", 1)?;
+        self.square_sum = state.slots[0].clone();
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // minimizes f(p) = sum(p^2) whose gradient is 2p
+    fn minimize(optimizer: &mut dyn Optimizer, learning_rate: f64, steps: usize) -> Vector2D {
+        let mut p: Vector2D = Vector2D::new(vec![1., -2., 3., -4.], [2, 2]);
+        for _ in 0..steps {
+            let g: Vector2D = 2. * &p;
+            optimizer.step(learning_rate, vec![&mut p], vec![&g]);
+        }
+        p
+    }
+
+    fn norm(v: &Vector2D) -> f64 {
+        v.values.iter().map(|x| x * x).sum::<f64>().sqrt()
+    }
+
+    #[test]
+    fn test_sgd() {
+        let p: Vector2D = minimize(&mut Sgd::new(), 0.25, 1);
+        assert!(p.values == vec![0.5, -1., 1.5, -2.]);
+    }
+
+    #[test]
+    fn test_all_optimizers_converge() {
+        let initial: f64 = norm(&Vector2D::new(vec![1., -2., 3., -4.], [2, 2]));
+        let mut optimizers: Vec<(Box<dyn Optimizer>, f64)> = vec![
+            (Box::new(Sgd::new()), 0.1),
+            (Box::new(Sgd::with_momentum(0.9)), 0.01),
+            (Box::new(Sgd::nesterov(0.9)), 0.01),
+            (Box::new(Adam::new()), 0.1),
+            (Box::new(Adam::adamw(0.01)), 0.1),
+            (Box::new(RmsProp::default()), 0.05),
+            (Box::new(AdaGrad::new()), 0.5),
+        ];
+        for (optimizer, learning_rate) in optimizers.iter_mut() {
+            let p: Vector2D = minimize(optimizer.as_mut(), *learning_rate, 200);
+            assert!(norm(&p) < 0.1 * initial);
+        }
+    }
+
+    #[test]
+    fn test_adam_first_step_is_learning_rate() {
+        // bias correction makes the very first Adam step roughly lr * sign(gradient)
+        let p: Vector2D = minimize(&mut Adam::new(), 0.1, 1);
+        let expected: Vec<f64> = vec![0.9, -1.9, 2.9, -3.9];
+        for (a, b) in p.values.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_momentum_accumulates_velocity() {
+        let mut p: Vector2D = Vector2D::new(vec![0.], [1, 1]);
+        let g: Vector2D = Vector2D::new(vec![1.], [1, 1]);
+        let mut optimizer: Sgd = Sgd::with_momentum(0.5);
+        optimizer.step(1., vec![&mut p], vec![&g]);
+        assert!(p[0] == -1.);
+        optimizer.step(1., vec![&mut p], vec![&g]);
+        assert!(p[0] == -2.5);
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let mut p: Vector2D = Vector2D::new(vec![1., 2.], [1, 2]);
+        let g: Vector2D = Vector2D::new(vec![0.5, -0.5], [1, 2]);
+        let mut optimizer: Adam = Adam::new();
+        optimizer.step(0.1, vec![&mut p], vec![&g]);
+
+        let mut restored: Adam = Adam::new();
+        restored.load_state(&optimizer.state()).unwrap();
+        let mut q: Vector2D = p.clone();
+        optimizer.step(0.1, vec![&mut p], vec![&g]);
+        restored.step(0.1, vec![&mut q], vec![&g]);
+        assert!(p.values == q.values);
+
+        assert!(Sgd::new().load_state(&optimizer.state()).is_err());
+    }
+}
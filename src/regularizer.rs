@@ -0,0 +1,71 @@
+// This file contains weight penalties that are added to the loss and its gradient.
+use crate::vectors::models::Vector2D;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Regularizer {
+    // l1 * sum(|w|)
+    L1(f64),
+    // l2 * sum(w^2)
+    L2(f64),
+    ElasticNet { l1: f64, l2: f64 },
+}
+
+impl Regularizer {
+    fn factors(&self) -> (f64, f64) {
+        match *self {
+            Regularizer::L1(l1) => (l1, 0.),
+            Regularizer::L2(l2) => (0., l2),
+            Regularizer::ElasticNet { l1, l2 } => (l1, l2),
+        }
+    }
+
+    pub fn penalty(&self, w: &Vector2D) -> f64 {
+        let (l1, l2) = self.factors();
+        w.values.iter().map(|v| l1 * v.abs() + l2 * v * v).sum()
+    }
+
+    // The subgradient of |w| is taken as 0 at w = 0.
+    pub fn gradient(&self, w: &Vector2D) -> Vector2D {
+        let (l1, l2) = self.factors();
+        w.map(|v| {
+            let sign: f64 = if v > 0. { 1. } else if v < 0. { -1. } else { 0. };
+            l1 * sign + 2. * l2 * v
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_penalties() {
+        let w: Vector2D = Vector2D::new(vec![-2., 0., 1., 3.], [2, 2]);
+        assert!(Regularizer::L1(0.5).penalty(&w) == 3.);
+        assert!(Regularizer::L2(0.5).penalty(&w) == 7.);
+        assert!(Regularizer::ElasticNet { l1: 0.5, l2: 0.5 }.penalty(&w) == 10.);
+    }
+
+    #[test]
+    fn test_gradients() {
+        let w: Vector2D = Vector2D::new(vec![-2., 0., 1., 3.], [2, 2]);
+        assert!(Regularizer::L1(0.5).gradient(&w).values == vec![-0.5, 0., 0.5, 0.5]);
+        assert!(Regularizer::L2(0.5).gradient(&w).values == vec![-2., 0., 1., 3.]);
+        assert!(Regularizer::ElasticNet { l1: 0.5, l2: 0.5 }.gradient(&w).values == vec![-2.5, 0., 1.5, 3.5]);
+
+        // the gradient matches a central difference of the penalty away from 0
+        let regularizer: Regularizer = Regularizer::ElasticNet { l1: 0.3, l2: 0.2 };
+        let eps: f64 = 1e-6;
+        for idx in 0..w.values.len() {
+            if w[idx] == 0. {
+                continue;
+            }
+            let (mut plus, mut minus) = (w.clone(), w.clone());
+            plus.values[idx] += eps;
+            minus.values[idx] -= eps;
+            let numeric: f64 = (regularizer.penalty(&plus) - regularizer.penalty(&minus)) / (2. * eps);
+            assert!((numeric - regularizer.gradient(&w)[idx]).abs() < 1e-6);
+        }
+    }
+}
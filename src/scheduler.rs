@@ -0,0 +1,300 @@
+// This file contains learning rate schedulers that adapt the learning rate during training
+use std::f64::consts::PI;
+
+// Queried once at the beginning of every epoch with the base learning rate
+// (HyperParameters::learning_rate) and the monitored loss of the previous epoch, if any.
+pub trait LrScheduler: Send + Sync {
+    fn learning_rate(&mut self, epoch: usize, base_learning_rate: f64, metric: Option<f64>) -> f64;
+
+    // Schedulers that only depend on the epoch have no state of their own.
+    fn state(&self) -> Vec<f64> {
+        vec![]
+    }
+
+    fn load_state(&mut self, _state: &[f64]) {}
+}
+
+// Multiplies the learning rate by gamma every step_size epochs. Panics if step_size is 0.
+pub struct StepDecay {
+    pub step_size: usize,
+    pub gamma: f64,
+}
+
+impl StepDecay {
+    pub fn new(step_size: usize, gamma: f64) -> StepDecay {
+        if step_size == 0 {
+            panic!("StepDecay needs a step_size of at least 1 epoch");
+        }
+        StepDecay { step_size, gamma }
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn learning_rate(&mut self, epoch: usize, base_learning_rate: f64, _metric: Option<f64>) -> f64 {
+        base_learning_rate * self.gamma.powi((epoch / self.step_size) as i32)
+    }
+}
+
+// Multiplies the learning rate by gamma every epoch.
+pub struct ExponentialDecay {
+    pub gamma: f64,
+}
+
+impl ExponentialDecay {
+    pub fn new(gamma: f64) -> ExponentialDecay {
+        ExponentialDecay { gamma }
+    }
+}
+
+impl LrScheduler for ExponentialDecay {
+    fn learning_rate(&mut self, epoch: usize, base_learning_rate: f64, _metric: Option<f64>) -> f64 {
+        base_learning_rate * self.gamma.powi(epoch as i32)
+    }
+}
+
+// Cosine annealing with warm restarts (SGDR). The first cycle lasts period epochs and
+// every following cycle is period_multiplier times longer than the one before. Panics if
+// period is 0.
+pub struct CosineAnnealing {
+    pub period: usize,
+    pub period_multiplier: usize,
+    pub min_learning_rate: f64,
+}
+
+impl CosineAnnealing {
+    pub fn new(period: usize, min_learning_rate: f64) -> CosineAnnealing {
+        CosineAnnealing::with_restarts(period, 1, min_learning_rate)
+    }
+
+    pub fn with_restarts(period: usize, period_multiplier: usize, min_learning_rate: f64) -> CosineAnnealing {
+        if period == 0 {
+            panic!("CosineAnnealing needs a period of at least 1 epoch");
+        }
+        CosineAnnealing { period, period_multiplier, min_learning_rate }
+    }
+}
+
+impl LrScheduler for CosineAnnealing {
+    fn learning_rate(&mut self, epoch: usize, base_learning_rate: f64, _metric: Option<f64>) -> f64 {
+        let mut position: usize = epoch;
+        let mut period: usize = self.period;
+        while position >= period {
+            position -= period;
+            period *= self.period_multiplier.max(1);
+        }
+        let progress: f64 = position as f64 / period as f64;
+        self.min_learning_rate + 0.5 * (base_learning_rate - self.min_learning_rate) * (1. + (PI * progress).cos())
+    }
+}
+
+// Linearly increases the learning rate during the first warmup_epochs and then hands over
+// to the wrapped scheduler (or keeps the base learning rate if there is none).
+pub struct LinearWarmup {
+    pub warmup_epochs: usize,
+    pub after: Option<Box<dyn LrScheduler>>,
+}
+
+impl LinearWarmup {
+    pub fn new(warmup_epochs: usize) -> LinearWarmup {
+        LinearWarmup { warmup_epochs, after: None }
+    }
+
+    pub fn then(warmup_epochs: usize, after: Box<dyn LrScheduler>) -> LinearWarmup {
+        LinearWarmup { warmup_epochs, after: Some(after) }
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    fn learning_rate(&mut self, epoch: usize, base_learning_rate: f64, metric: Option<f64>) -> f64 {
+        if epoch < self.warmup_epochs {
+            return base_learning_rate * (epoch + 1) as f64 / self.warmup_epochs as f64;
+        }
+        match &mut self.after {
+            Some(scheduler) => scheduler.learning_rate(epoch - self.warmup_epochs, base_learning_rate, metric),
+            None => base_learning_rate,
+        }
+    }
+
+    fn state(&self) -> Vec<f64> {
+        match &self.after {
+            Some(scheduler) => scheduler.state(),
+            None => vec![],
+        }
+    }
+
+    fn load_state(&mut self, state: &[f64]) {
+        if let Some(scheduler) = &mut self.after {
+            scheduler.load_state(state);
+        }
+    }
+}
+
+// One-cycle policy: the base learning rate is the peak. The rate rises from
+// base / div_factor to the peak during the first pct_start of total_epochs and then
+// anneals down to base / (div_factor * final_div_factor).
+pub struct OneCycle {
+    pub total_epochs: usize,
+    pub pct_start: f64,
+    pub div_factor: f64,
+    pub final_div_factor: f64,
+}
+
+impl OneCycle {
+    pub fn new(total_epochs: usize) -> OneCycle {
+        OneCycle { total_epochs, pct_start: 0.3, div_factor: 25., final_div_factor: 1e4 }
+    }
+}
+
+fn cosine_interpolation(start: f64, end: f64, progress: f64) -> f64 {
+    end + 0.5 * (start - end) * (1. + (PI * progress.clamp(0., 1.)).cos())
+}
+
+impl LrScheduler for OneCycle {
+    fn learning_rate(&mut self, epoch: usize, base_learning_rate: f64, _metric: Option<f64>) -> f64 {
+        let initial: f64 = base_learning_rate / self.div_factor;
+        let last: f64 = initial / self.final_div_factor;
+        let warmup_epochs: f64 = (self.pct_start * self.total_epochs as f64).max(1.);
+        let epoch: f64 = epoch as f64;
+
+        if epoch < warmup_epochs {
+            cosine_interpolation(initial, base_learning_rate, epoch / warmup_epochs)
+        } else {
+            let remaining: f64 = (self.total_epochs as f64 - warmup_epochs).max(1.);
+            cosine_interpolation(base_learning_rate, last, (epoch - warmup_epochs) / remaining)
+        }
+    }
+}
+
+// Multiplies the learning rate by factor once the monitored loss did not improve by more
+// than min_delta for patience epochs.
+pub struct ReduceOnPlateau {
+    pub factor: f64,
+    pub patience: usize,
+    pub min_delta: f64,
+    pub min_learning_rate: f64,
+    scale: f64,
+    best: f64,
+    wait: usize,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(factor: f64, patience: usize) -> ReduceOnPlateau {
+        ReduceOnPlateau { factor, patience, min_delta: 0., min_learning_rate: 0., scale: 1., best: f64::INFINITY, wait: 0 }
+    }
+}
+
+impl LrScheduler for ReduceOnPlateau {
+    fn learning_rate(&mut self, _epoch: usize, base_learning_rate: f64, metric: Option<f64>) -> f64 {
+        if let Some(loss) = metric {
+            if loss < self.best - self.min_delta {
+                self.best = loss;
+                self.wait = 0;
+            } else {
+                self.wait += 1;
+                if self.wait > self.patience {
+                    self.scale *= self.factor;
+                    self.wait = 0;
+                }
+            }
+        }
+        (base_learning_rate * self.scale).max(self.min_learning_rate)
+    }
+
+    fn state(&self) -> Vec<f64> {
+        vec![self.scale, self.best, self.wait as f64]
+    }
+
+    fn load_state(&mut self, state: &[f64]) {
+        if let [scale, best, wait] = state {
+            self.scale = *scale;
+            self.best = *best;
+            self.wait = *wait as usize;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_step_decay() {
+        let mut s: StepDecay = StepDecay::new(10, 0.5);
+        assert!(close(s.learning_rate(0, 1., None), 1.));
+        assert!(close(s.learning_rate(9, 1., None), 1.));
+        assert!(close(s.learning_rate(10, 1., None), 0.5));
+        assert!(close(s.learning_rate(25, 1., None), 0.25));
+    }
+
+    #[test]
+    #[should_panic(expected = "step_size of at least 1 epoch")]
+    fn test_step_decay_rejects_zero_step_size() {
+        StepDecay::new(0, 0.5);
+    }
+
+    #[test]
+    fn test_exponential_decay() {
+        let mut s: ExponentialDecay = ExponentialDecay::new(0.9);
+        assert!(close(s.learning_rate(0, 2., None), 2.));
+        assert!(close(s.learning_rate(2, 2., None), 1.62));
+    }
+
+    #[test]
+    fn test_cosine_annealing_with_restarts() {
+        let mut s: CosineAnnealing = CosineAnnealing::with_restarts(10, 2, 0.);
+        assert!(close(s.learning_rate(0, 1., None), 1.));
+        assert!(close(s.learning_rate(5, 1., None), 0.5));
+        // first restart after 10 epochs, second cycle is 20 epochs long
+        assert!(close(s.learning_rate(10, 1., None), 1.));
+        assert!(close(s.learning_rate(20, 1., None), 0.5));
+        assert!(close(s.learning_rate(30, 1., None), 1.));
+    }
+
+    #[test]
+    #[should_panic(expected = "period of at least 1 epoch")]
+    fn test_cosine_annealing_rejects_zero_period() {
+        CosineAnnealing::new(0, 0.);
+    }
+
+    #[test]
+    fn test_linear_warmup() {
+        let mut s: LinearWarmup = LinearWarmup::then(4, Box::new(StepDecay::new(1, 0.5)));
+        assert!(close(s.learning_rate(0, 1., None), 0.25));
+        assert!(close(s.learning_rate(3, 1., None), 1.));
+        assert!(close(s.learning_rate(4, 1., None), 1.));
+        assert!(close(s.learning_rate(5, 1., None), 0.5));
+    }
+
+    #[test]
+    fn test_one_cycle() {
+        let mut s: OneCycle = OneCycle::new(10);
+        assert!(close(s.learning_rate(0, 1., None), 1. / 25.));
+        assert!(close(s.learning_rate(3, 1., None), 1.));
+        assert!(s.learning_rate(10, 1., None) < 1e-5);
+    }
+
+    #[test]
+    fn test_reduce_on_plateau() {
+        let mut s: ReduceOnPlateau = ReduceOnPlateau::new(0.1, 1);
+        assert!(close(s.learning_rate(0, 1., None), 1.));
+        assert!(close(s.learning_rate(1, 1., Some(1.)), 1.));
+        assert!(close(s.learning_rate(2, 1., Some(1.)), 1.));
+        assert!(close(s.learning_rate(3, 1., Some(1.)), 0.1));
+        assert!(close(s.learning_rate(4, 1., Some(0.5)), 0.1));
+    }
+
+    #[test]
+    fn test_reduce_on_plateau_state() {
+        let mut s: ReduceOnPlateau = ReduceOnPlateau::new(0.1, 0);
+        s.learning_rate(0, 1., Some(1.));
+        s.learning_rate(1, 1., Some(1.));
+        let mut restored: ReduceOnPlateau = ReduceOnPlateau::new(0.1, 0);
+        restored.load_state(&s.state());
+        assert!(close(restored.learning_rate(2, 1., Some(1.)), s.learning_rate(2, 1., Some(1.))));
+    }
+}
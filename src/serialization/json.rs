@@ -0,0 +1,338 @@
+// This file holds a minimal JSON value type with a writer and a parser, just enough to
+// store models, checkpoints and training histories in a human readable format.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // Non-finite numbers are not valid JSON and are written as null, which is not a number
+    // here: fields that may be non-finite have to handle null themselves.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0. && n.fract() == 0. => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn to_string_pretty(&self) -> String {
+        let mut out: String = String::new();
+        self.write(&mut out, 0);
+        out
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Number(n) => {
+                if n.is_finite() {
+                    out.push_str(&format!("{:?}", n));
+                } else {
+                    out.push_str("null");
+                }
+            },
+            Json::String(s) => write_string(s, out),
+            Json::Array(values) => {
+                // arrays of numbers (the parameters) are kept on a single line
+                if values.iter().all(|v| matches!(v, Json::Number(_) | Json::Null)) {
+                    out.push('[');
+                    for (idx, value) in values.iter().enumerate() {
+                        if idx > 0 {
+                            out.push_str(", ");
+                        }
+                        value.write(out, indent);
+                    }
+                    out.push(']');
+                    return;
+                }
+                out.push_str("[\n");
+                for (idx, value) in values.iter().enumerate() {
+                    out.push_str(&"  ".repeat(indent + 1));
+                    value.write(out, indent + 1);
+                    if idx + 1 < values.len() {
+                        out.push(',');
+                    }
+                    out.push('\n');
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push(']');
+            },
+            Json::Object(entries) => {
+                out.push_str("{\n");
+                for (idx, (key, value)) in entries.iter().enumerate() {
+                    out.push_str(&"  ".repeat(indent + 1));
+                    write_string(key, out);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                    if idx + 1 < entries.len() {
+                        out.push(',');
+                    }
+                    out.push('\n');
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push('}');
+            },
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser: Parser = Parser { chars: text.chars().collect(), position: 0 };
+        let value: Json = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.chars.len() {
+            return Err(format!("Unexpected trailing characters at position {}", parser.position));
+        }
+        Ok(value)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Json {
+        Json::Number(n)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<Vec<f64>> for Json {
+    fn from(values: Vec<f64>) -> Json {
+        Json::Array(values.into_iter().map(Json::Number).collect())
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.chars.len() && self.chars[self.position].is_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.position).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at position {}", c, self.position))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        let end: usize = self.position + word.len();
+        if end <= self.chars.len() && self.chars[self.position..end].iter().collect::<String>() == word {
+            self.position = end;
+            Ok(value)
+        } else {
+            Err(format!("Unexpected character at position {}", self.position))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(format!("Unexpected character '{}' at position {}", c, self.position)),
+            None => Err("Unexpected end of input".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut entries: Vec<(String, Json)> = vec![];
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            if self.peek() != Some('"') {
+                return Err(format!("Expected object key at position {}", self.position));
+            }
+            let key: String = self.string()?;
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(entries));
+                },
+                _ => return Err(format!("Expected ',' or '}}' at position {}", self.position)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut values: Vec<Json> = vec![];
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                },
+                _ => return Err(format!("Expected ',' or ']' at position {}", self.position)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s: String = String::new();
+        while let Some(&c) = self.chars.get(self.position) {
+            self.position += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped: char = *self.chars.get(self.position).ok_or("Unterminated escape sequence")?;
+                    self.position += 1;
+                    match escaped {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let end: usize = self.position + 4;
+                            if end > self.chars.len() {
+                                return Err("Unterminated unicode escape".to_string());
+                            }
+                            let hex: String = self.chars[self.position..end].iter().collect();
+                            let code: u32 = u32::from_str_radix(&hex, 16).map_err(|e| e.to_string())?;
+                            s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                            self.position = end;
+                        },
+                        other => s.push(other),
+                    }
+                },
+                c => s.push(c),
+            }
+        }
+        Err("Unterminated string".to_string())
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start: usize = self.position;
+        while self.position < self.chars.len() && matches!(self.chars[self.position], '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse::<f64>()
+            .map(Json::Number)
+            .map_err(|_| format!("Invalid number '{}' at position {}", text, start))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let value: Json = Json::Object(vec![
+            ("name".to_string(), Json::from("xnor \"net\"\n")),
+            ("version".to_string(), Json::from(1usize)),
+            ("values".to_string(), Json::from(vec![0.1, -2.5e-8, 3.])),
+            ("nested".to_string(), Json::Array(vec![Json::Bool(true), Json::Null, Json::Object(vec![])])),
+        ]);
+        let parsed: Json = Json::parse(&value.to_string_pretty()).unwrap();
+        assert!(parsed == value);
+        assert!(parsed.get("version").unwrap().as_usize() == Some(1));
+        assert!(parsed.get("values").unwrap().as_array().unwrap()[1].as_f64() == Some(-2.5e-8));
+    }
+
+    #[test]
+    fn test_non_finite_numbers_are_written_as_null() {
+        let value: Json = Json::from(vec![f64::NAN, f64::INFINITY]);
+        assert!(value.to_string_pretty() == "[null, null]");
+        assert!(Json::Null.as_f64().is_none());
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("{} x").is_err());
+    }
+}
@@ -0,0 +1,449 @@
+// This file holds everything to save a trained neural network to disk and load it again.
+// Models are either written as (human readable) JSON or in a compact little-endian
+// binary format, both carry a format version and the full architecture.
+pub mod json;
+
+use std::{fmt, fs, path::Path};
+use crate::{activation::Activation, loss::Loss, neuralnetwork::{HyperParameters, NeuralNetwork}, vectors::models::Vector2D};
+use json::Json;
+
+pub const FORMAT_VERSION: u32 = 1;
+const FORMAT_NAME: &str = "rust-network";
+const MAGIC: &[u8; 4] = b"RNNM";
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(std::io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+    ShapeMismatch { parameter: String, expected: [usize; 2], found: [usize; 2] },
+    // only dense layers are stored, a network with added layers would load as a different one
+    UnsupportedLayer(String),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "I/O error: {}", e),
+            ModelError::Format(message) => write!(f, "Invalid model file: {}", message),
+            ModelError::UnsupportedVersion(version) => write!(f, "Unsupported model format version {} (supported: {})", version, FORMAT_VERSION),
+            ModelError::ShapeMismatch { parameter, expected, found } => {
+                write!(f, "Shape mismatch for {}: expected {:?} but found {:?}", parameter, expected, found)
+            },
+            ModelError::UnsupportedLayer(name) => write!(f, "Cannot save the added {} layer, only dense layers are stored", name),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<std::io::Error> for ModelError {
+    fn from(e: std::io::Error) -> ModelError {
+        ModelError::Io(e)
+    }
+}
+
+fn format_error<T>(message: &str) -> Result<T, ModelError> {
+    Err(ModelError::Format(message.to_string()))
+}
+
+// Checks that the stored parameters fit the stored architecture.
+fn validate(hyperparameters: &HyperParameters, weights: &[Vector2D], biases: &[Vector2D]) -> Result<(), ModelError> {
+    let shape: &Vec<usize> = &hyperparameters.shape;
+    if shape.len() < 2 {
+        return format_error("the network needs at least an input and an output layer");
+    }
+    if hyperparameters.activations.len() != shape.len() - 1 {
+        return format_error(&format!("expected {} activations but found {}", shape.len() - 1, hyperparameters.activations.len()));
+    }
+    if weights.len() != shape.len() - 1 || biases.len() != shape.len() - 1 {
+        return format_error(&format!("expected {} weight and bias matrices but found {} and {}", shape.len() - 1, weights.len(), biases.len()));
+    }
+    for layer in 0..shape.len() - 1 {
+        let expected: [usize; 2] = [shape[layer], shape[layer+1]];
+        if weights[layer].shape != expected {
+            return Err(ModelError::ShapeMismatch { parameter: format!("weights[{}]", layer), expected, found: weights[layer].shape });
+        }
+        let expected: [usize; 2] = [1, shape[layer+1]];
+        if biases[layer].shape != expected {
+            return Err(ModelError::ShapeMismatch { parameter: format!("biases[{}]", layer), expected, found: biases[layer].shape });
+        }
+    }
+    Ok(())
+}
+
+fn check_version(version: u32) -> Result<(), ModelError> {
+    if version != FORMAT_VERSION {
+        return Err(ModelError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+pub fn vector_to_json(v: &Vector2D) -> Json {
+    Json::Object(vec![
+        ("shape".to_string(), Json::Array(vec![Json::from(v.shape[0]), Json::from(v.shape[1])])),
+        ("values".to_string(), Json::from(v.values.clone())),
+    ])
+}
+
+pub fn vector_from_json(json: &Json, name: &str) -> Result<Vector2D, ModelError> {
+    let shape: Vec<usize> = json.get("shape").and_then(Json::as_array)
+        .and_then(|s| s.iter().map(Json::as_usize).collect::<Option<Vec<usize>>>())
+        .ok_or_else(|| ModelError::Format(format!("{} has no valid shape", name)))?;
+    let values: Vec<f64> = json.get("values").and_then(Json::as_array)
+        .and_then(|v| v.iter().map(Json::as_f64).collect::<Option<Vec<f64>>>())
+        .ok_or_else(|| ModelError::Format(format!("{} has no valid values", name)))?;
+    if shape.len() != 2 {
+        return format_error(&format!("{} has to be two dimensional", name));
+    }
+    let size: usize = shape[0].checked_mul(shape[1])
+        .ok_or_else(|| ModelError::Format(format!("{} has the impossible shape {:?}", name, shape)))?;
+    if values.len() != size {
+        return format_error(&format!("{} has shape {:?} but {} values", name, shape, values.len()));
+    }
+    Ok(Vector2D::new(values, [shape[0], shape[1]]))
+}
+
+fn vectors_from_json(json: Option<&Json>, name: &str) -> Result<Vec<Vector2D>, ModelError> {
+    let entries: &Vec<Json> = json.and_then(Json::as_array)
+        .ok_or_else(|| ModelError::Format(format!("missing {}", name)))?;
+    entries.iter().enumerate()
+        .map(|(idx, entry)| vector_from_json(entry, &format!("{}[{}]", name, idx)))
+        .collect()
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ModelError> {
+        if self.position + n > self.bytes.len() {
+            return format_error("unexpected end of file");
+        }
+        let slice: &'a [u8] = &self.bytes[self.position..self.position + n];
+        self.position += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, ModelError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ModelError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, ModelError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Result<String, ModelError> {
+        let length: usize = self.u8()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).or_else(|_| format_error("invalid name"))
+    }
+
+    fn vector(&mut self) -> Result<Vector2D, ModelError> {
+        let shape: [usize; 2] = [self.u32()? as usize, self.u32()? as usize];
+        let mut values: Vec<f64> = vec![];
+        for _ in 0..shape[0] * shape[1] {
+            values.push(self.f64()?);
+        }
+        Ok(Vector2D::new(values, shape))
+    }
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.push(name.len() as u8);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+fn write_vector(bytes: &mut Vec<u8>, v: &Vector2D) {
+    bytes.extend_from_slice(&(v.shape[0] as u32).to_le_bytes());
+    bytes.extend_from_slice(&(v.shape[1] as u32).to_le_bytes());
+    for value in &v.values {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+impl NeuralNetwork {
+    pub(crate) fn check_saveable(&self) -> Result<(), ModelError> {
+        match self.input_layers.iter().chain(self.extra_layers.iter().flatten()).next() {
+            Some(layer) => Err(ModelError::UnsupportedLayer(layer.name().to_string())),
+            None => Ok(()),
+        }
+    }
+
+    // JSON has no numbers for NaN and infinity, so networks with non-finite values are rejected
+    // instead of writing a file that can not be loaded.
+    fn check_finite(&self) -> Result<(), ModelError> {
+        if !self.hyperparameters.learning_rate.is_finite() {
+            return format_error(&format!("learning rate {} is not finite", self.hyperparameters.learning_rate));
+        }
+        let pairs = [("weights", &self.parameters.weights), ("biases", &self.parameters.biases)];
+        for (name, vectors) in pairs {
+            for (layer, v) in vectors.iter().enumerate() {
+                if let Some((idx, value)) = v.values.iter().enumerate().find(|(_, value)| !value.is_finite()) {
+                    return format_error(&format!("{}[{}] has the non-finite value {} at index {}", name, layer, value, idx));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<Json, ModelError> {
+        self.check_saveable()?;
+        self.check_finite()?;
+        let hyperparameters: &HyperParameters = &self.hyperparameters;
+        Ok(Json::Object(vec![
+            ("format".to_string(), Json::from(FORMAT_NAME)),
+            ("version".to_string(), Json::from(FORMAT_VERSION as usize)),
+            ("shape".to_string(), Json::Array(hyperparameters.shape.iter().map(|s| Json::from(*s)).collect())),
+            ("activations".to_string(), Json::Array(hyperparameters.activations.iter().map(|a| Json::from(a.name())).collect())),
+            ("loss".to_string(), Json::from(hyperparameters.loss.name())),
+            ("learning_rate".to_string(), Json::from(hyperparameters.learning_rate)),
+            ("weights".to_string(), Json::Array(self.parameters.weights.iter().map(vector_to_json).collect())),
+            ("biases".to_string(), Json::Array(self.parameters.biases.iter().map(vector_to_json).collect())),
+        ]))
+    }
+
+    pub fn from_json(json: &Json) -> Result<NeuralNetwork, ModelError> {
+        if json.get("format").and_then(Json::as_str) != Some(FORMAT_NAME) {
+            return format_error("not a rust-network model");
+        }
+        let version: usize = json.get("version").and_then(Json::as_usize)
+            .ok_or_else(|| ModelError::Format("missing version".to_string()))?;
+        check_version(version as u32)?;
+
+        let shape: Vec<usize> = json.get("shape").and_then(Json::as_array)
+            .and_then(|s| s.iter().map(Json::as_usize).collect::<Option<Vec<usize>>>())
+            .ok_or_else(|| ModelError::Format("missing shape".to_string()))?;
+        let activations: Vec<Activation> = json.get("activations").and_then(Json::as_array)
+            .and_then(|a| a.iter().map(|a| a.as_str().and_then(Activation::from_name)).collect::<Option<Vec<Activation>>>())
+            .ok_or_else(|| ModelError::Format("missing or unknown activations".to_string()))?;
+        let loss: Loss = json.get("loss").and_then(Json::as_str).and_then(Loss::from_name)
+            .ok_or_else(|| ModelError::Format("missing or unknown loss".to_string()))?;
+        let learning_rate: f64 = json.get("learning_rate").and_then(Json::as_f64)
+            .ok_or_else(|| ModelError::Format("missing learning rate".to_string()))?;
+        let weights: Vec<Vector2D> = vectors_from_json(json.get("weights"), "weights")?;
+        let biases: Vec<Vector2D> = vectors_from_json(json.get("biases"), "biases")?;
+
+        if shape.len() < 2 {
+            return format_error("the network needs at least an input and an output layer");
+        }
+        let mut hyperparameters: HyperParameters = HyperParameters::new(shape, learning_rate);
+        hyperparameters.activations = activations;
+        hyperparameters.loss = loss;
+        validate(&hyperparameters, &weights, &biases)?;
+        Ok(NeuralNetwork::from_parameters(hyperparameters, weights, biases))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ModelError> {
+        self.check_saveable()?;
+        let hyperparameters: &HyperParameters = &self.hyperparameters;
+        let mut bytes: Vec<u8> = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(hyperparameters.shape.len() as u32).to_le_bytes());
+        for size in &hyperparameters.shape {
+            bytes.extend_from_slice(&(*size as u32).to_le_bytes());
+        }
+        for activation in &hyperparameters.activations {
+            write_name(&mut bytes, activation.name());
+        }
+        write_name(&mut bytes, hyperparameters.loss.name());
+        bytes.extend_from_slice(&hyperparameters.learning_rate.to_le_bytes());
+        for (weights, biases) in self.parameters.weights.iter().zip(self.parameters.biases.iter()) {
+            write_vector(&mut bytes, weights);
+            write_vector(&mut bytes, biases);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<NeuralNetwork, ModelError> {
+        let mut reader: ByteReader = ByteReader { bytes, position: 0 };
+        if reader.take(4)? != MAGIC {
+            return format_error("not a rust-network model");
+        }
+        check_version(reader.u32()?)?;
+
+        let layers: usize = reader.u32()? as usize;
+        if layers < 2 {
+            return format_error("the network needs at least an input and an output layer");
+        }
+        let mut shape: Vec<usize> = vec![];
+        for _ in 0..layers {
+            shape.push(reader.u32()? as usize);
+        }
+        let mut activations: Vec<Activation> = vec![];
+        for _ in 0..layers - 1 {
+            let name: String = reader.name()?;
+            activations.push(Activation::from_name(&name).ok_or_else(|| ModelError::Format(format!("unknown activation {}", name)))?);
+        }
+        let name: String = reader.name()?;
+        let loss: Loss = Loss::from_name(&name).ok_or_else(|| ModelError::Format(format!("unknown loss {}", name)))?;
+        let learning_rate: f64 = reader.f64()?;
+
+        let mut weights: Vec<Vector2D> = vec![];
+        let mut biases: Vec<Vector2D> = vec![];
+        for _ in 0..layers - 1 {
+            weights.push(reader.vector()?);
+            biases.push(reader.vector()?);
+        }
+        if reader.position != bytes.len() {
+            return format_error("unexpected trailing bytes");
+        }
+
+        let mut hyperparameters: HyperParameters = HyperParameters::new(shape, learning_rate);
+        hyperparameters.activations = activations;
+        hyperparameters.loss = loss;
+        validate(&hyperparameters, &weights, &biases)?;
+        Ok(NeuralNetwork::from_parameters(hyperparameters, weights, biases))
+    }
+
+    // Files ending in .json are written as JSON, everything else in the binary format.
+    // Networks with added layers are rejected with ModelError::UnsupportedLayer, JSON files of
+    // networks with NaN or infinite values with ModelError::Format.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelError> {
+        let path: &Path = path.as_ref();
+        if path.extension().is_some_and(|e| e == "json") {
+            fs::write(path, self.to_json()?.to_string_pretty())?;
+        } else {
+            fs::write(path, self.to_bytes()?)?;
+        }
+        Ok(())
+    }
+
+    // The format is detected from the file content.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<NeuralNetwork, ModelError> {
+        let bytes: Vec<u8> = fs::read(path)?;
+        if bytes.starts_with(MAGIC) {
+            return NeuralNetwork::from_bytes(&bytes);
+        }
+        let text: String = String::from_utf8(bytes).or_else(|_| format_error("neither a binary nor a JSON model"))?;
+        let json: Json = Json::parse(&text).map_err(ModelError::Format)?;
+        NeuralNetwork::from_json(&json)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::{dropout::Dropout, pooling::Flatten};
+
+    fn trained_network() -> NeuralNetwork {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.hyperparameters.activations[0] = Activation::Tanh;
+        let input: Vector2D = Vector2D::new(vec![0., 0., 0., 1., 1., 0., 1., 1.], [4, 2]);
+        let output: Vector2D = Vector2D::new(vec![1., 0., 0., 1.], [4, 1]);
+        nn.training(input, output, None, 10, false);
+        nn
+    }
+
+    fn assert_same_network(a: &NeuralNetwork, b: &NeuralNetwork) {
+        assert!(a.hyperparameters.shape == b.hyperparameters.shape);
+        assert!(a.hyperparameters.activations == b.hyperparameters.activations);
+        assert!(a.hyperparameters.loss == b.hyperparameters.loss);
+        for layer in 0..a.parameters.weights.len() {
+            assert!(a.parameters.weights[layer].values == b.parameters.weights[layer].values);
+            assert!(a.parameters.biases[layer].values == b.parameters.biases[layer].values);
+        }
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let nn: NeuralNetwork = trained_network();
+        let text: String = nn.to_json().unwrap().to_string_pretty();
+        let loaded: NeuralNetwork = NeuralNetwork::from_json(&Json::parse(&text).unwrap()).unwrap();
+        assert_same_network(&nn, &loaded);
+    }
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let nn: NeuralNetwork = trained_network();
+        let loaded: NeuralNetwork = NeuralNetwork::from_bytes(&nn.to_bytes().unwrap()).unwrap();
+        assert_same_network(&nn, &loaded);
+    }
+
+    #[test]
+    fn test_save_and_load_files() {
+        let nn: NeuralNetwork = trained_network();
+        let directory = std::env::temp_dir();
+        for name in ["rust_network_test_model.json", "rust_network_test_model.bin"] {
+            let path = directory.join(name);
+            nn.save(&path).unwrap();
+            let loaded: NeuralNetwork = NeuralNetwork::load(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_same_network(&nn, &loaded);
+        }
+    }
+
+    #[test]
+    fn test_shape_mismatch() {
+        let mut nn: NeuralNetwork = trained_network();
+        nn.parameters.weights[1] = Vector2D::zeros([4, 1]);
+        match NeuralNetwork::from_bytes(&nn.to_bytes().unwrap()) {
+            Err(ModelError::ShapeMismatch { parameter, expected, found }) => {
+                assert!(parameter == "weights[1]");
+                assert!(expected == [3, 1]);
+                assert!(found == [4, 1]);
+            },
+            _ => panic!("expected a shape mismatch"),
+        }
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let nn: NeuralNetwork = trained_network();
+        let mut bytes: Vec<u8> = nn.to_bytes().unwrap();
+        bytes[4] = 99;
+        assert!(matches!(NeuralNetwork::from_bytes(&bytes), Err(ModelError::UnsupportedVersion(99))));
+        assert!(matches!(NeuralNetwork::from_bytes(&bytes[..20]), Err(ModelError::UnsupportedVersion(99))));
+        assert!(matches!(NeuralNetwork::from_bytes(b"nope"), Err(ModelError::Format(_))));
+    }
+
+    #[test]
+    fn test_network_with_added_layer_is_not_saved() {
+        let mut nn: NeuralNetwork = trained_network();
+        nn.add_layer(0, Box::new(Dropout::with_seed(0.5, 1)));
+        assert!(matches!(nn.to_json(), Err(ModelError::UnsupportedLayer(ref name)) if name == "dropout"));
+        assert!(matches!(nn.to_bytes(), Err(ModelError::UnsupportedLayer(_))));
+
+        let mut nn: NeuralNetwork = trained_network();
+        nn.add_input_layer(Box::new(Flatten::new([1, 1, 2])));
+        let path = std::env::temp_dir().join("rust_network_test_layer_model.json");
+        let error: ModelError = nn.save(&path).err().unwrap();
+        assert!(error.to_string() == "Cannot save the added flatten layer, only dense layers are stored");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_null_values_are_rejected() {
+        let text: String = trained_network().to_json().unwrap().to_string_pretty();
+        let json: Json = Json::parse(&text.replacen("\"learning_rate\": ", "\"learning_rate\": null, \"x\": ", 1)).unwrap();
+        assert!(NeuralNetwork::from_json(&json).err().unwrap().to_string() == "Invalid model file: missing learning rate");
+
+        let text: String = trained_network().to_json().unwrap().to_string_pretty();
+        let json: Json = Json::parse(&text.replacen("\"values\": [", "\"values\": [null, ", 1)).unwrap();
+        assert!(NeuralNetwork::from_json(&json).err().unwrap().to_string() == "Invalid model file: weights[0] has no valid values");
+    }
+
+    #[test]
+    fn test_overflowing_shape_is_rejected() {
+        let json: Json = Json::parse(&format!("{{\"shape\": [{}, 3], \"values\": []}}", usize::MAX / 2)).unwrap();
+        assert!(matches!(vector_from_json(&json, "weights[0]"), Err(ModelError::Format(message)) if message.contains("impossible shape")));
+    }
+
+    #[test]
+    fn test_non_finite_values_are_not_saved_as_json() {
+        let mut nn: NeuralNetwork = trained_network();
+        nn.parameters.weights[0].values[1] = f64::NAN;
+        let error: ModelError = nn.to_json().err().unwrap();
+        assert!(error.to_string() == "Invalid model file: weights[0] has the non-finite value NaN at index 1");
+        let path = std::env::temp_dir().join("rust_network_test_non_finite_model.json");
+        assert!(nn.save(&path).is_err() && !path.exists());
+        // the binary format stores every f64
+        assert!(NeuralNetwork::from_bytes(&nn.to_bytes().unwrap()).unwrap().parameters.weights[0].values[1].is_nan());
+    }
+}
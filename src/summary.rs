@@ -0,0 +1,158 @@
+// This file contains the model summary: one row per layer with its input and output shape,
+// activation and number of trainable parameters, and an estimate of the memory the network
+// needs. All values are f64, so every stored number takes 8 bytes.
+use std::fmt;
+use crate::activation::Activation;
+
+const BYTES_PER_VALUE: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerSummary {
+    pub name: String,
+    // values per sample, None if the layer accepts inputs of any length and the size could
+    // not be inferred
+    pub input: Option<usize>,
+    pub output: Option<usize>,
+    // only dense layers have an activation
+    pub activation: Option<Activation>,
+    pub parameters: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub layers: Vec<LayerSummary>,
+    // buffers the optimizer keeps per parameter, e.g. 2 for Adam
+    pub optimizer_slots: usize,
+}
+
+impl Summary {
+    pub fn parameters(&self) -> usize {
+        self.layers.iter().map(|layer| layer.parameters).sum()
+    }
+
+    pub fn parameter_bytes(&self) -> usize {
+        self.parameters() * BYTES_PER_VALUE
+    }
+
+    // Parameters, their gradients and the optimizer buffers.
+    pub fn training_bytes(&self) -> usize {
+        self.parameter_bytes() * (2 + self.optimizer_slots)
+    }
+
+    // The outputs every layer keeps for backward; dense layers keep their pre-activation too.
+    // Layers of unknown size are left out.
+    pub fn activation_bytes_per_sample(&self) -> usize {
+        self.layers.iter()
+            .map(|layer| {
+                let copies: usize = if layer.activation.is_some() { 2 } else { 1 };
+                copies * layer.output.unwrap_or(0)
+            })
+            .sum::<usize>() * BYTES_PER_VALUE
+    }
+}
+
+fn shape(size: Option<usize>) -> String {
+    match size {
+        Some(size) => format!("(None, {})", size),
+        None => "(None, ?)".to_string(),
+    }
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    let units: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value: f64 = bytes as f64 / 1024.;
+    let mut unit: usize = 0;
+    while value >= 1024. && unit < units.len() - 1 {
+        value /= 1024.;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header: [String; 5] = ["Layer", "Input", "Output", "Activation", "Parameters"].map(String::from);
+        let rows: Vec<[String; 5]> = self.layers.iter().map(|layer| [
+            layer.name.clone(),
+            shape(layer.input),
+            shape(layer.output),
+            layer.activation.map_or("-".to_string(), |activation| activation.name().to_string()),
+            layer.parameters.to_string(),
+        ]).collect();
+
+        let mut widths: [usize; 5] = header.clone().map(|column| column.len());
+        for row in &rows {
+            for (width, column) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(column.len());
+            }
+        }
+        let line: String = "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1));
+        let write_row = |f: &mut fmt::Formatter, row: &[String; 5]| -> fmt::Result {
+            writeln!(
+                f, "{:<w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {:>w4$}",
+                row[0], row[1], row[2], row[3], row[4],
+                w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3], w4 = widths[4]
+            )
+        };
+
+        write_row(f, &header)?;
+        writeln!(f, "{}", line)?;
+        for row in &rows {
+            write_row(f, row)?;
+        }
+        writeln!(f, "{}", line)?;
+        writeln!(f, "Trainable parameters: {}", self.parameters())?;
+        writeln!(f, "Parameter memory: {}", format_bytes(self.parameter_bytes()))?;
+        writeln!(f, "Training memory: {} (parameters, gradients and {} optimizer buffers)", format_bytes(self.training_bytes()), self.optimizer_slots)?;
+        write!(f, "Activation memory: {} per sample", format_bytes(self.activation_bytes_per_sample()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> Summary {
+        Summary {
+            layers: vec![
+                LayerSummary { name: "dense 0".to_string(), input: Some(2), output: Some(3), activation: Some(Activation::Tanh), parameters: 9 },
+                LayerSummary { name: "dropout".to_string(), input: Some(3), output: Some(3), activation: None, parameters: 0 },
+                LayerSummary { name: "dense 1".to_string(), input: Some(3), output: Some(1), activation: Some(Activation::Sigmoid), parameters: 4 },
+            ],
+            optimizer_slots: 2,
+        }
+    }
+
+    #[test]
+    fn test_memory_estimates() {
+        let summary: Summary = summary();
+        assert!(summary.parameters() == 13);
+        assert!(summary.parameter_bytes() == 104);
+        assert!(summary.training_bytes() == 416);
+        // z and a of both dense layers and the dropout output
+        assert!(summary.activation_bytes_per_sample() == (6 + 3 + 2) * 8);
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert!(format_bytes(1023) == "1023 B");
+        assert!(format_bytes(1536) == "1.5 KiB");
+        assert!(format_bytes(3 * 1024 * 1024) == "3.0 MiB");
+    }
+
+    #[test]
+    fn test_table() {
+        let table: String = summary().to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0] == "Layer    Input      Output     Activation  Parameters");
+        assert!(lines[2] == "dense 0  (None, 2)  (None, 3)  tanh                 9");
+        assert!(lines[3] == "dropout  (None, 3)  (None, 3)  -                    0");
+        assert!(lines[1].len() == lines[0].len());
+        assert!(lines[6] == "Trainable parameters: 13");
+        assert!(lines[8] == "Training memory: 416 B (parameters, gradients and 2 optimizer buffers)");
+    }
+}
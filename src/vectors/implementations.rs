@@ -676,7 +676,7 @@ mod tests {
         let shape: [usize; 2] = [2, 2];
         let v2d: Vector2D = Vector2D::new(values, shape);
         let result: Vector2D = v2d * v;
-        assert!(&result.values == &expected_result);
+        assert!(result.values == expected_result);
         let v2d: Vector2D = (1./v) * result;
         assert!(&v2d.values == values_copy);
     }
@@ -687,10 +687,10 @@ mod tests {
         let values: Vec<f64> = vec![0., 1.5, 3., 4.5];
         let shape: [usize; 2] = [2, 2];
         let v2d: Vector2D = Vector2D::new(values, shape);
-        assert!(vec_compare(&(v2d - f).values, &vec![-3., -1.5, 0., 1.5]));
+        assert!(vec_compare(&(v2d - f).values, &[-3., -1.5, 0., 1.5]));
 
         let values: Vec<f64> = vec![0., 1.5, 3., 4.5];
         let v2d: Vector2D = Vector2D::new(values, shape);
-        assert!(vec_compare(&(f - v2d).values, &vec![3., 1.5, 0., -1.5]));
+        assert!(vec_compare(&(f - v2d).values, &[3., 1.5, 0., -1.5]));
     }
 }
\ No newline at end of file
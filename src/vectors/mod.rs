@@ -1,3 +1,4 @@
 pub mod models;
 pub mod implementations;
-pub mod tests;
+#[cfg(test)]
+mod tests;
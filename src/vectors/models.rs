@@ -1,6 +1,6 @@
 // This file contains all implementation of custom vector models (structs)
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Vector2D {
     pub values: Vec<f64>,
     pub shape: [usize; 2]
@@ -14,19 +14,35 @@ impl Vector2D {
         Vector2D { values, shape }
     }
 
+    pub fn zeros(shape: [usize; 2]) -> Vector2D {
+        Vector2D::new(vec![0.; shape[0] * shape[1]], shape)
+    }
+
+    pub fn map<F: Fn(f64) -> f64>(&self, f: F) -> Vector2D {
+        let mut new_values: Vec<f64> = vec![];
+        for value in &self.values {
+            new_values.push(f(*value));
+        }
+        Vector2D::new(new_values, self.shape)
+    }
+
     pub fn print(&self) {
         for row in 0..self.shape[0] {
             for column in 0..self.shape[1] {
                 print!("{}, ", self.get_mat_value(row, column));
             }
-            print!("\n");
+            println!();
         }
     }
 
-    pub fn len(self) -> usize {
+    pub fn len(&self) -> usize {
         self.values.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
     pub fn transpose(&self) -> Vector2D {
         let new_shape: [usize; 2] = [self.shape[1], self.shape[0]];
         let mut new_values: Vec<f64> = vec![];
@@ -39,12 +55,12 @@ impl Vector2D {
         Vector2D::new(new_values, new_shape)    
     }
 
-    pub fn row_add(&mut self, b_vector: &Vector2D) -> Vector2D {
+    pub fn row_add(&self, b_vector: &Vector2D) -> Vector2D {
         if self.shape[1] != b_vector.shape[1] {
             panic!("Can not row-wise add vector with shape {:?} to vector with shape {:?}", b_vector.shape, self.shape);
         } else {
             let mut result: Vec<f64> = vec![];
-            let result_shape: [usize; 2] = self.shape.clone();
+            let result_shape: [usize; 2] = self.shape;
             
             for row in 0..result_shape[0] {
                 result.append(&mut (self.get_mat_row_values(row) + b_vector.clone()).values);
@@ -53,7 +69,31 @@ impl Vector2D {
         }
     }
 
-    pub fn dot(&mut self, b_vector: &Vector2D) -> Vector2D {
+    pub fn row_mul(&self, b_vector: &Vector2D) -> Vector2D {
+        if b_vector.shape != [1, self.shape[1]] {
+            panic!("Can not row-wise multiply vector with shape {:?} with vector with shape {:?}", self.shape, b_vector.shape);
+        }
+        let values: Vec<f64> = self.values.iter().enumerate().map(|(idx, v)| v * b_vector.values[idx % self.shape[1]]).collect();
+        Vector2D::new(values, self.shape)
+    }
+
+    pub fn column_add(&self, b_vector: &Vector2D) -> Vector2D {
+        if b_vector.shape != [self.shape[0], 1] {
+            panic!("Can not column-wise add vector with shape {:?} to vector with shape {:?}", b_vector.shape, self.shape);
+        }
+        let values: Vec<f64> = self.values.iter().enumerate().map(|(idx, v)| v + b_vector.values[idx / self.shape[1]]).collect();
+        Vector2D::new(values, self.shape)
+    }
+
+    pub fn column_mul(&self, b_vector: &Vector2D) -> Vector2D {
+        if b_vector.shape != [self.shape[0], 1] {
+            panic!("Can not column-wise multiply vector with shape {:?} with vector with shape {:?}", self.shape, b_vector.shape);
+        }
+        let values: Vec<f64> = self.values.iter().enumerate().map(|(idx, v)| v * b_vector.values[idx / self.shape[1]]).collect();
+        Vector2D::new(values, self.shape)
+    }
+
+    pub fn dot(&self, b_vector: &Vector2D) -> Vector2D {
         if self.shape[1] != b_vector.shape[0] {
             panic!("Can not dot multiply vectors with shape {:?} @ {:?}", self.shape, b_vector.shape);
         } else {
@@ -86,8 +126,8 @@ impl Vector2D {
         Vector2D::new(log_values, self.shape)
     }
 
-    pub fn mean(&mut self, axis: usize) -> Vector2D {
-        let mut new_shape: [usize; 2] = self.shape.clone();
+    pub fn mean(&self, axis: usize) -> Vector2D {
+        let mut new_shape: [usize; 2] = self.shape;
         let mut new_values: Vec<f64> = vec![];
 
         if axis == 0 {
@@ -114,10 +154,10 @@ impl Vector2D {
         Vector2D::new(new_values, new_shape)
     }
 
-    pub fn overall_mean(&mut self) -> f64 {
-        let mut mean_0: Vector2D = self.mean(0);
+    pub fn overall_mean(&self) -> f64 {
+        let mean_0: Vector2D = self.mean(0);
         let mean_01: Vector2D = mean_0.mean(1);
-        return *mean_01.get_value(0);        
+        *mean_01.get_value(0)
     }
 
     pub fn get_value(&self, i: usize) -> &f64 {
@@ -162,4 +202,39 @@ impl Vector2D {
             )
         }
     }
-}
\ No newline at end of file
+
+    pub fn select_rows(&self, rows: &[usize]) -> Vector2D {
+        let mut new_values: Vec<f64> = vec![];
+        for row in rows {
+            new_values.append(&mut self.get_mat_row_values(*row).values);
+        }
+        Vector2D::new(new_values, [rows.len(), self.shape[1]])
+    }
+
+    // The columns start..end of every row.
+    pub fn select_columns(&self, start: usize, end: usize) -> Vector2D {
+        if start > end || end > self.shape[1] {
+            panic!("Can not select columns {}..{} of vector with shape {:?}", start, end, self.shape);
+        }
+        let mut new_values: Vec<f64> = vec![];
+        for row in 0..self.shape[0] {
+            new_values.extend_from_slice(&self.values[row * self.shape[1] + start..row * self.shape[1] + end]);
+        }
+        Vector2D::new(new_values, [self.shape[0], end - start])
+    }
+
+    // Places vectors with the same number of rows next to each other.
+    pub fn concat_columns(vectors: &[Vector2D]) -> Vector2D {
+        let rows: usize = vectors.first().map_or(0, |v| v.shape[0]);
+        if let Some(v) = vectors.iter().find(|v| v.shape[0] != rows) {
+            panic!("Can not concatenate vector with shape {:?} to vectors with {} rows", v.shape, rows);
+        }
+        let mut new_values: Vec<f64> = vec![];
+        for row in 0..rows {
+            for v in vectors {
+                new_values.extend_from_slice(&v.values[row * v.shape[1]..(row + 1) * v.shape[1]]);
+            }
+        }
+        Vector2D::new(new_values, [rows, vectors.iter().map(|v| v.shape[1]).sum()])
+    }
+}